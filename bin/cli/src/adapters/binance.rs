@@ -1,87 +1,720 @@
+use crate::adapters::effects::{Effects, relabel_all};
 use binance_client::BinanceClient;
+use binance_client::payloads::{
+    AssetBalance, ConvertRecord, DepositRecord, DustConversion, EarnRedemption, EarnReward,
+    EarnSubscription, FlexEarnPos, LockedEarnPos, StakingPositionResp, StakingRecord, Trade,
+    WithdrawalRecord,
+};
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use itertools::Itertools;
 use lib_core::traits::{IsProvider, Issuer3};
-use lib_core::{AssetId, Position, PositionId, Product, ProductId, ProviderId, Transaction};
+use lib_core::{
+    AccountId, Amount, AssetId, AssetRegistry, Position, PositionId, Product, ProductId,
+    ProviderId, SyncBatch, SyncCursor, Transaction,
+};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use utils::decimal;
 
-const PROVIDER_ID_BINANCE: &str = "binance";
+pub const PROVIDER_ID_BINANCE: &str = "binance";
 
 pub struct BinanceSvc {
+    /// Instance label, used as the provider of imported accounts
+    pub label: ProviderId,
     pub client: BinanceClient,
+    pub assets: Arc<AssetRegistry>,
+    /// Spot markets to import trades from, Binance can't list them for us
+    pub trade_pairs: Vec<TradePair>,
+    /// Oldest history to import
+    pub history_start: DateTime<Utc>,
 }
 impl BinanceSvc {
-    pub fn new() -> anyhow::Result<Self> {
-        Ok(BinanceSvc {
-            client: BinanceClient::new()?,
-        })
+    /// Imports from the Binance launch, without trade pairs
+    pub fn new(label: ProviderId, client: BinanceClient, assets: Arc<AssetRegistry>) -> Self {
+        BinanceSvc {
+            label,
+            client,
+            assets,
+            trade_pairs: Vec::new(),
+            history_start: Utc.with_ymd_and_hms(2017, 7, 1, 0, 0, 0).unwrap(), // Binance launch
+        }
     }
-    // pub fn new_asset(local_asset_id: &str, binance_asset_id: &str) -> AssetId {
-    //     AssetId::new(local_asset_id)
-    //         .with_ext_id(ExternalAssetId::new::<BinanceSvc>(binance_asset_id))
-    // }
 }
 
-fn get_decimals(asset: &str) -> u8 {
-    match asset {
-        "ETH" | "ethereum" => 18,
-        "BTC" | "bitcoin" => 8,
-        "USDT" => 6,
-        _ => 18, // Default
-    }
+/// Resolves a Binance symbol through the asset registry
+fn asset_id(assets: &AssetRegistry, binance_asset: &str) -> anyhow::Result<AssetId> {
+    let asset = assets.resolve(&ProviderId::from(PROVIDER_ID_BINANCE), binance_asset)?;
+    Ok(asset.id.clone())
 }
 
-fn to_u64(amount: f64, decimals: u8) -> u64 {
-    (amount * 10f64.powi(decimals as i32)).round() as u64
+/// Resolves a Binance symbol and converts a quantity of it to base units
+fn to_amount(assets: &AssetRegistry, amount: &str, binance_asset: &str) -> anyhow::Result<Amount> {
+    let asset = assets.resolve(&ProviderId::from(PROVIDER_ID_BINANCE), binance_asset)?;
+    Ok(asset.amount_from_str(amount)?)
 }
 
-impl BinanceSvc {
-    // TODO move this to Provider trait
+/// Keeps the records that could be mapped and reports the others,
+/// usually assets missing from the registry
+fn skip_errors<T>(results: impl IntoIterator<Item = anyhow::Result<T>>) -> Vec<T> {
+    results
+        .into_iter()
+        .filter_map(|result| {
+            result
+                .inspect_err(|err| eprintln!("skipped Binance record: {err}"))
+                .ok()
+        })
+        .collect()
+}
 
-    // pub fn fetch_assets(&self) -> anyhow::Result<Vec<AssetId>> {
-    //     Ok(vec![BinanceSvc::new_asset("ETH", "ethereum")])
-    // }
+impl BinanceSvc {
     pub async fn fetch_products(&self) -> anyhow::Result<Vec<Product>> {
-        let binance_products = self.client.list_staking_products().await?;
+        let (binance_products, locked, flexible, spot, funding) = tokio::try_join!(
+            self.client.list_staking_products(),
+            self.client.list_locked_earn_positions(),
+            self.client.list_flexible_earn_pos(),
+            self.client.list_spot_balances(),
+            self.client.list_funding_balances(),
+        )?;
+        let assets = &self.assets;
 
-        let products = binance_products
-            .into_iter()
-            .map(|sp| Product {
+        // Simple Earn products are only known through the positions held in them
+        let staking = binance_products.iter().map(|sp| {
+            Ok(Product {
                 id: ProductId::from(&sp.project_id),
-                asset_id: asset_id_from_binance(&sp.detail.asset),
+                asset_id: asset_id(assets, &sp.detail.asset)?,
                 apy: sp.detail.apy,
             })
+        });
+        let products = staking
+            .chain(locked.iter().map(|lp| product_from_locked(assets, lp)))
+            .chain(flexible.iter().map(|fp| product_from_flexible(assets, fp)))
+            .chain(
+                spot.iter()
+                    .map(|b| product_from_wallet(assets, b, Wallet::Spot)),
+            )
+            .chain(
+                funding
+                    .iter()
+                    .map(|b| product_from_wallet(assets, b, Wallet::Funding)),
+            );
+        let products = skip_errors(products)
+            .into_iter()
+            .unique_by(|product| product.id.clone())
             .collect();
         Ok(products)
     }
 
     pub async fn fetch_positions(&self) -> anyhow::Result<Vec<Position>> {
-        let binance_positions = self.client.list_staking_positions().await?;
-        // TODO also simple flex and simple lock positions
+        let (staking, locked, flexible, spot, funding) = tokio::try_join!(
+            self.client.list_staking_positions(),
+            self.client.list_locked_earn_positions(),
+            self.client.list_flexible_earn_pos(),
+            self.client.list_spot_balances(),
+            self.client.list_funding_balances(),
+        )?;
+        let assets = &self.assets;
+        let now = Utc::now();
 
-        let positions = binance_positions
-            .into_iter()
-            // TODO impl TryFrom<BinanceModel> for each model
-            .map(|sp| {
-                Ok(Position {
-                    id: PositionId::from(&(*sp.position_id).to_string()),
-                    product_id: ProductId::from(&sp.product_id),
-                    amount: to_u64(sp.amount, get_decimals(&sp.asset_id)),
-                    start_date: sp.purchase_time,
-                    end_date: sp.interest_end_date,
-                })
-            })
-            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut positions = skip_errors(
+            staking
+                .iter()
+                .map(|sp| position_from_staking(assets, sp))
+                .chain(locked.iter().map(|lp| position_from_locked(assets, lp)))
+                .chain(
+                    flexible
+                        .iter()
+                        .map(|fp| position_from_flexible(assets, fp, now)),
+                ),
+        );
+        let wallets = spot
+            .iter()
+            .map(|balance| positions_from_wallet(assets, balance, Wallet::Spot, now))
+            .chain(
+                funding
+                    .iter()
+                    .map(|balance| positions_from_wallet(assets, balance, Wallet::Funding, now)),
+            );
+        positions.extend(skip_errors(wallets).into_iter().flatten());
         Ok(positions)
     }
 }
+
+impl BinanceSvc {
+    pub async fn fetch_transactions(&self) -> anyhow::Result<Vec<Transaction>> {
+        let batch = self
+            .fetch_transactions_since(&SyncCursor::default())
+            .await?;
+        Ok(batch.transactions)
+    }
+
+    /// History endpoints resume [`SYNC_LOOKBACK`] before the newest record synced, so that
+    /// records still pending then, like a slow withdrawal, are fetched once settled. Records
+    /// fetched again are skipped as duplicates. Trades resume after the last trade id of
+    /// each market. Neither moves past a record that couldn't be mapped, so it is retried.
+    pub async fn fetch_transactions_since(&self, cursor: &SyncCursor) -> anyhow::Result<SyncBatch> {
+        let from = sync_start(cursor, self.history_start);
+        let (start, end) = (from.unwrap_or(self.history_start), Utc::now());
+        let client = &self.client;
+        let mut next_cursor = cursor.clone();
+        let (deposits, withdrawals, converts, dust) = tokio::try_join!(
+            client.list_deposits(start, end),
+            client.list_withdrawals(start, end),
+            client.list_converts(start, end),
+            client.list_dust_conversions(start, end),
+        )?;
+        let (flex_subscriptions, flex_redemptions, flex_rewards) = tokio::try_join!(
+            client.list_flexible_subscriptions(start, end),
+            client.list_flexible_redemptions(start, end),
+            client.list_flexible_rewards(start, end),
+        )?;
+        let (locked_subscriptions, locked_redemptions, locked_rewards, staking_rewards) = tokio::try_join!(
+            client.list_locked_subscriptions(start, end),
+            client.list_locked_redemptions(start, end),
+            client.list_locked_rewards(start, end),
+            client.list_staking_rewards(start, end),
+        )?;
+        let mut locked_products = LockedProducts::new(
+            &client.list_locked_earn_positions().await?,
+            &locked_subscriptions,
+        );
+        let unknown_position = locked_redemptions
+            .iter()
+            .filter_map(|r| r.position_id.as_ref())
+            .chain(locked_rewards.iter().filter_map(|r| r.position_id.as_ref()))
+            .any(|id| !locked_products.contains(id));
+        if from.is_some() && unknown_position {
+            // positions redeemed since, subscribed to before the sync window
+            let older = client
+                .list_locked_subscriptions(self.history_start, start)
+                .await?;
+            locked_products.extend(&older);
+        }
+
+        let assets = &self.assets;
+        // each mapped record with its date, to hold the cursor at the oldest one that failed
+        let mut results = Vec::new();
+        for pair in &self.trade_pairs {
+            let marker = format!("trades:{}", pair.symbol());
+            let from_id = match cursor.marker(&marker) {
+                Some(last_id) => last_id.parse::<u64>()? + 1,
+                None => 0,
+            };
+            let mut trades = client.list_trades(&pair.symbol(), from_id).await?;
+            trades.sort_by_key(|trade| trade.id);
+            let mapped = trades
+                .iter()
+                .map(|trade| (trade, tx_from_trade(assets, trade, pair)))
+                .collect::<Vec<_>>();
+            // trades resume after the last one before the first that couldn't be mapped
+            let last_mapped = mapped
+                .iter()
+                .take_while(|(_, tx)| tx.is_ok())
+                .map(|(trade, _)| trade.id)
+                .last();
+            if let Some(last) = last_mapped {
+                next_cursor.set_marker(marker, last);
+            }
+            results.extend(mapped.into_iter().map(|(trade, tx)| (trade.time, tx)));
+        }
+        for deposit in deposits.iter().filter(|d| d.is_credited()) {
+            results.push((deposit.insert_time, tx_from_deposit(assets, deposit)));
+        }
+        for withdrawal in withdrawals.iter().filter(|w| w.is_completed()) {
+            results.push((
+                withdrawal.apply_time,
+                tx_from_withdrawal(assets, withdrawal),
+            ));
+        }
+        for convert in converts.iter().filter(|c| c.is_success()) {
+            results.push((convert.create_time, tx_from_convert(assets, convert)));
+        }
+        for conversion in &dust {
+            results.push((conversion.operate_time, tx_from_dust(assets, conversion)));
+        }
+        for subscription in flex_subscriptions.iter().filter(|s| !is_failed(&s.status)) {
+            let tx = tx_from_subscription(assets, subscription, Earn::Flexible);
+            results.push((subscription.time, tx));
+        }
+        for subscription in locked_subscriptions
+            .iter()
+            .filter(|s| !is_failed(&s.status))
+        {
+            let tx = tx_from_subscription(assets, subscription, Earn::Locked);
+            results.push((subscription.time, tx));
+        }
+        for redemption in flex_redemptions.iter().filter(|r| !is_failed(&r.status)) {
+            let product_id = redemption.product_id.as_deref();
+            let tx = tx_from_redemption(assets, redemption, Earn::Flexible, product_id);
+            results.push((redemption.time, tx));
+        }
+        for redemption in locked_redemptions.iter().filter(|r| !is_failed(&r.status)) {
+            let product_id = locked_products.get(redemption.position_id.as_ref());
+            let tx = tx_from_redemption(assets, redemption, Earn::Locked, product_id);
+            results.push((redemption.time, tx));
+        }
+        // flexible rewards are added to the position, locked ones are paid to spot
+        for reward in &flex_rewards {
+            let product_id = reward.product_id.as_deref();
+            let tx = tx_from_reward(assets, reward, Earn::Flexible, product_id);
+            results.push((reward.time, tx));
+        }
+        for reward in &locked_rewards {
+            let product_id = locked_products.get(reward.position_id.as_ref());
+            let tx = tx_from_reward(assets, reward, Earn::Locked, product_id);
+            results.push((reward.time, tx));
+        }
+        for reward in staking_rewards.iter().filter(|r| !is_failed(&r.status)) {
+            results.push((reward.time, tx_from_staking_reward(assets, reward)));
+        }
+
+        let oldest_skipped = results
+            .iter()
+            .filter(|(_, tx)| tx.is_err())
+            .map(|(datetime, _)| *datetime)
+            .min();
+        let mut transactions = skip_errors(results.into_iter().map(|(_, tx)| tx));
+        transactions.sort_by_key(|tx| tx.datetime);
+        Ok(SyncBatch {
+            from,
+            cursor: next_cursor.advance(&transactions).hold_at(oldest_skipped),
+            transactions,
+        })
+    }
+}
+
+/// How far before the cursor history endpoints are queried again. Binance settles
+/// deposits and withdrawals within hours, a week leaves room for stuck ones.
+pub const SYNC_LOOKBACK: TimeDelta = TimeDelta::days(7);
+
+/// Start of an incremental sync, None for a full one
+fn sync_start(cursor: &SyncCursor, history_start: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let last = cursor.last_datetime?;
+    Some((last - SYNC_LOOKBACK).max(history_start))
+}
+
+/// A spot market, e.g. ETH/USDT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TradePair {
+    pub base: String,
+    pub quote: String,
+}
+impl TradePair {
+    pub fn symbol(&self) -> String {
+        format!("{}{}", self.base, self.quote)
+    }
+}
+impl FromStr for TradePair {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (base, quote) = s
+            .trim()
+            .split_once('/')
+            .ok_or_else(|| anyhow::anyhow!("invalid trade pair {s:?}, expected BASE/QUOTE"))?;
+        Ok(TradePair {
+            base: base.to_uppercase(),
+            quote: quote.to_uppercase(),
+        })
+    }
+}
+
+fn is_failed(status: &str) -> bool {
+    status.eq_ignore_ascii_case("FAILED")
+}
+
+/// Binance reports moves between its own users as "Internal transfer <id>", not on chain
+fn chain_tx_hash(tx_id: &str) -> Option<&str> {
+    Some(tx_id).filter(|id| !id.starts_with("Internal transfer"))
+}
+
+fn tx_from_deposit(assets: &AssetRegistry, deposit: &DepositRecord) -> anyhow::Result<Transaction> {
+    let mut effects = effects_at(deposit.insert_time)
+        .with_native_id(format!("deposit:{}", deposit.id))
+        .with_tx_hash(chain_tx_hash(&deposit.tx_id));
+    let amount = to_amount(assets, &deposit.amount, &deposit.coin)?;
+    let asset = amount.asset_id.clone();
+    effects.transfer(external(&asset), spot(&asset), amount)?;
+    Ok(effects.into_transaction())
+}
+
+fn tx_from_withdrawal(
+    assets: &AssetRegistry,
+    withdrawal: &WithdrawalRecord,
+) -> anyhow::Result<Transaction> {
+    let mut effects = effects_at(withdrawal.apply_time)
+        .with_native_id(format!("withdrawal:{}", withdrawal.id))
+        .with_tx_hash(chain_tx_hash(&withdrawal.tx_id));
+    let amount = to_amount(assets, &withdrawal.amount, &withdrawal.coin)?;
+    let fee = to_amount(assets, &withdrawal.transaction_fee, &withdrawal.coin)?;
+    let asset = amount.asset_id.clone();
+    effects.transfer(spot(&asset), external(&asset), amount)?;
+    effects.fee(spot(&asset), fee)?;
+    Ok(effects.into_transaction())
+}
+
+fn tx_from_trade(
+    assets: &AssetRegistry,
+    trade: &Trade,
+    pair: &TradePair,
+) -> anyhow::Result<Transaction> {
+    let mut effects =
+        effects_at(trade.time).with_native_id(format!("trade:{}:{}", trade.symbol, trade.id));
+    let base = to_amount(assets, &trade.qty, &pair.base)?;
+    let quote = to_amount(assets, &trade.quote_qty, &pair.quote)?;
+    let (base_asset, quote_asset) = (base.asset_id.clone(), quote.asset_id.clone());
+    if trade.is_buyer {
+        effects.swap(spot(&quote_asset), quote, spot(&base_asset), base)?;
+    } else {
+        effects.swap(spot(&base_asset), base, spot(&quote_asset), quote)?;
+    }
+    let commission = to_amount(assets, &trade.commission, &trade.commission_asset)?;
+    let commission_account = spot(&commission.asset_id);
+    effects.fee(commission_account, commission)?;
+    Ok(effects.into_transaction())
+}
+
+fn tx_from_convert(assets: &AssetRegistry, convert: &ConvertRecord) -> anyhow::Result<Transaction> {
+    let mut effects =
+        effects_at(convert.create_time).with_native_id(format!("convert:{}", convert.order_id));
+    let sold = to_amount(assets, &convert.from_amount, &convert.from_asset)?;
+    let bought = to_amount(assets, &convert.to_amount, &convert.to_asset)?;
+    let (from, to) = (spot(&sold.asset_id), spot(&bought.asset_id));
+    effects.swap(from, sold, to, bought)?;
+    Ok(effects.into_transaction())
+}
+
+const DUST_TARGET_ASSET: &str = "BNB";
+
+/// Each small balance is sold for BNB, then Binance keeps a service charge
+fn tx_from_dust(
+    assets: &AssetRegistry,
+    conversion: &DustConversion,
+) -> anyhow::Result<Transaction> {
+    let mut effects =
+        effects_at(conversion.operate_time).with_native_id(format!("dust:{}", conversion.trans_id));
+    for detail in &conversion.details {
+        let sold = to_amount(assets, &detail.amount, &detail.from_asset)?;
+        let received = to_amount(assets, &detail.transfered_amount, DUST_TARGET_ASSET)?;
+        let charge = to_amount(assets, &detail.service_charge_amount, DUST_TARGET_ASSET)?;
+        let bought = received.checked_add(&charge)?;
+        let bnb = spot(&bought.asset_id);
+        effects.swap(spot(&sold.asset_id), sold, bnb.clone(), bought)?;
+        effects.fee(bnb, charge)?;
+    }
+    Ok(effects.into_transaction())
+}
+
+fn tx_from_subscription(
+    assets: &AssetRegistry,
+    subscription: &EarnSubscription,
+    earn_kind: Earn,
+) -> anyhow::Result<Transaction> {
+    let amount = to_amount(assets, &subscription.amount, &subscription.asset)?;
+    let native_id = match &subscription.purchase_id {
+        Some(purchase_id) => format!("subscription:{earn_kind}:{purchase_id}"),
+        None => {
+            let product_id = &subscription.product_id;
+            let earner = subscription.position_id.as_ref().unwrap_or(product_id);
+            let kind = format!("subscription:{earn_kind}");
+            unnamed_record_id(&kind, earner, subscription.time, &amount)
+        }
+    };
+    let mut effects = effects_at(subscription.time).with_native_id(native_id);
+    let asset = amount.asset_id.clone();
+    effects.transfer(spot(&asset), earn(&asset, &subscription.product_id), amount)?;
+    Ok(effects.into_transaction())
+}
+
+/// Redemption out of `product_id`, which locked redemptions only know through their position
+fn tx_from_redemption(
+    assets: &AssetRegistry,
+    redemption: &EarnRedemption,
+    earn_kind: Earn,
+    product_id: Option<&str>,
+) -> anyhow::Result<Transaction> {
+    let Some(product_id) = product_id else {
+        anyhow::bail!(
+            "{earn_kind} redemption of {} without a known product",
+            redemption.asset
+        );
+    };
+    let amount = to_amount(assets, &redemption.amount, &redemption.asset)?;
+    let native_id = match &redemption.redeem_id {
+        Some(redeem_id) => format!("redemption:{earn_kind}:{redeem_id}"),
+        None => {
+            let earner = redemption.position_id.as_deref().unwrap_or(product_id);
+            let kind = format!("redemption:{earn_kind}");
+            unnamed_record_id(&kind, earner, redemption.time, &amount)
+        }
+    };
+    let mut effects = effects_at(redemption.time).with_native_id(native_id);
+    let asset = amount.asset_id.clone();
+    effects.transfer(earn(&asset, product_id), spot(&asset), amount)?;
+    Ok(effects.into_transaction())
+}
+
+/// Reward earned by `product_id`. Flexible rewards are added to the position, locked ones
+/// are paid to spot. Rewards have no id of their own, but a position or product is paid
+/// each reward type at most once at a given time, while two positions may well earn the
+/// same amount at once.
+fn tx_from_reward(
+    assets: &AssetRegistry,
+    reward: &EarnReward,
+    earn_kind: Earn,
+    product_id: Option<&str>,
+) -> anyhow::Result<Transaction> {
+    let Some(product_id) = product_id else {
+        anyhow::bail!(
+            "{earn_kind} reward in {} without a known product",
+            reward.asset
+        );
+    };
+    let earner = reward.position_id.as_deref().unwrap_or(product_id);
+    let time = reward.time.timestamp_millis();
+    let native_id = match &reward.reward_type {
+        Some(reward_type) => format!("reward:{earn_kind}:{earner}:{time}:{reward_type}"),
+        None => format!("reward:{earn_kind}:{earner}:{time}"),
+    };
+    let mut effects = effects_at(reward.time).with_native_id(native_id);
+    let amount = to_amount(assets, &reward.amount, &reward.asset)?;
+    let asset = amount.asset_id.clone();
+    let to = match earn_kind {
+        Earn::Flexible => earn(&asset, product_id),
+        Earn::Locked => spot(&asset),
+    };
+    effects.income(earn(&asset, product_id), to, amount)?;
+    Ok(effects.into_transaction())
+}
+
+fn tx_from_staking_reward(
+    assets: &AssetRegistry,
+    reward: &StakingRecord,
+) -> anyhow::Result<Transaction> {
+    let amount = to_amount(assets, &reward.amount, &reward.asset)?;
+    let earner = reward.position_id.as_ref().or(reward.product_id.as_ref());
+    let earner = earner.map_or("staking", String::as_str);
+    let native_id = unnamed_record_id("staking-reward", earner, reward.time, &amount);
+    let mut effects = effects_at(reward.time).with_native_id(native_id);
+    let asset = amount.asset_id.clone();
+    effects.transfer(income(&asset), spot(&asset), amount)?;
+    Ok(effects.into_transaction())
+}
+
+/// Native id of a record Binance gives no id to. Content hashes would merge two equal
+/// records of different positions at the same time, so the position or product is part of it.
+fn unnamed_record_id(kind: &str, earner: &str, time: DateTime<Utc>, amount: &Amount) -> String {
+    let time = time.timestamp_millis();
+    format!(
+        "{kind}:{earner}:{time}:{}:{}",
+        amount.asset_id.0, amount.units
+    )
+}
+
+/// Effects of a record, identified by its content unless given a native id
+fn effects_at(datetime: DateTime<Utc>) -> Effects {
+    Effects::new(ProviderId::from(PROVIDER_ID_BINANCE), datetime)
+}
+
+/// Simple Earn flavour, records of each come from their own endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, derive_more::Display)]
+enum Earn {
+    #[display(fmt = "flexible")]
+    Flexible,
+    #[display(fmt = "locked")]
+    Locked,
+}
+
+/// Locked product of each position, locked redemptions and rewards only name the position
+#[derive(Debug, Default)]
+struct LockedProducts(HashMap<String, String>);
+impl LockedProducts {
+    fn new(positions: &[LockedEarnPos], subscriptions: &[EarnSubscription]) -> Self {
+        let mut products = LockedProducts::default();
+        for position in positions {
+            let position_id = position.position_id.to_string();
+            products.0.insert(position_id, position.project_id.clone());
+        }
+        products.extend(subscriptions);
+        products
+    }
+
+    fn extend(&mut self, subscriptions: &[EarnSubscription]) {
+        for subscription in subscriptions {
+            if let Some(position_id) = &subscription.position_id {
+                let product_id = subscription.product_id.clone();
+                self.0.insert(position_id.clone(), product_id);
+            }
+        }
+    }
+
+    fn contains(&self, position_id: &str) -> bool {
+        self.0.contains_key(position_id)
+    }
+
+    fn get(&self, position_id: Option<&String>) -> Option<&str> {
+        self.0.get(position_id?).map(String::as_str)
+    }
+}
+
+/// The spot wallet holding the given asset
+fn spot(asset: &AssetId) -> AccountId {
+    AccountId::new(ProviderId::from(PROVIDER_ID_BINANCE), asset.clone())
+}
+
+/// Funds subscribed to a Simple Earn product, keyed like the products and positions
+/// Binance lists
+fn earn(asset: &AssetId, product_id: &str) -> AccountId {
+    AccountId::product(
+        ProviderId::from(PROVIDER_ID_BINANCE),
+        asset.clone(),
+        ProductId::from(product_id),
+    )
+}
+
+fn external(asset: &AssetId) -> AccountId {
+    AccountId::external(ProviderId::from(PROVIDER_ID_BINANCE), asset.clone())
+}
+
+fn income(asset: &AssetId) -> AccountId {
+    AccountId::income(ProviderId::from(PROVIDER_ID_BINANCE), asset.clone())
+}
+
+fn position_from_staking(
+    assets: &AssetRegistry,
+    sp: &StakingPositionResp,
+) -> anyhow::Result<Position> {
+    Ok(Position {
+        id: PositionId::from((*sp.position_id).to_string()),
+        product_id: ProductId::from(&sp.product_id),
+        amount: to_amount(assets, &sp.amount, &sp.asset_id)?,
+        start_date: sp.purchase_time,
+        end_date: Some(sp.interest_end_date),
+        auto_renew: sp.renewable,
+    })
+}
+
+fn position_from_locked(assets: &AssetRegistry, lp: &LockedEarnPos) -> anyhow::Result<Position> {
+    Ok(Position {
+        id: PositionId::from(lp.position_id.to_string()),
+        product_id: ProductId::from(&lp.project_id),
+        amount: to_amount(assets, &lp.amount, &lp.asset_id)?,
+        start_date: lp.purchase_time,
+        end_date: Some(lp.redeem_date),
+        auto_renew: lp.is_auto_renew,
+    })
+}
+
+/// Flexible positions have no id nor subscription date: there is a single one per product,
+/// and it is dated at the time it was fetched.
+fn position_from_flexible(
+    assets: &AssetRegistry,
+    fp: &FlexEarnPos,
+    fetched_at: DateTime<Utc>,
+) -> anyhow::Result<Position> {
+    Ok(Position {
+        id: PositionId::from(format!("flexible-{}", fp.product_id)),
+        product_id: ProductId::from(&fp.product_id),
+        amount: to_amount(assets, &fp.total_amount, &fp.asset_id)?,
+        start_date: fetched_at,
+        end_date: None,
+        auto_renew: fp.auto_subscribe,
+    })
+}
+
+/// Wallets holding idle assets
+#[derive(Debug, Clone, Copy)]
+enum Wallet {
+    Spot,
+    Funding,
+}
+impl Wallet {
+    fn name(self) -> &'static str {
+        match self {
+            Wallet::Spot => "spot",
+            Wallet::Funding => "funding",
+        }
+    }
+
+    /// Products are per asset, like earn products
+    fn product_id(self, binance_asset: &str) -> ProductId {
+        ProductId::from(format!("binance-{}-{binance_asset}", self.name()))
+    }
+}
+
+/// Free and locked (in open orders) parts of a wallet balance, when non-zero
+fn positions_from_wallet(
+    assets: &AssetRegistry,
+    balance: &AssetBalance,
+    wallet: Wallet,
+    fetched_at: DateTime<Utc>,
+) -> anyhow::Result<Vec<Position>> {
+    let asset = &balance.asset;
+    let parts = [("free", &balance.free), ("locked", &balance.locked)];
+    let mut positions = Vec::new();
+    // zero balances are skipped before resolving, most assets listed aren't in the registry
+    for (part, amount) in parts
+        .into_iter()
+        .filter(|(_, amount)| !decimal::is_zero(amount))
+    {
+        positions.push(Position {
+            id: PositionId::from(format!("{}-{asset}-{part}", wallet.name())),
+            product_id: wallet.product_id(asset),
+            amount: to_amount(assets, amount, asset)?,
+            start_date: fetched_at,
+            end_date: None,
+            auto_renew: false,
+        });
+    }
+    Ok(positions)
+}
+
+fn product_from_wallet(
+    assets: &AssetRegistry,
+    balance: &AssetBalance,
+    wallet: Wallet,
+) -> anyhow::Result<Product> {
+    Ok(Product {
+        id: wallet.product_id(&balance.asset),
+        asset_id: asset_id(assets, &balance.asset)?,
+        apy: 0.0,
+    })
+}
+
+fn product_from_locked(assets: &AssetRegistry, lp: &LockedEarnPos) -> anyhow::Result<Product> {
+    Ok(Product {
+        id: ProductId::from(&lp.project_id),
+        asset_id: asset_id(assets, &lp.asset_id)?,
+        apy: lp.apy,
+    })
+}
+
+fn product_from_flexible(assets: &AssetRegistry, fp: &FlexEarnPos) -> anyhow::Result<Product> {
+    Ok(Product {
+        id: ProductId::from(&fp.product_id),
+        asset_id: asset_id(assets, &fp.asset_id)?,
+        apy: fp.latest_annual_percentage_rate,
+    })
+}
+
 #[async_trait::async_trait]
 impl IsProvider for BinanceSvc {
     fn provider_id(&self) -> ProviderId {
-        ProviderId::from(PROVIDER_ID_BINANCE)
+        self.label.clone()
     }
     async fn fetch_positions(&self) -> anyhow::Result<Vec<Position>> {
         self.fetch_positions().await
     }
+    async fn fetch_products(&self) -> anyhow::Result<Vec<Product>> {
+        self.fetch_products().await
+    }
     async fn fetch_transactions(&self) -> anyhow::Result<Vec<Transaction>> {
-        todo!()
+        let transactions = self.fetch_transactions().await?;
+        Ok(relabel_all(transactions, &self.label))
+    }
+    async fn fetch_transactions_since(&self, cursor: &SyncCursor) -> anyhow::Result<SyncBatch> {
+        let mut batch = self.fetch_transactions_since(cursor).await?;
+        batch.transactions = relabel_all(batch.transactions, &self.label);
+        Ok(batch)
     }
 }
 impl Issuer3 for BinanceSvc {
@@ -90,11 +723,235 @@ impl Issuer3 for BinanceSvc {
     }
 }
 
-/// Convert a Binance asset identifier to an AssetId
-fn asset_id_from_binance(binance_asset: &str) -> AssetId {
-    match binance_asset {
-        "ethereum" => AssetId::Eth,
-        _ => AssetId::unknown(binance_asset),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::effects::summary;
+    use lib_core::{AccountKind, AllProducts, BalanceReplay, RealizedYields};
+
+    #[test]
+    fn test_simple_earn_positions() -> anyhow::Result<()> {
+        let assets = AssetRegistry::builtin();
+        let locked = serde_json::from_str::<LockedEarnPos>(
+            r#"{"positionId": "123123","projectId": "Axs*90","asset": "AXS","amount": "122.09202928","purchaseTime": "1646182276000","duration": "60","accrualDays": "4","rewardAsset": "AXS","APY": "0.23","isRenewable": true,"isAutoRenew": true,"redeemDate": "1732182276000"}"#,
+        )?;
+        let position = position_from_locked(&assets, &locked)?;
+        assert_eq!(position.id, PositionId::from("123123"));
+        assert_eq!(position.product_id, ProductId::from("Axs*90"));
+        assert_eq!(
+            position.end_date,
+            DateTime::from_timestamp_millis(1732182276000)
+        );
+        assert!(position.auto_renew);
+        assert_eq!(product_from_locked(&assets, &locked)?.apy, 0.23);
+
+        let flexible = serde_json::from_str::<FlexEarnPos>(
+            r#"{"totalAmount": "75.46000000","tierAnnualPercentageRate": {"0-5BTC": 0.05},"latestAnnualPercentageRate": "0.02599895","yesterdayAirdropPercentageRate": "0.02599895","asset": "USDT","airDropAsset": "BETH","canRedeem": true,"collateralAmount": "232.23123213","productId": "USDT001","yesterdayRealTimeRewards": "0.10293829","cumulativeBonusRewards": "0.22759183","cumulativeRealTimeRewards": "0.22759183","cumulativeTotalRewards": "0.45459183","autoSubscribe": false}"#,
+        )?;
+        let now = Utc::now();
+        let position = position_from_flexible(&assets, &flexible, now)?;
+        assert_eq!(position.end_date, None);
+        assert_eq!(position.start_date, now);
+        assert_eq!(position.amount.units, 75_460_000);
+        assert!(!position.auto_renew);
+        assert_eq!(product_from_flexible(&assets, &flexible)?.apy, 0.02599895);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wallet_balances() -> anyhow::Result<()> {
+        let balance = serde_json::from_str::<AssetBalance>(
+            r#"{"asset": "USDT","free": "12.5","locked": "0.00000000"}"#,
+        )?;
+        let positions = positions_from_wallet(
+            &AssetRegistry::builtin(),
+            &balance,
+            Wallet::Funding,
+            Utc::now(),
+        )?;
+        let [position] = &positions[..] else {
+            panic!("expected only the free balance");
+        };
+        assert_eq!(position.id, PositionId::from("funding-USDT-free"));
+        assert_eq!(position.product_id, ProductId::from("binance-funding-USDT"));
+        assert_eq!(position.amount.units, 12_500_000);
+        assert_eq!(position.end_date, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_withdrawal_fee_is_a_separate_effect() -> anyhow::Result<()> {
+        let withdrawal = serde_json::from_str::<WithdrawalRecord>(
+            r#"{"id": "b6ae22b3","amount": "8.91","transactionFee": "0.004","coin": "USDT","status": 6,"txId": "0xb5ef","applyTime": "2019-10-12 11:12:02","network": "ETH"}"#,
+        )?;
+        let tx = tx_from_withdrawal(&AssetRegistry::builtin(), &withdrawal)?;
+        tx.validate()?;
+        assert_eq!(tx.tx_hash.as_deref(), Some("0xb5ef"));
+        let usdt = || AssetId::from("USDT");
+        assert_eq!(
+            summary(&tx),
+            [
+                (AccountKind::Holding, usdt(), -8_910_000),
+                (AccountKind::Holding, usdt(), -4_000),
+                (AccountKind::External, usdt(), 8_910_000),
+                (AccountKind::Fee, usdt(), 4_000),
+            ]
+        );
+        Ok(())
+    }
+
+    const LOCKED_AXS_POSITION: &str = r#"{"positionId": "123123","projectId": "Axs*90","asset": "AXS","amount": "100","purchaseTime": "1646182276000","duration": "60","accrualDays": "4","rewardAsset": "AXS","APY": "0.23","isRenewable": true,"isAutoRenew": true,"redeemDate": "1732182276000"}"#;
+
+    fn locked_reward(position_id: &str, time: i64, amount: &str) -> anyhow::Result<EarnReward> {
+        Ok(serde_json::from_str(&format!(
+            r#"{{"positionId": "{position_id}","time": {time},"asset": "AXS","lockPeriod": "30","amount": "{amount}"}}"#
+        ))?)
+    }
+
+    #[test]
+    fn test_locked_rewards_of_distinct_positions_are_distinct() -> anyhow::Result<()> {
+        let assets = AssetRegistry::builtin();
+        let time = 1646182276000;
+        let reward = |position_id| {
+            let reward = locked_reward(position_id, time, "1.20")?;
+            tx_from_reward(&assets, &reward, Earn::Locked, Some("Axs*90"))
+        };
+        let first = reward("123123")?;
+        first.validate()?;
+        assert_ne!(first.id, reward("456456")?.id);
+        assert_eq!(first.id, reward("123123")?.id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flexible_reward_types_paid_at_once_are_distinct() -> anyhow::Result<()> {
+        let assets = AssetRegistry::builtin();
+        let reward = |reward_type: &str| -> anyhow::Result<Transaction> {
+            let reward = serde_json::from_str::<EarnReward>(&format!(
+                r#"{{"asset": "USDT","rewards": "0.000064","productId": "USDT001","type": "{reward_type}","time": 1646182276000}}"#
+            ))?;
+            tx_from_reward(&assets, &reward, Earn::Flexible, Some("USDT001"))
+        };
+        let bonus = reward("BONUS")?;
+        bonus.validate()?;
+        assert_ne!(bonus.id, reward("REALTIME")?.id);
+        assert_eq!(bonus.id, reward("BONUS")?.id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unnamed_records_of_distinct_positions_are_distinct() -> anyhow::Result<()> {
+        let assets = AssetRegistry::builtin();
+        let staking = |position_id: &str| -> anyhow::Result<Transaction> {
+            let reward = serde_json::from_str::<StakingRecord>(&format!(
+                r#"{{"positionId": "{position_id}","time": 1646182276000,"asset": "BNB","project": "BNB*90","amount": "0.05","status": "SUCCESS"}}"#
+            ))?;
+            tx_from_staking_reward(&assets, &reward)
+        };
+        assert_ne!(staking("7416")?.id, staking("7417")?.id);
+        assert_eq!(staking("7416")?.id, staking("7416")?.id);
+
+        let subscription = |position_id: &str| -> anyhow::Result<Transaction> {
+            let subscription = serde_json::from_str::<EarnSubscription>(&format!(
+                r#"{{"positionId": "{position_id}","projectId": "Axs*90","time": 1646182276000,"asset": "AXS","amount": "100","status": "PURCHASE"}}"#
+            ))?;
+            tx_from_subscription(&assets, &subscription, Earn::Locked)
+        };
+        assert_ne!(subscription("123123")?.id, subscription("456456")?.id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_locked_yield_drift() -> anyhow::Result<()> {
+        let assets = AssetRegistry::builtin();
+        let subscription = serde_json::from_str::<EarnSubscription>(
+            r#"{"positionId": "123123","purchaseId": "26055","projectId": "Axs*90","time": 1646182276000,"asset": "AXS","amount": "100","lockPeriod": "30","type": "AUTO","status": "PURCHASE"}"#,
+        )?;
+        // the reward only names its position, the subscription says which product it is in
+        let locked_products = LockedProducts::new(&[], std::slice::from_ref(&subscription));
+        let from = subscription.time;
+        let to = from + TimeDelta::days(30);
+        let reward = locked_reward("123123", to.timestamp_millis(), "1.20")?;
+        let product_id = locked_products.get(reward.position_id.as_ref());
+        assert_eq!(product_id, Some("Axs*90"));
+        let txs = [
+            tx_from_subscription(&assets, &subscription, Earn::Locked)?,
+            tx_from_reward(&assets, &reward, Earn::Locked, product_id)?,
+        ];
+
+        let mut products = AllProducts::new();
+        let locked = serde_json::from_str::<LockedEarnPos>(LOCKED_AXS_POSITION)?;
+        products.insert(product_from_locked(&assets, &locked)?);
+        let replay = BalanceReplay::replay(&txs)?;
+        let yields = RealizedYields::compute(&txs, &replay, &products, from, to);
+
+        // paid to spot, but earned by the locked product advertising 23%
+        let [drifting] = &yields.drifting(0.05).collect::<Vec<_>>()[..] else {
+            panic!("expected the locked product to drift");
+        };
+        let axs = AssetId::from("AXS");
+        let earn_account = earn(&axs, "Axs*90");
+        assert_eq!(drifting.account_id, earn_account);
+        assert_eq!(drifting.advertised_apy, Some(0.23));
+        assert!((drifting.realized_apy - 0.156).abs() < 1e-3);
+        // the earn account only passes the reward on
+        let principal = replay.balance_at(&earn_account, to).map(|b| b.units);
+        assert_eq!(principal, Some(100_000_000_000_000_000_000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_looks_back() {
+        let history_start = Utc.with_ymd_and_hms(2017, 7, 1, 0, 0, 0).unwrap();
+        assert_eq!(sync_start(&SyncCursor::default(), history_start), None);
+        let last = Utc.with_ymd_and_hms(2024, 4, 19, 12, 0, 0).unwrap();
+        let cursor = SyncCursor {
+            last_datetime: Some(last),
+            ..SyncCursor::default()
+        };
+        assert_eq!(
+            sync_start(&cursor, history_start),
+            Some(last - SYNC_LOOKBACK)
+        );
+        // never before the configured start
+        assert_eq!(sync_start(&cursor, last), Some(last));
+    }
+
+    #[test]
+    fn test_trades_and_dust_balance() -> anyhow::Result<()> {
+        let trade = serde_json::from_str::<Trade>(
+            r#"{"symbol": "BNBUSDT","id": 28457,"orderId": 100234,"orderListId": -1,"price": "400.0","qty": "0.5","quoteQty": "200.0","commission": "0.0005","commissionAsset": "BNB","time": 1499865549590,"isBuyer": true,"isMaker": false,"isBestMatch": true}"#,
+        )?;
+        let pair = TradePair::from_str("bnb/usdt")?;
+        assert_eq!(pair.symbol(), "BNBUSDT");
+        let tx = tx_from_trade(&AssetRegistry::builtin(), &trade, &pair)?;
+        tx.validate()?;
+        let holdings = summary(&tx)
+            .into_iter()
+            .filter(|(kind, _, _)| *kind == AccountKind::Holding)
+            .collect::<Vec<_>>();
+        let (bnb, usdt) = (AssetId::from("BNB"), AssetId::from("USDT"));
+        assert_eq!(
+            holdings,
+            [
+                (AccountKind::Holding, usdt.clone(), -200_000_000),
+                (AccountKind::Holding, bnb.clone(), -500_000_000_000_000),
+                (AccountKind::Holding, bnb, 500_000_000_000_000_000),
+            ]
+        );
+
+        let dust = serde_json::from_str::<DustConversion>(
+            r#"{"operateTime": 1615985535000,"transId": 45178372831,"userAssetDribbletDetails": [{"transId": 4359321,"serviceChargeAmount": "0.000009","amount": "0.0009","operateTime": 1615985535000,"transferedAmount": "0.000441","fromAsset": "USDT"},{"transId": 4359321,"serviceChargeAmount": "0.00001","amount": "0.0011","operateTime": 1615985535000,"transferedAmount": "0.0005","fromAsset": "ETH"}]}"#,
+        )?;
+        let tx = tx_from_dust(&AssetRegistry::builtin(), &dust)?;
+        tx.validate()?;
+        assert_eq!(
+            tx.effects()
+                .filter(|e| e.account_id.kind == AccountKind::Fee)
+                .count(),
+            2
+        );
+        Ok(())
     }
 }
 
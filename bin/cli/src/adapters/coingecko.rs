@@ -1,49 +1,167 @@
 use coingecko_client::{CoingeckoClient, payloads::CurrentPriceReq};
 use serde::Serialize;
 
-use lib_core::{AssetId, history::AssetPricePoint};
+use chrono::{NaiveDate, NaiveTime, Utc};
+use lib_core::{
+    AssetId, AssetRegistry, ProviderId, Store,
+    history::{AssetPriceHistory, AssetPricePoint},
+};
+use std::collections::HashSet;
+use std::sync::Arc;
 
 pub const PROVIDER_ID_COINGECKO: &str = "coingecko";
 
 pub struct CoinGeckoSvc {
     pub api_client: CoingeckoClient,
+    pub assets: Arc<AssetRegistry>,
 }
 impl CoinGeckoSvc {
     pub fn service_id() -> &'static str {
         PROVIDER_ID_COINGECKO
     }
-    pub fn new() -> anyhow::Result<Self> {
+    pub fn new(assets: Arc<AssetRegistry>) -> anyhow::Result<Self> {
         Ok(CoinGeckoSvc {
             api_client: CoingeckoClient::new()?,
+            assets,
         })
     }
 
-    pub async fn fetch_current_prices(&self) -> anyhow::Result<Vec<AssetPricePoint>> {
-        let resp = self
-            .api_client
-            .fetch_current_prices(CurrentPriceReq::default())
-            .await?;
+    /// Prices of every registry asset CoinGecko lists, in each of `vs_asset_ids`
+    pub async fn fetch_current_prices(
+        &self,
+        vs_asset_ids: &[AssetId],
+    ) -> anyhow::Result<Vec<AssetPricePoint>> {
+        let provider = ProviderId::from(PROVIDER_ID_COINGECKO);
+        let ids = self
+            .assets
+            .assets()
+            .filter_map(|asset| self.assets.external_id(&asset.id, &provider))
+            .map(str::to_string)
+            .collect();
+        let vs_ids = vs_asset_ids
+            .iter()
+            .map(|vs| {
+                let vs_id = self.assets.external_id(vs, &provider);
+                vs_id
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow::anyhow!("{vs:?} has no coingecko id"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let req = CurrentPriceReq::new(ids, vs_ids);
+        let resp = self.api_client.fetch_current_prices(req).await?;
 
         let prices = resp
             .into_iter()
-            .map(|p| AssetPricePoint {
-                asset_id: asset_id_from_coingecko(&p.asset_id),
-                vs_asset_id: asset_id_from_coingecko(&p.vs_asset_id),
-                price: p.price,
-                datetime: p.time,
+            .map(|p| {
+                Ok(AssetPricePoint {
+                    asset_id: self.assets.resolve(&provider, &p.asset_id)?.id.clone(),
+                    vs_asset_id: self.assets.resolve(&provider, &p.vs_asset_id)?.id.clone(),
+                    price: p.price,
+                    datetime: p.time,
+                })
             })
-            .collect::<Vec<AssetPricePoint>>();
+            .collect::<anyhow::Result<Vec<AssetPricePoint>>>()?;
 
         Ok(prices)
     }
+
+    /// Daily-or-finer prices of `asset_id` in `vs_asset_id` from `from` to `to` (inclusive).
+    /// Only days missing from the store are fetched; past days that got a price, or that
+    /// CoinGecko reports as before listing, are then never fetched again.
+    pub async fn fetch_price_history(
+        &self,
+        store: &mut Store,
+        asset_id: &AssetId,
+        vs_asset_id: &AssetId,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> anyhow::Result<AssetPriceHistory> {
+        let provider = ProviderId::from(PROVIDER_ID_COINGECKO);
+        let coin_id = self
+            .assets
+            .external_id(asset_id, &provider)
+            .ok_or_else(|| anyhow::anyhow!("{asset_id:?} has no coingecko id"))?;
+        let vs_id = self
+            .assets
+            .external_id(vs_asset_id, &provider)
+            .ok_or_else(|| anyhow::anyhow!("{vs_asset_id:?} has no coingecko id"))?;
+
+        let today = Utc::now().date_naive();
+        let missing = store.missing_price_days(asset_id, vs_asset_id, from, to.min(today))?;
+        for (first, last) in contiguous_ranges(&missing) {
+            let (resp, unlisted) = if first == last {
+                match self.api_client.fetch_history(coin_id, first).await? {
+                    Some(mut points) => {
+                        points.retain(|p| p.vs_asset_id == vs_id);
+                        (points, false)
+                    }
+                    None => (Vec::new(), true),
+                }
+            } else {
+                let (start, end) = (first, last.succ_opt().unwrap_or(last));
+                let points = self
+                    .api_client
+                    .fetch_market_chart_range(coin_id, vs_id, start_of(start), start_of(end))
+                    .await?;
+                (points, false)
+            };
+            let points = resp
+                .into_iter()
+                .map(|p| AssetPricePoint {
+                    datetime: p.time,
+                    asset_id: asset_id.clone(),
+                    vs_asset_id: vs_asset_id.clone(),
+                    price: p.price,
+                })
+                .collect::<Vec<_>>();
+            store.save_prices(&points)?;
+
+            let complete = if unlisted {
+                complete_days(first, last, today, |_| true)
+            } else {
+                let priced = points
+                    .iter()
+                    .map(|p| p.datetime.date_naive())
+                    .collect::<HashSet<_>>();
+                complete_days(first, last, today, |day| priced.contains(day))
+            };
+            store.mark_price_days_fetched(asset_id, vs_asset_id, &complete)?;
+        }
+
+        let end = to.succ_opt().map(start_of).unwrap_or(start_of(to));
+        store.get_price_history(asset_id, vs_asset_id, start_of(from), end)
+    }
+}
+
+fn start_of(day: NaiveDate) -> chrono::DateTime<Utc> {
+    day.and_time(NaiveTime::MIN).and_utc()
 }
 
-/// Convert a CoinGecko asset identifier to an AssetId
-fn asset_id_from_coingecko(coingecko_asset: &str) -> AssetId {
-    match coingecko_asset {
-        "ETH" => AssetId::Eth,
-        _ => AssetId::unknown(coingecko_asset),
+/// Days of `[first, last]` that can be marked fetched: before today, whose prices are still
+/// moving, and accepted by `has_data` so empty or truncated responses get retried
+fn complete_days(
+    first: NaiveDate,
+    last: NaiveDate,
+    today: NaiveDate,
+    has_data: impl Fn(&NaiveDate) -> bool,
+) -> Vec<NaiveDate> {
+    first
+        .iter_days()
+        .take_while(|day| *day <= last && *day < today)
+        .filter(has_data)
+        .collect()
+}
+
+/// Groups sorted days into runs of consecutive days, as (first, last) pairs
+fn contiguous_ranges(days: &[NaiveDate]) -> Vec<(NaiveDate, NaiveDate)> {
+    let mut ranges: Vec<(NaiveDate, NaiveDate)> = Vec::new();
+    for &day in days {
+        match ranges.last_mut() {
+            Some((_, last)) if last.succ_opt() == Some(day) => *last = day,
+            _ => ranges.push((day, day)),
+        }
     }
+    ranges
 }
 
 pub mod old {
@@ -134,3 +252,36 @@ pub mod old {
     //     todo!()
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contiguous_ranges() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 4, d).unwrap();
+        let days = [day(1), day(2), day(3), day(5), day(7), day(8)];
+        assert_eq!(
+            contiguous_ranges(&days),
+            [(day(1), day(3)), (day(5), day(5)), (day(7), day(8))]
+        );
+        assert!(contiguous_ranges(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_complete_days() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 4, d).unwrap();
+        // a truncated response only covered the 1st and 2nd
+        let priced = [day(1), day(2)];
+        assert_eq!(
+            complete_days(day(1), day(4), day(10), |d| priced.contains(d)),
+            [day(1), day(2)]
+        );
+        // today is never complete
+        assert_eq!(
+            complete_days(day(8), day(10), day(10), |_| true),
+            [day(8), day(9)]
+        );
+        assert!(complete_days(day(1), day(4), day(10), |_| false).is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use chrono::{DateTime, Utc};
+use lib_core::{AccountId, Amount, ProviderId, Transaction, TransactionId, TxEffect};
+use std::fmt::Display;
+
+/// Balanced effects of a single provider record
+pub struct Effects {
+    provider: ProviderId,
+    datetime: DateTime<Utc>,
+    native_id: Option<String>,
+    tx_hash: Option<String>,
+    inputs: Vec<TxEffect>,
+    outputs: Vec<TxEffect>,
+}
+impl Effects {
+    pub fn new(provider: ProviderId, datetime: DateTime<Utc>) -> Self {
+        Effects {
+            provider,
+            datetime,
+            native_id: None,
+            tx_hash: None,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Identifies the record by the provider's own id rather than by its content
+    pub fn with_native_id(mut self, native_id: impl Display) -> Self {
+        self.native_id = Some(native_id.to_string());
+        self
+    }
+
+    /// On-chain hash of the record, blank ones are ignored
+    pub fn with_tx_hash(mut self, tx_hash: Option<&str>) -> Self {
+        self.tx_hash = tx_hash
+            .map(str::trim)
+            .filter(|hash| !hash.is_empty())
+            .map(str::to_string);
+        self
+    }
+
+    /// Moves a positive `amount` from one account to another
+    pub fn transfer(
+        &mut self,
+        from: AccountId,
+        to: AccountId,
+        amount: Amount,
+    ) -> anyhow::Result<()> {
+        self.inputs.push(TxEffect {
+            account_id: from,
+            amount: amount.checked_neg()?,
+            datetime: self.datetime,
+        });
+        self.outputs.push(TxEffect {
+            account_id: to,
+            amount,
+            datetime: self.datetime,
+        });
+        Ok(())
+    }
+
+    /// Trades `sold` from one account for `bought` into another, with the provider as
+    /// counterparty. A no-op when both sides are the same asset.
+    pub fn swap(
+        &mut self,
+        from: AccountId,
+        sold: Amount,
+        to: AccountId,
+        bought: Amount,
+    ) -> anyhow::Result<()> {
+        if sold.asset_id == bought.asset_id {
+            return Ok(());
+        }
+        let sold_to = AccountId::external(self.provider.clone(), sold.asset_id.clone());
+        let bought_from = AccountId::external(self.provider.clone(), bought.asset_id.clone());
+        self.transfer(from, sold_to, sold)?;
+        self.transfer(bought_from, to, bought)
+    }
+
+    /// Income earned by the `earner` account and paid into `to`. When they differ, the
+    /// income passes through the earner so that it is attributed to it.
+    pub fn income(
+        &mut self,
+        earner: AccountId,
+        to: AccountId,
+        amount: Amount,
+    ) -> anyhow::Result<()> {
+        let income = AccountId::income(self.provider.clone(), amount.asset_id.clone());
+        if earner != to {
+            self.transfer(income, earner.clone(), amount.clone())?;
+            return self.transfer(earner, to, amount);
+        }
+        self.transfer(income, to, amount)
+    }
+
+    /// Pays a fee out of `from`, skipped when zero
+    pub fn fee(&mut self, from: AccountId, fee: Amount) -> anyhow::Result<()> {
+        if fee.is_zero() {
+            return Ok(());
+        }
+        let fee_account = AccountId::fee(self.provider.clone(), fee.asset_id.clone());
+        self.transfer(from, fee_account, fee)
+    }
+
+    pub fn into_transaction(self) -> Transaction {
+        let mut tx = Transaction {
+            id: TransactionId::default(),
+            datetime: self.datetime,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            tx_hash: self.tx_hash,
+        };
+        tx.id = match &self.native_id {
+            Some(native_id) => TransactionId::native(&self.provider, native_id),
+            None => TransactionId::from_content(&self.provider, &tx),
+        };
+        tx
+    }
+}
+
+/// Moves transactions mapped with a provider's default id onto the accounts of one of
+/// its configured instances, e.g. "binance" to "binance-sub"
+pub fn relabel_all(mut transactions: Vec<Transaction>, label: &ProviderId) -> Vec<Transaction> {
+    for tx in &mut transactions {
+        tx.id = tx.id.with_provider(label);
+        for effect in tx.inputs.iter_mut().chain(tx.outputs.iter_mut()) {
+            effect.account_id.provider = label.clone();
+        }
+    }
+    transactions
+}
+
+/// (account kind, asset, units) of every effect of a mapped transaction, inputs first
+#[cfg(test)]
+pub fn summary(tx: &Transaction) -> Vec<(lib_core::AccountKind, lib_core::AssetId, i128)> {
+    tx.effects()
+        .map(|e| {
+            let kind = e.account_id.kind.clone();
+            (kind, e.amount.asset_id.clone(), e.amount.units)
+        })
+        .collect()
+}
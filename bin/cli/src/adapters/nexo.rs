@@ -1,138 +1,472 @@
+use crate::adapters::effects::{Effects, relabel_all};
+use chrono::{DateTime, Utc};
 use lib_core::traits::IsProvider;
-use lib_core::{AccountId, AssetId, Position, ProviderId, Transaction, TxEffect};
-use nexo_csv::{NexoCsv, NexoTx};
+use lib_core::{
+    AccountId, Amount, AssetId, AssetRegistry, Position, ProductId, ProviderId, SyncBatch,
+    SyncCursor, Transaction,
+};
+use nexo_csv::{NexoCsv, NexoImport, NexoTx};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 pub struct NexoSvc {
-    // pub transactions_csv: NexoCsv,
+    /// Instance label, used as the provider of imported accounts
+    pub label: ProviderId,
+    /// A Nexo CSV export, or a directory of exports
+    pub csv_path: PathBuf,
+    pub assets: Arc<AssetRegistry>,
 }
 impl NexoSvc {
-    pub fn new() -> anyhow::Result<Self> {
+    pub fn new(
+        label: ProviderId,
+        csv_path: impl Into<PathBuf>,
+        assets: Arc<AssetRegistry>,
+    ) -> anyhow::Result<Self> {
         Ok(NexoSvc {
-            // transactions_csv: NexoCsv {},
+            label,
+            csv_path: csv_path.into(),
+            assets,
         })
     }
 
-    pub fn fetch_transactions(&self) -> anyhow::Result<Vec<()>> {
-        let mut transactions = NexoCsv::read_all()?;
-        transactions.sort_by(|a, b| a.date_time_utc.cmp(&b.date_time_utc));
-
-        dbg!(&transactions);
-
-        // TODO create DataFrame with only difference in ETH and timestamp
-        // TODO cumsum for total ETH
-
-        Ok(Vec::new())
+    /// Reads the exports, reporting malformed rows instead of failing on them
+    fn read_csv(&self) -> anyhow::Result<NexoImport> {
+        let import = NexoCsv::read_path(&self.csv_path)?;
+        for err in &import.errors {
+            eprintln!("skipped malformed Nexo row: {err}");
+        }
+        Ok(import)
     }
 }
+pub const PROVIDER_ID_NEXO: &str = "nexo";
+
 #[async_trait::async_trait]
 impl IsProvider for NexoSvc {
     fn provider_id(&self) -> ProviderId {
-        const PROVIDER_ID_NEXO: &str = "nexo";
-        ProviderId::from(PROVIDER_ID_NEXO)
+        self.label.clone()
     }
 
     async fn fetch_positions(&self) -> anyhow::Result<Vec<Position>> {
-        todo!()
+        // the CSV export only has transactions, balances come from replaying them
+        Ok(Vec::new())
     }
 
     async fn fetch_transactions(&self) -> anyhow::Result<Vec<Transaction>> {
-        let mut nexo_transactions = NexoCsv::read_all()?;
-        nexo_transactions.sort_by(|a, b| a.date_time_utc.cmp(&b.date_time_utc));
-
-        let transactions = nexo_transactions
-            .into_iter()
-            .map(|ntx| transaction_from_nexo_tx(ntx))
-            .collect();
+        let (transactions, _) = self.map_transactions(None)?;
+        Ok(relabel_all(transactions, &self.label))
+    }
 
-        Ok(transactions)
+    /// Exports are read in full, rows older than the cursor are left out. Rows sharing the
+    /// cursor's datetime are fetched again, in case an export was cut in the middle of them.
+    /// The cursor stops at the oldest row that couldn't be mapped, so it is retried.
+    async fn fetch_transactions_since(&self, cursor: &SyncCursor) -> anyhow::Result<SyncBatch> {
+        let from = cursor.last_datetime;
+        let (transactions, oldest_skipped) = self.map_transactions(from)?;
+        let transactions = relabel_all(transactions, &self.label);
+        Ok(SyncBatch {
+            from,
+            cursor: cursor
+                .clone()
+                .advance(&transactions)
+                .hold_at(oldest_skipped),
+            transactions,
+        })
     }
 }
 
-fn get_decimals(asset: &str) -> u8 {
-    match asset {
-        "ETH" => 18,
-        "BTC" => 8,
-        "USDT" => 6,
-        _ => 18, // Default
+impl NexoSvc {
+    /// Maps the rows dated at or after `from`, oldest first, along with the date of the
+    /// oldest row that couldn't be mapped
+    fn map_transactions(
+        &self,
+        from: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<(Vec<Transaction>, Option<DateTime<Utc>>)> {
+        let mut nexo_transactions = self.read_csv()?.transactions;
+        nexo_transactions.retain(|tx| from.is_none_or(|from| tx.date_time_utc >= from));
+        nexo_transactions.sort_by_key(|tx| tx.date_time_utc);
+
+        // a row we can't map shouldn't hide the rest of the history
+        let mut transactions = Vec::new();
+        let mut oldest_skipped = None;
+        for nexo_tx in nexo_transactions {
+            let (tx_id, datetime) = (nexo_tx.tx_id.clone(), nexo_tx.date_time_utc);
+            match transaction_from_nexo_tx(nexo_tx, &self.assets) {
+                Ok(tx) => transactions.push(tx),
+                Err(err) => {
+                    eprintln!("skipped Nexo transaction {tx_id}: {err}");
+                    oldest_skipped = oldest_skipped.or(Some(datetime));
+                }
+            }
+        }
+        Ok((transactions, oldest_skipped))
     }
 }
 
-fn to_u64(amount: f64, decimals: u8) -> u64 {
-    (amount * 10f64.powi(decimals as i32)).round() as u64
+/// Resolves a Nexo currency and converts a quantity of it to base units
+fn to_amount(assets: &AssetRegistry, amount: &str, nexo_asset: &str) -> anyhow::Result<Amount> {
+    let asset = assets.resolve(&ProviderId::from(PROVIDER_ID_NEXO), nexo_asset)?;
+    Ok(asset.amount_from_str(amount)?)
 }
 
-/// Convert a Nexo transaction to a Transaction
-fn transaction_from_nexo_tx(nexo_tx: NexoTx) -> Transaction {
-    let inputs = match &nexo_tx.kind {
-        // TODO make transactionInputOutput use ProductId not assetId
-        // TODO figure out content by logging
-        nexo_csv::TransactionType::ExchangeDepositedOn => vec![],
-        nexo_csv::TransactionType::DepositToExchange => vec![],
-        nexo_csv::TransactionType::WithdrawExchanged => vec![],
-        nexo_csv::TransactionType::ExchangeToWithdraw => vec![],
-        // should have no impact on total -> no inputs/outputs // TODO same inputs / outputs
-        nexo_csv::TransactionType::TransferFromProWallet => vec![],
-        nexo_csv::TransactionType::TransferToProWallet => vec![],
-        nexo_csv::TransactionType::LockTermDeposit => vec![], // TODO some(move between products)
-        nexo_csv::TransactionType::UnlockTermDeposit => vec![], // TODO Some(move between products)
-        // TODO inputs from other people / outside of Nexo
-        nexo_csv::TransactionType::TopUpCrypto => vec![],
-        nexo_csv::TransactionType::TermInterest => vec![],
-        nexo_csv::TransactionType::Interest => vec![],
-    };
-    let outputs = match &nexo_tx.kind {
-        nexo_csv::TransactionType::Interest => vec![TxEffect {
-            // asset: asset_id_from_nexo(&nexo_tx.output_currency),
-            amount: to_u64(
-                nexo_tx.output_amount,
-                get_decimals(&nexo_tx.output_currency),
-            ),
-            account_id: todo!(),
-            datetime: todo!(),
-        }],
-        nexo_csv::TransactionType::TopUpCrypto => vec![TxEffect {
-            // asset: asset_id_from_nexo(&nexo_tx.output_currency),
-            amount: to_u64(
-                nexo_tx.output_amount,
-                get_decimals(&nexo_tx.output_currency),
-            ),
-            account_id: todo!(),
-            datetime: todo!(),
-        }],
-        nexo_csv::TransactionType::TermInterest => vec![TxEffect {
-            // asset: asset_id_from_nexo(&nexo_tx.output_currency),
-            amount: to_u64(
-                nexo_tx.output_amount,
-                get_decimals(&nexo_tx.output_currency),
-            ),
-            account_id: todo!(),
-            datetime: todo!(),
-        }],
-
-        // TODO figure out content by logging
-        nexo_csv::TransactionType::ExchangeDepositedOn => vec![],
-        nexo_csv::TransactionType::DepositToExchange => vec![],
-        nexo_csv::TransactionType::WithdrawExchanged => vec![],
-        nexo_csv::TransactionType::ExchangeToWithdraw => vec![],
-        // should have no impact on total -> no inputs/outputs // TODO same inputs / outputs
-        nexo_csv::TransactionType::TransferFromProWallet => vec![],
-        nexo_csv::TransactionType::TransferToProWallet => vec![],
-        nexo_csv::TransactionType::LockTermDeposit => vec![], // TODO some(move between products)
-        nexo_csv::TransactionType::UnlockTermDeposit => vec![], // TODO Some(move between products)
-    };
+/// Convert a Nexo transaction to a balanced Transaction
+fn transaction_from_nexo_tx(
+    nexo_tx: NexoTx,
+    assets: &AssetRegistry,
+) -> anyhow::Result<Transaction> {
+    use nexo_csv::TransactionType as Type;
 
-    Transaction {
-        // id: TransactionId::from(""), // TODO
-        datetime: nexo_tx.date_time_utc,
-        inputs,
-        outputs,
+    let input = to_amount(assets, nexo_tx.input_quantity(), &nexo_tx.input_currency)?;
+    let output = to_amount(assets, nexo_tx.output_quantity(), &nexo_tx.output_currency)?;
+    let in_asset = input.asset_id.clone();
+    let out_asset = output.asset_id.clone();
+
+    let provider = ProviderId::from(PROVIDER_ID_NEXO);
+    let mut effects = Effects::new(provider, nexo_tx.date_time_utc)
+        .with_native_id(&nexo_tx.tx_id)
+        .with_tx_hash(nexo_tx.tx_hash());
+    match &nexo_tx.kind {
+        // inputs from other people / outside of Nexo
+        Type::TopUpCrypto
+        | Type::Deposit
+        | Type::TransferIn
+        | Type::LoanWithdrawal
+        | Type::NexoCardRefund => {
+            effects.transfer(external(&out_asset), savings(&out_asset), output)?
+        }
+        // paid to the savings wallet, but earned by the term deposit
+        Type::TermInterest => {
+            effects.income(term_deposit(&out_asset), savings(&out_asset), output)?
+        }
+        Type::Interest
+        | Type::Cashback
+        | Type::ExchangeCashback
+        | Type::ReferralBonus
+        | Type::Dividend => effects.transfer(income(&out_asset), savings(&out_asset), output)?,
+        // outputs to other people / outside of Nexo
+        Type::Withdrawal
+        | Type::TransferOut
+        | Type::Repayment
+        | Type::ManualRepayment
+        | Type::Liquidation
+        | Type::NexoCardPurchase => {
+            effects.transfer(savings(&in_asset), external(&in_asset), input)?
+        }
+        Type::Exchange | Type::ManualSellOrder => {
+            effects.swap(savings(&in_asset), input, savings(&out_asset), output)?
+        }
+        // moves between products, no impact on total
+        Type::LockTermDeposit => {
+            effects.transfer(savings(&in_asset), term_deposit(&out_asset), output)?
+        }
+        Type::UnlockTermDeposit => {
+            effects.transfer(term_deposit(&in_asset), savings(&out_asset), output)?
+        }
+        Type::TransferToProWallet => {
+            effects.transfer(savings(&in_asset), pro_wallet(&out_asset), output)?
+        }
+        Type::TransferFromProWallet => {
+            effects.transfer(pro_wallet(&in_asset), savings(&out_asset), output)?
+        }
+        // funds deposited from outside, then exchanged
+        Type::ExchangeDepositedOn | Type::DepositToExchange => {
+            effects.transfer(external(&in_asset), savings(&in_asset), input.clone())?;
+            effects.swap(savings(&in_asset), input, savings(&out_asset), output)?;
+        }
+        // funds exchanged, then withdrawn outside
+        Type::ExchangeToWithdraw | Type::WithdrawExchanged => {
+            effects.swap(
+                savings(&in_asset),
+                input,
+                savings(&out_asset),
+                output.clone(),
+            )?;
+            effects.transfer(savings(&out_asset), external(&out_asset), output)?;
+        }
+        Type::Other(label) => anyhow::bail!("unsupported Nexo transaction type {label:?}"),
+    };
+    if let Some((fee, fee_asset)) = nexo_tx.fee() {
+        let fee = to_amount(assets, fee, fee_asset)?;
+        effects.fee(savings(&fee.asset_id), fee)?;
     }
+
+    Ok(effects.into_transaction())
+}
+
+/// Exports don't say which term product a deposit is locked in, so term deposits of an
+/// asset share one account
+const PRODUCT_ID_TERM_DEPOSIT: &str = "nexo-term-deposit";
+const PRODUCT_ID_PRO_WALLET: &str = "nexo-pro-wallet";
+
+/// The Nexo savings wallet holding the given asset
+fn savings(asset: &AssetId) -> AccountId {
+    AccountId::new(ProviderId::from(PROVIDER_ID_NEXO), asset.clone())
+}
+
+fn term_deposit(asset: &AssetId) -> AccountId {
+    AccountId::product(
+        ProviderId::from(PROVIDER_ID_NEXO),
+        asset.clone(),
+        ProductId::from(PRODUCT_ID_TERM_DEPOSIT),
+    )
+}
+
+fn pro_wallet(asset: &AssetId) -> AccountId {
+    AccountId::product(
+        ProviderId::from(PROVIDER_ID_NEXO),
+        asset.clone(),
+        ProductId::from(PRODUCT_ID_PRO_WALLET),
+    )
 }
 
-/// Convert a Nexo asset identifier to an AssetId
-fn asset_id_from_nexo(nexo_asset: &str) -> AssetId {
-    match nexo_asset {
-        "ETH" => AssetId::Eth,
-        _ => AssetId::unknown(nexo_asset),
+fn external(asset: &AssetId) -> AccountId {
+    AccountId::external(ProviderId::from(PROVIDER_ID_NEXO), asset.clone())
+}
+
+fn income(asset: &AssetId) -> AccountId {
+    AccountId::income(ProviderId::from(PROVIDER_ID_NEXO), asset.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::effects::summary;
+    use lib_core::{AccountKind, TransactionId};
+    use std::collections::HashMap;
+
+    const FIXTURE: &str = include_str!("../../fixtures/nexo_transactions.csv");
+
+    fn fixture_tx(nexo_tx_id: &str) -> anyhow::Result<Transaction> {
+        let nexo_tx = NexoCsv::read(FIXTURE.as_bytes())?
+            .transactions
+            .into_iter()
+            .find(|ntx| ntx.tx_id == nexo_tx_id)
+            .expect("fixture row");
+        let tx = transaction_from_nexo_tx(nexo_tx, &AssetRegistry::builtin())?;
+        tx.validate()?;
+        Ok(tx)
+    }
+
+    const ETH: i128 = 1_000_000_000_000_000_000;
+
+    #[test]
+    fn test_every_fixture_row_balances() -> anyhow::Result<()> {
+        let import = NexoCsv::read(FIXTURE.as_bytes())?;
+        assert!(import.errors.is_empty());
+        for nexo_tx in import.transactions {
+            if matches!(nexo_tx.kind, nexo_csv::TransactionType::Other(_)) {
+                continue;
+            }
+            let tx_id = nexo_tx.tx_id.clone();
+            let tx = transaction_from_nexo_tx(nexo_tx, &AssetRegistry::builtin())?;
+            assert!(!tx.inputs.is_empty(), "{tx_id} has no effects");
+            tx.validate().map_err(|e| anyhow::anyhow!("{tx_id}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_since_cursor() -> anyhow::Result<()> {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/nexo_transactions.csv"
+        );
+        let label = ProviderId::from("nexo-joint");
+        let svc = NexoSvc::new(label.clone(), path, Arc::new(AssetRegistry::builtin()))?;
+
+        let full = svc.fetch_transactions_since(&SyncCursor::default()).await?;
+        assert_eq!(full.from, None);
+        let last = full.cursor.last_datetime.expect("fixture has rows");
+        assert!(
+            full.transactions
+                .iter()
+                .flat_map(|tx| tx.effects())
+                .all(|e| e.account_id.provider == label)
+        );
+        let top_up = TransactionId::from("nexo-joint:NXTtopup0001");
+        assert!(full.transactions.iter().any(|tx| tx.id == top_up));
+
+        // the newest rows are fetched again, nothing older
+        let resumed = svc.fetch_transactions_since(&full.cursor).await?;
+        assert_eq!(resumed.from, Some(last));
+        assert!(!resumed.transactions.is_empty());
+        assert!(resumed.transactions.iter().all(|tx| tx.datetime == last));
+        assert_eq!(resumed.cursor, full.cursor);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_stops_at_unmapped_rows() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("nexo-sync-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let csv = [
+            "Transaction,Type,Input Currency,Input Amount,Output Currency,Output Amount,USD Equivalent,Details,Date / Time (UTC)",
+            "NXT1,Interest,ETH,0.0005,ETH,0.0005,$1.50,approved,2024-01-01 06:00:00",
+            "NXT2,Interest,NOTLISTED,1,NOTLISTED,1,-,approved,2024-01-02 06:00:00",
+            "NXT3,Interest,ETH,0.0005,ETH,0.0005,$1.50,approved,2024-01-03 06:00:00",
+        ];
+        std::fs::write(dir.join("export.csv"), csv.join("\n"))?;
+        let label = ProviderId::from(PROVIDER_ID_NEXO);
+        let svc = NexoSvc::new(label, &dir, Arc::new(AssetRegistry::builtin()))?;
+
+        // NXT2 is fetched again by the next sync, once its asset may be registered
+        let batch = svc.fetch_transactions_since(&SyncCursor::default()).await?;
+        assert_eq!(batch.transactions.len(), 2);
+        let skipped_at = "2024-01-02T06:00:00Z".parse::<DateTime<Utc>>()?;
+        assert_eq!(batch.cursor.last_datetime, Some(skipped_at));
+        let resumed = svc.fetch_transactions_since(&batch.cursor).await?;
+        assert_eq!(resumed.transactions.len(), 1);
+        assert_eq!(resumed.cursor.last_datetime, Some(skipped_at));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_up_and_interest() -> anyhow::Result<()> {
+        let top_up = fixture_tx("NXTtopup0001")?;
+        assert_eq!(
+            summary(&top_up),
+            [
+                (AccountKind::External, AssetId::from("ETH"), -3 * ETH / 2),
+                (AccountKind::Holding, AssetId::from("ETH"), 3 * ETH / 2),
+            ]
+        );
+
+        let interest = fixture_tx("NXTinterest01")?;
+        assert_eq!(interest.inputs[0].account_id.kind, AccountKind::Income);
+        assert_eq!(interest.outputs[0].account_id.kind, AccountKind::Holding);
+
+        // term interest lands in savings through the term deposit that earned it
+        let term_deposit = AccountKind::Product(ProductId::from(PRODUCT_ID_TERM_DEPOSIT));
+        let eth = || AssetId::from("ETH");
+        assert_eq!(
+            summary(&fixture_tx("NXTterm000001")?),
+            [
+                (AccountKind::Income, eth(), -ETH / 100),
+                (term_deposit.clone(), eth(), -ETH / 100),
+                (term_deposit, eth(), ETH / 100),
+                (AccountKind::Holding, eth(), ETH / 100),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_term_deposit_lock_unlock() -> anyhow::Result<()> {
+        let term_deposit = AccountKind::Product(ProductId::from(PRODUCT_ID_TERM_DEPOSIT));
+        assert_eq!(
+            summary(&fixture_tx("NXTlock000001")?),
+            [
+                (AccountKind::Holding, AssetId::from("ETH"), -ETH),
+                (term_deposit.clone(), AssetId::from("ETH"), ETH),
+            ]
+        );
+        assert_eq!(
+            summary(&fixture_tx("NXTunlock0001")?),
+            [
+                (term_deposit, AssetId::from("ETH"), -ETH),
+                (AccountKind::Holding, AssetId::from("ETH"), ETH),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pro_wallet_transfers() -> anyhow::Result<()> {
+        let pro_wallet = AccountKind::Product(ProductId::from(PRODUCT_ID_PRO_WALLET));
+        let to_pro = fixture_tx("NXTtopro00001")?;
+        assert_eq!(to_pro.inputs[0].account_id.kind, AccountKind::Holding);
+        assert_eq!(to_pro.outputs[0].account_id.kind, pro_wallet);
+
+        let from_pro = fixture_tx("NXTfrompro001")?;
+        assert_eq!(from_pro.inputs[0].account_id.kind, pro_wallet);
+        assert_eq!(from_pro.outputs[0].account_id.kind, AccountKind::Holding);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exchanges_are_swaps() -> anyhow::Result<()> {
+        let eur = || AssetId::from("EUR");
+        let btc = || AssetId::from("BTC");
+
+        // EUR deposited then swapped for BTC: savings ends up with BTC only
+        let deposit = fixture_tx("NXTdepex00001")?;
+        let holdings = |tx: &Transaction| {
+            summary(tx)
+                .into_iter()
+                .filter(|(kind, _, _)| *kind == AccountKind::Holding)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            holdings(&deposit),
+            [
+                (AccountKind::Holding, eur(), -20_000_000_000),
+                (AccountKind::Holding, eur(), 20_000_000_000),
+                (AccountKind::Holding, btc(), 500_000),
+            ]
+        );
+        fixture_tx("NXTexdep00001")?;
+
+        // ETH swapped for EUR then withdrawn: savings only loses ETH
+        let withdraw = fixture_tx("NXTextow00001")?;
+        let net = withdraw
+            .effects()
+            .filter(|e| e.account_id.kind == AccountKind::Holding)
+            .fold(HashMap::<AssetId, i128>::new(), |mut net, e| {
+                *net.entry(e.amount.asset_id.clone()).or_default() += e.amount.units;
+                net
+            });
+        assert_eq!(net[&AssetId::from("ETH")], -ETH / 2);
+        assert_eq!(net[&eur()], 0);
+        fixture_tx("NXTwdex000001")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_withdrawal_with_fee() -> anyhow::Result<()> {
+        let usdt = || AssetId::from("USDT");
+        assert_eq!(
+            summary(&fixture_tx("NXTwithdraw01")?),
+            [
+                (AccountKind::Holding, usdt(), -100_000_000),
+                (AccountKind::Holding, usdt(), -2_500_000),
+                (AccountKind::External, usdt(), 100_000_000),
+                (AccountKind::Fee, usdt(), 2_500_000),
+            ]
+        );
+        assert_eq!(
+            fixture_tx("NXTcashback01")?.inputs[0].account_id.kind,
+            AccountKind::Income
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_type_is_rejected() -> anyhow::Result<()> {
+        let nexo_tx = NexoCsv::read(FIXTURE.as_bytes())?
+            .transactions
+            .into_iter()
+            .find(|ntx| matches!(ntx.kind, nexo_csv::TransactionType::Other(_)))
+            .expect("fixture row");
+        assert!(transaction_from_nexo_tx(nexo_tx, &AssetRegistry::builtin()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_amounts_are_exact() -> anyhow::Result<()> {
+        let csv = [
+            "Transaction,Type,Input Currency,Input Amount,Output Currency,Output Amount,USD Equivalent,Details,Date / Time (UTC)",
+            "NXT1,Interest,USDT,0.123456,USDT,0.123456,$0.12,approved / USDT Interest Earned,2024-01-01 06:00:00",
+            "NXT2,Interest,USDT,0.1234567,USDT,0.1234567,$0.12,approved / USDT Interest Earned,2024-01-02 06:00:00",
+        ]
+        .join("\n");
+        let mut rows = NexoCsv::read(csv.as_bytes())?.transactions.into_iter();
+        let assets = AssetRegistry::builtin();
+
+        let exact = transaction_from_nexo_tx(rows.next().expect("row"), &assets)?;
+        assert_eq!(exact.outputs[0].amount.units, 123_456);
+        // USDT has 6 decimals, a 7th isn't silently rounded away
+        assert!(transaction_from_nexo_tx(rows.next().expect("row"), &assets).is_err());
+        Ok(())
     }
 }
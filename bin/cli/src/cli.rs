@@ -0,0 +1,407 @@
+//! Command-line arguments: `money-tracker [--json] [--config FILE] <command> [args]`
+
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use lib_core::{AssetId, CostBasisMethod, DecisionKind};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: money-tracker [--json] [--config FILE] <command>
+
+Commands:
+  import <label> [--prune]           Re-fetch the whole history of one provider, --prune
+                                     removes stored transactions of the period it covers
+                                     that it doesn't report anymore
+  sync                               Fetch what's new for every enabled provider
+  sync status                        When each provider last synced
+  transfers                          Withdrawals matched with deposits on another provider
+  transfers <confirm|reject> <withdrawal id> <deposit id>
+                                     Confirm or reject a withdrawal and deposit pair
+  balances [--at DATE]               Balances replayed from stored transactions
+  positions                          Current positions reported by providers
+  networth [--at DATE] [--quote ASSET]
+                                     Total value, by asset, provider and product
+  yield [--quote ASSET]              Projected earn-per-year of current positions
+  gains [--method METHOD] [--at DATE] [--quote ASSET]
+                                     Realized gains per disposal, unrealized per open lot
+  tax <YEAR> [--method METHOD] [--quote ASSET] [--csv DIR]
+                                     Disposals, income and year-end holdings of a fiscal
+                                     year under the config's [tax] rules, --csv writes
+                                     disposals.csv, income.csv and holdings.csv to DIR
+  prices fetch [--from DATE] [--to DATE] [--quote ASSET] [ASSET...]
+                                     Fetch and cache daily price history
+  export <balances|effects|prices> [--quote ASSET] [--out FILE]
+                                     Write a CSV export, to stdout without --out
+
+Options:
+  --json                             Print JSON instead of tables
+  --config FILE                      Provider config, default: $MONEY_TRACKER_CONFIG,
+                                     then money-tracker.toml when present
+  -h, --help                         Print this help
+
+DATE is YYYY-MM-DD or RFC 3339, ASSET a canonical asset id (default quote: USD),
+METHOD the lots disposals consume: fifo (default), lifo, hifo or average";
+
+const DEFAULT_QUOTE: &str = "USD";
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum CliError {
+    #[error("missing command")]
+    MissingCommand,
+    #[error("unknown command {0:?}")]
+    UnknownCommand(String),
+    #[error("missing {0}")]
+    MissingArgument(&'static str),
+    #[error("unexpected argument {0:?}")]
+    UnexpectedArgument(String),
+    #[error("{0} expects a value")]
+    MissingValue(String),
+    #[error("invalid date {0:?}, expected YYYY-MM-DD or RFC 3339")]
+    InvalidDate(String),
+    #[error("invalid method {0:?}, expected fifo, lifo, hifo or average")]
+    InvalidMethod(String),
+    #[error("invalid year {0:?}")]
+    InvalidYear(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportKind {
+    Balances,
+    Effects,
+    Prices,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    Import {
+        label: String,
+        /// Remove stored transactions the provider doesn't report anymore
+        prune: bool,
+    },
+    Sync,
+    SyncStatus,
+    Transfers,
+    DecideTransfer {
+        withdrawal_id: String,
+        deposit_id: String,
+        kind: DecisionKind,
+    },
+    Balances {
+        at: Option<DateTime<Utc>>,
+    },
+    Positions,
+    NetWorth {
+        at: Option<DateTime<Utc>>,
+        quote: AssetId,
+    },
+    Yield {
+        quote: AssetId,
+    },
+    Gains {
+        method: CostBasisMethod,
+        at: Option<DateTime<Utc>>,
+        quote: AssetId,
+    },
+    /// Method and quote default to the config's tax rules
+    Tax {
+        year: i32,
+        method: Option<CostBasisMethod>,
+        quote: Option<AssetId>,
+        csv: Option<PathBuf>,
+    },
+    FetchPrices {
+        assets: Vec<AssetId>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        quote: AssetId,
+    },
+    Export {
+        kind: ExportKind,
+        quote: AssetId,
+        out: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    pub json: bool,
+    pub config: Option<PathBuf>,
+    pub command: Command,
+}
+
+impl Cli {
+    /// Parses arguments, without the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, CliError> {
+        let mut args = Args::new(args);
+        let json = args.switch("--json");
+        let config = args.flag("--config")?.map(PathBuf::from);
+        if args.switch("--help") || args.switch("-h") {
+            return Ok(Cli {
+                json,
+                config,
+                command: Command::Help,
+            });
+        }
+
+        let command = match args.positional().ok_or(CliError::MissingCommand)?.as_str() {
+            "help" => Command::Help,
+            "import" => Command::Import {
+                label: args
+                    .positional()
+                    .ok_or(CliError::MissingArgument("provider label"))?,
+                prune: args.switch("--prune"),
+            },
+            "sync" => match args.positional().as_deref() {
+                Some("status") => Command::SyncStatus,
+                Some(other) => return Err(CliError::UnknownCommand(format!("sync {other}"))),
+                None => Command::Sync,
+            },
+            "transfers" => match args.positional().as_deref() {
+                None => Command::Transfers,
+                Some(action) => {
+                    let kind = match action {
+                        "confirm" => DecisionKind::Confirmed,
+                        "reject" => DecisionKind::Rejected,
+                        other => {
+                            return Err(CliError::UnknownCommand(format!("transfers {other}")));
+                        }
+                    };
+                    Command::DecideTransfer {
+                        withdrawal_id: args
+                            .positional()
+                            .ok_or(CliError::MissingArgument("withdrawal id"))?,
+                        deposit_id: args
+                            .positional()
+                            .ok_or(CliError::MissingArgument("deposit id"))?,
+                        kind,
+                    }
+                }
+            },
+            "balances" => Command::Balances {
+                at: args.flag("--at")?.map(|d| parse_datetime(&d)).transpose()?,
+            },
+            "positions" => Command::Positions,
+            "networth" => Command::NetWorth {
+                at: args.flag("--at")?.map(|d| parse_datetime(&d)).transpose()?,
+                quote: args.quote()?,
+            },
+            "yield" => Command::Yield {
+                quote: args.quote()?,
+            },
+            "gains" => Command::Gains {
+                method: args.method()?.unwrap_or_default(),
+                at: args.flag("--at")?.map(|d| parse_datetime(&d)).transpose()?,
+                quote: args.quote()?,
+            },
+            "tax" => {
+                let year = args.positional().ok_or(CliError::MissingArgument("year"))?;
+                Command::Tax {
+                    year: year.parse().map_err(|_| CliError::InvalidYear(year))?,
+                    method: args.method()?,
+                    quote: args.flag("--quote")?.map(AssetId::from),
+                    csv: args.flag("--csv")?.map(PathBuf::from),
+                }
+            }
+            "prices" => match args.positional().as_deref() {
+                Some("fetch") => Command::FetchPrices {
+                    from: args.flag("--from")?.map(|d| parse_date(&d)).transpose()?,
+                    to: args.flag("--to")?.map(|d| parse_date(&d)).transpose()?,
+                    quote: args.quote()?,
+                    assets: std::iter::from_fn(|| args.positional())
+                        .map(AssetId::from)
+                        .collect(),
+                },
+                Some(other) => return Err(CliError::UnknownCommand(format!("prices {other}"))),
+                None => return Err(CliError::MissingArgument("prices subcommand")),
+            },
+            "export" => {
+                let kind = match args.positional().as_deref() {
+                    Some("balances") => ExportKind::Balances,
+                    Some("effects") => ExportKind::Effects,
+                    Some("prices") => ExportKind::Prices,
+                    Some(other) => return Err(CliError::UnexpectedArgument(other.to_string())),
+                    None => return Err(CliError::MissingArgument("export kind")),
+                };
+                Command::Export {
+                    kind,
+                    quote: args.quote()?,
+                    out: args.flag("--out")?.map(PathBuf::from),
+                }
+            }
+            other => return Err(CliError::UnknownCommand(other.to_string())),
+        };
+        args.finish()?;
+        Ok(Cli {
+            json,
+            config,
+            command,
+        })
+    }
+}
+
+/// Remaining arguments, consumed as the command is recognized
+struct Args(VecDeque<String>);
+
+impl Args {
+    fn new(args: impl IntoIterator<Item = String>) -> Self {
+        Args(args.into_iter().collect())
+    }
+
+    fn switch(&mut self, name: &str) -> bool {
+        let idx = self.0.iter().position(|arg| arg == name);
+        idx.and_then(|idx| self.0.remove(idx)).is_some()
+    }
+
+    fn flag(&mut self, name: &str) -> Result<Option<String>, CliError> {
+        let Some(idx) = self.0.iter().position(|arg| arg == name) else {
+            return Ok(None);
+        };
+        self.0.remove(idx);
+        match self.0.remove(idx) {
+            Some(value) if !value.starts_with("--") => Ok(Some(value)),
+            _ => Err(CliError::MissingValue(name.to_string())),
+        }
+    }
+
+    fn quote(&mut self) -> Result<AssetId, CliError> {
+        let quote = self.flag("--quote")?;
+        Ok(AssetId::from(quote.as_deref().unwrap_or(DEFAULT_QUOTE)))
+    }
+
+    fn method(&mut self) -> Result<Option<CostBasisMethod>, CliError> {
+        self.flag("--method")?
+            .map(|method| method.parse().map_err(|_| CliError::InvalidMethod(method)))
+            .transpose()
+    }
+
+    /// Next argument, unless it is a flag
+    fn positional(&mut self) -> Option<String> {
+        let is_flag = self.0.front()?.starts_with('-');
+        if is_flag { None } else { self.0.pop_front() }
+    }
+
+    fn finish(self) -> Result<(), CliError> {
+        match self.0.into_iter().next() {
+            Some(arg) => Err(CliError::UnexpectedArgument(arg)),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn parse_date(s: &str) -> Result<NaiveDate, CliError> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| CliError::InvalidDate(s.to_string()))
+}
+
+/// A date alone means the end of that day
+pub fn parse_datetime(s: &str) -> Result<DateTime<Utc>, CliError> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Ok(datetime.to_utc());
+    }
+    let date = parse_date(s)?;
+    let end_of_day =
+        date.and_time(NaiveTime::MIN) + TimeDelta::days(1) - TimeDelta::milliseconds(1);
+    Ok(end_of_day.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, CliError> {
+        Cli::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let cli = parse("networth --quote EUR --at 2024-04-19 --json").unwrap();
+        assert!(cli.json);
+        let Command::NetWorth { at, quote } = cli.command else {
+            panic!("expected networth, got {:?}", cli.command);
+        };
+        assert_eq!(quote, AssetId::from("EUR"));
+        assert_eq!(at.unwrap().to_rfc3339(), "2024-04-19T23:59:59.999+00:00");
+
+        let cli = parse("--config money.toml prices fetch ETH BTC --from 2024-01-01").unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("money.toml")));
+        assert_eq!(
+            cli.command,
+            Command::FetchPrices {
+                assets: vec![AssetId::from("ETH"), AssetId::from("BTC")],
+                from: NaiveDate::from_ymd_opt(2024, 1, 1),
+                to: None,
+                quote: AssetId::from("USD"),
+            }
+        );
+        assert_eq!(parse("--help").unwrap().command, Command::Help);
+        assert_eq!(parse("transfers").unwrap().command, Command::Transfers);
+        assert_eq!(
+            parse("import nexo --prune").unwrap().command,
+            Command::Import {
+                label: "nexo".to_string(),
+                prune: true,
+            }
+        );
+        assert_eq!(
+            parse("gains --method hifo").unwrap().command,
+            Command::Gains {
+                method: CostBasisMethod::Hifo,
+                at: None,
+                quote: AssetId::from("USD"),
+            }
+        );
+        assert_eq!(
+            parse("tax 2024 --csv out --quote EUR").unwrap().command,
+            Command::Tax {
+                year: 2024,
+                method: None,
+                quote: Some(AssetId::from("EUR")),
+                csv: Some(PathBuf::from("out")),
+            }
+        );
+        assert_eq!(
+            parse("transfers reject nexo:NXT1 binance:deposit:7")
+                .unwrap()
+                .command,
+            Command::DecideTransfer {
+                withdrawal_id: "nexo:NXT1".to_string(),
+                deposit_id: "binance:deposit:7".to_string(),
+                kind: DecisionKind::Rejected,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse(""), Err(CliError::MissingCommand));
+        assert_eq!(
+            parse("networth --at"),
+            Err(CliError::MissingValue("--at".to_string()))
+        );
+        assert_eq!(
+            parse("balances --at yesterday"),
+            Err(CliError::InvalidDate("yesterday".to_string()))
+        );
+        assert_eq!(
+            parse("sync --quote EUR"),
+            Err(CliError::UnexpectedArgument("--quote".to_string()))
+        );
+        assert_eq!(
+            parse("import"),
+            Err(CliError::MissingArgument("provider label"))
+        );
+        assert_eq!(
+            parse("gains --method newest"),
+            Err(CliError::InvalidMethod("newest".to_string()))
+        );
+        assert_eq!(parse("tax"), Err(CliError::MissingArgument("year")));
+        assert_eq!(
+            parse("tax last"),
+            Err(CliError::InvalidYear("last".to_string()))
+        );
+        assert_eq!(
+            parse("transfers confirm nexo:NXT1"),
+            Err(CliError::MissingArgument("deposit id"))
+        );
+    }
+}
@@ -0,0 +1,1179 @@
+//! Runs CLI commands against the local store and the providers, printing tables or JSON.
+
+use crate::adapters::coingecko::CoinGeckoSvc;
+use crate::cli::{Command, ExportKind, USAGE};
+use crate::config::Config;
+use crate::polars::{
+    BalanceFrameBuilder, EffectRow, PositionRow, TaxDisposalRow, TaxHoldingRow, TaxIncomeRow,
+    VecExt, account_label,
+};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use comfy_table::Table;
+use lib_core::traits::IsProvider;
+use lib_core::{
+    AllProducts, AssetId, AssetRegistry, BalanceReplay, CostBasis, CostBasisMethod, DecisionKind,
+    Disposal, HoldingTerm, PriceBook, Store, SyncCursor, TaxReport, Transaction, TransactionId,
+    TransferDecision, TransferMatch, TransferMatcher, UnrealizedGain, Valuation, YieldProjection,
+    merge_transfers,
+};
+use polars::prelude::{CsvWriter, DataFrame, SerWriter};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display};
+use std::hash::Hash;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Default start of `prices fetch` when the store has no transactions
+const DEFAULT_PRICE_HISTORY: TimeDelta = TimeDelta::days(30);
+
+pub struct App {
+    pub store: Store,
+    pub assets: Arc<AssetRegistry>,
+    pub config: Config,
+    pub json: bool,
+}
+
+impl App {
+    pub async fn run(&mut self, command: Command) -> anyhow::Result<()> {
+        match command {
+            Command::Help => println!("{USAGE}"),
+            Command::Import { label, prune } => {
+                let provider = self.config.provider(&label)?.build(&self.assets)?;
+                let report = self
+                    .import(provider.as_ref(), &SyncCursor::default(), prune)
+                    .await?;
+                self.print(&Rows(vec![report]))?;
+            }
+            Command::Sync => self.sync().await?,
+            Command::SyncStatus => self.sync_status()?,
+            Command::Transfers => self.transfers()?,
+            Command::DecideTransfer {
+                withdrawal_id,
+                deposit_id,
+                kind,
+            } => self.decide_transfer(withdrawal_id.into(), deposit_id.into(), kind)?,
+            Command::Balances { at } => self.balances(at)?,
+            Command::Positions => self.positions().await?,
+            Command::NetWorth { at, quote } => self.networth(at, &quote).await?,
+            Command::Yield { quote } => self.projected_yield(&quote).await?,
+            Command::Gains { method, at, quote } => self.gains(method, at, &quote).await?,
+            Command::Tax {
+                year,
+                method,
+                quote,
+                csv,
+            } => self.tax(year, method, quote, csv.as_deref())?,
+            Command::FetchPrices {
+                assets,
+                from,
+                to,
+                quote,
+            } => self.fetch_prices(assets, from, to, &quote).await?,
+            Command::Export { kind, quote, out } => {
+                let mut df = match kind {
+                    ExportKind::Balances => {
+                        let prices = self.stored_prices()?;
+                        BalanceFrameBuilder::new(&self.assets)
+                            .with_prices(&prices, quote)
+                            .build(&self.replay()?)?
+                    }
+                    ExportKind::Effects => {
+                        let transactions = self.store.get_transactions()?;
+                        let effects = transactions.iter().flat_map(|tx| tx.effects());
+                        let rows = effects
+                            .filter_map(|effect| match EffectRow::new(&self.assets, effect) {
+                                Ok(row) => Some(row),
+                                Err(error) => {
+                                    eprintln!("left out of the export: {error}");
+                                    None
+                                }
+                            })
+                            .collect::<Vec<_>>();
+                        rows.to_df()?
+                    }
+                    ExportKind::Prices => self.store.get_prices()?.to_df()?,
+                };
+                match out {
+                    Some(path) => CsvWriter::new(std::fs::File::create(path)?).finish(&mut df)?,
+                    None => CsvWriter::new(std::io::stdout()).finish(&mut df)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The enabled provider instances of the config
+    fn providers(&self) -> anyhow::Result<Vec<Box<dyn IsProvider>>> {
+        self.config
+            .enabled()
+            .map(|provider| provider.build(&self.assets))
+            .collect()
+    }
+
+    fn print(&self, report: &(impl Serialize + Display)) -> anyhow::Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(report)?);
+        } else {
+            println!("{report}");
+        }
+        Ok(())
+    }
+
+    /// Stored transactions, with matched withdrawals and deposits merged into transfers
+    fn transactions(&self) -> anyhow::Result<Vec<Transaction>> {
+        let transactions = self.store.get_transactions()?;
+        let decisions = self.store.get_transfer_decisions()?;
+        let matches = TransferMatcher::new().resolve(&transactions, &decisions);
+        Ok(merge_transfers(transactions, &matches)?)
+    }
+
+    fn replay(&self) -> anyhow::Result<BalanceReplay> {
+        let transactions = self.transactions()?;
+        let replay = BalanceReplay::replay(&transactions)?;
+        for negative in replay.negative_balances() {
+            eprintln!(
+                "negative balance on {} at {}, history may be incomplete",
+                account_label(&negative.account_id),
+                negative.datetime
+            );
+        }
+        Ok(replay)
+    }
+
+    /// Fetches a provider's transactions from `cursor` on, a default cursor fetching its
+    /// whole history, and stores the new ones. With `prune`, stored ones of the period
+    /// fetched that the provider doesn't report anymore are removed.
+    async fn import(
+        &mut self,
+        provider: &dyn IsProvider,
+        cursor: &SyncCursor,
+        prune: bool,
+    ) -> anyhow::Result<ImportLine> {
+        let provider_id = provider.provider_id();
+        let batch = provider.fetch_transactions_since(cursor).await?;
+        let outcome = self
+            .store
+            .save_sync_batch(&provider_id, &batch, Utc::now(), prune)?;
+        if outcome.quarantined > 0 {
+            eprintln!(
+                "quarantined {} {} transactions, see the quarantined_transactions table",
+                outcome.quarantined, provider_id.0
+            );
+        }
+        Ok(ImportLine {
+            provider: provider_id.0,
+            fetched: batch.transactions.len(),
+            saved: outcome.saved,
+            duplicates: outcome.duplicates,
+            removed: outcome.removed,
+            quarantined: outcome.quarantined,
+        })
+    }
+
+    /// Fetches what's new for every enabled provider, a failing provider doesn't stop
+    /// the others and keeps its cursor
+    async fn sync(&mut self) -> anyhow::Result<()> {
+        let mut reports = Vec::new();
+        let mut failed = 0;
+        let configs = self.config.enabled().cloned().collect::<Vec<_>>();
+        for config in configs {
+            let cursor = match self.store.get_sync_state(&config.label)? {
+                Some(state) => state.cursor,
+                None => SyncCursor::default(),
+            };
+            let report = match config.build(&self.assets) {
+                Ok(provider) => self.import(provider.as_ref(), &cursor, false).await,
+                Err(err) => Err(err),
+            };
+            match report {
+                Ok(report) => reports.push(report),
+                Err(err) => {
+                    eprintln!("failed to sync {}: {err:#}", config.label.0);
+                    failed += 1;
+                }
+            }
+        }
+        self.print(&Rows(reports))?;
+        if failed > 0 {
+            anyhow::bail!("{failed} provider(s) failed to sync");
+        }
+        Ok(())
+    }
+
+    fn sync_status(&self) -> anyhow::Result<()> {
+        let states = self.store.get_sync_states()?;
+        let lines = self
+            .config
+            .providers
+            .iter()
+            .map(|provider| {
+                let state = states.iter().find(|state| state.provider == provider.label);
+                SyncStatusLine {
+                    provider: provider.label.0.clone(),
+                    enabled: provider.enabled,
+                    last_synced: state.map(|state| state.last_synced),
+                    newest_transaction: state.and_then(|state| state.cursor.last_datetime),
+                }
+            })
+            .collect();
+        self.print(&Rows(lines))
+    }
+
+    fn transfers(&self) -> anyhow::Result<()> {
+        let transactions = self.store.get_transactions()?;
+        let decisions = self.store.get_transfer_decisions()?;
+        let lines = TransferMatcher::new()
+            .resolve(&transactions, &decisions)
+            .iter()
+            .filter_map(|m| match TransferLine::new(&self.assets, m) {
+                Ok(line) => Some(line),
+                Err(error) => {
+                    eprintln!("left out of the transfers: {error}");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        self.print(&Rows(lines))
+    }
+
+    /// Stores the user's call on a pair, which must be a withdrawal and a deposit
+    /// of the same asset
+    fn decide_transfer(
+        &mut self,
+        withdrawal_id: TransactionId,
+        deposit_id: TransactionId,
+        kind: DecisionKind,
+    ) -> anyhow::Result<()> {
+        let transactions = self.store.get_transactions()?;
+        let mut transfer =
+            TransferMatcher::new().pair(&transactions, &withdrawal_id, &deposit_id)?;
+        transfer.confirmed = kind == DecisionKind::Confirmed;
+        let decision = TransferDecision {
+            withdrawal_id,
+            deposit_id,
+            kind,
+        };
+        self.store.save_transfer_decision(&decision, Utc::now())?;
+        let mut line = TransferLine::new(&self.assets, &transfer)?;
+        line.status = kind.as_str().to_string();
+        self.print(&Rows(vec![line]))
+    }
+
+    fn balances(&self, at: Option<DateTime<Utc>>) -> anyhow::Result<()> {
+        let replay = self.replay()?;
+        let balances = match at {
+            Some(at) => replay.balances_at(at),
+            None => replay.current_balances(),
+        };
+        let mut lines = Vec::new();
+        for (account_id, amount) in &balances {
+            if account_id.is_counter_account() || amount.is_zero() {
+                continue;
+            }
+            let asset = match self.assets.get(&amount.asset_id) {
+                Ok(asset) => asset,
+                Err(error) => {
+                    eprintln!("left out of the balances: {error}");
+                    continue;
+                }
+            };
+            lines.push(BalanceLine {
+                account: account_label(account_id),
+                provider: account_id.provider.0.clone(),
+                asset: amount.asset_id.0.clone(),
+                balance: amount.to_f64(asset.decimals),
+            });
+        }
+        lines.sort_by(|a, b| a.account.cmp(&b.account));
+        self.print(&Rows(lines))
+    }
+
+    async fn positions(&self) -> anyhow::Result<()> {
+        let mut rows = Vec::new();
+        for provider in self.providers()? {
+            let provider_id = provider.provider_id();
+            for position in provider.fetch_positions().await? {
+                rows.push(PositionRow::new(&self.assets, &provider_id, &position)?);
+            }
+        }
+        self.print(&Rows(rows))
+    }
+
+    async fn networth(&mut self, at: Option<DateTime<Utc>>, quote: &AssetId) -> anyhow::Result<()> {
+        let replay = self.replay()?;
+        let datetime = at.unwrap_or_else(Utc::now);
+        let held = replay
+            .balances_at(datetime)
+            .into_iter()
+            .filter(|(account_id, amount)| !account_id.is_counter_account() && !amount.is_zero())
+            .map(|(_, amount)| amount.asset_id)
+            .collect::<BTreeSet<_>>();
+        let prices = match at {
+            Some(at) => {
+                self.historical_prices(&held, quote, at.date_naive())
+                    .await?
+            }
+            None => self.current_prices(quote).await?,
+        };
+
+        let valuation = Valuation::from_replay(&replay, &self.assets, &prices, quote, datetime);
+        for unpriced in &valuation.unpriced {
+            eprintln!("left out of the total: {}", unpriced.error);
+        }
+        self.print(&NetWorthReport {
+            quote: quote.0.clone(),
+            datetime,
+            total: valuation.total(),
+            by_asset: shares(valuation.by_asset(), |asset_id| asset_id.0.clone()),
+            by_provider: shares(valuation.by_provider(), |provider| provider.0.clone()),
+            by_product: shares(valuation.by_product(), |product_id| {
+                product_id.as_ref().map_or("-".to_string(), |p| p.0.clone())
+            }),
+        })
+    }
+
+    async fn projected_yield(&self, quote: &AssetId) -> anyhow::Result<()> {
+        let mut positions = Vec::new();
+        let mut products = AllProducts::new();
+        for provider in self.providers()? {
+            let provider_id = provider.provider_id();
+            for position in provider.fetch_positions().await? {
+                positions.push((provider_id.clone(), position));
+            }
+            for product in provider.fetch_products().await? {
+                products.insert(product);
+            }
+        }
+        let prices = self.current_prices(quote).await?;
+
+        let projection = YieldProjection::project(
+            positions
+                .iter()
+                .map(|(provider, position)| (provider, position)),
+            &products,
+            &self.assets,
+            &prices,
+            quote,
+            Utc::now(),
+        );
+        for position_id in &projection.unknown_products {
+            eprintln!("no product known for position {}", position_id.0);
+        }
+        for error in &projection.unpriced {
+            eprintln!("left out of the total: {error}");
+        }
+        let mut lines = projection
+            .positions
+            .iter()
+            .map(|p| YieldLine {
+                provider: p.provider.0.clone(),
+                position: p.position_id.0.clone(),
+                product: p.product_id.0.clone(),
+                asset: p.asset_id.0.clone(),
+                apy: p.apy,
+                epy: p.epy,
+                epy_value: p.epy_value,
+            })
+            .collect::<Vec<_>>();
+        lines.sort_by(|a, b| {
+            b.epy_value
+                .unwrap_or(0.0)
+                .total_cmp(&a.epy_value.unwrap_or(0.0))
+        });
+        self.print(&YieldReport {
+            quote: quote.0.clone(),
+            total: projection.total_value(),
+            positions: Rows(lines),
+        })
+    }
+
+    /// Cost basis of the transactions up to `at`, open lots valued at `at` or now
+    async fn gains(
+        &self,
+        method: CostBasisMethod,
+        at: Option<DateTime<Utc>>,
+        quote: &AssetId,
+    ) -> anyhow::Result<()> {
+        let datetime = at.unwrap_or_else(Utc::now);
+        let mut transactions = self.transactions()?;
+        transactions.retain(|tx| tx.datetime <= datetime);
+        let prices = match at {
+            Some(_) => self.stored_prices()?,
+            None => self.current_prices(quote).await?,
+        };
+
+        let basis = CostBasis::compute(&transactions, method, &self.assets, &prices, quote)?;
+        for error in &basis.unpriced {
+            eprintln!("valued at 0: {error}");
+        }
+        for disposal in basis.disposals.iter().filter(|d| !d.uncovered.is_zero()) {
+            eprintln!(
+                "{} disposes of more {} than held, history may be incomplete",
+                disposal.tx_id.0, disposal.amount.asset_id.0
+            );
+        }
+        let unrealized = basis.unrealized(&self.assets, &prices, datetime);
+        for error in unrealized.iter().filter_map(|gain| gain.unpriced.as_ref()) {
+            eprintln!("open lot not valued: {error}");
+        }
+        self.print(&GainsReport {
+            method: method.as_str().to_string(),
+            quote: quote.0.clone(),
+            datetime,
+            realized: basis.realized_gain(),
+            unrealized: unrealized
+                .iter()
+                .filter_map(UnrealizedGain::gain)
+                .fold(0.0, |total, gain| total + gain),
+            // lines in unregistered assets can't be shown, they were reported above
+            disposals: Rows(
+                basis
+                    .disposals
+                    .iter()
+                    .filter_map(|disposal| DisposalLine::new(&self.assets, disposal).ok())
+                    .collect(),
+            ),
+            open_lots: Rows(
+                unrealized
+                    .iter()
+                    .filter_map(|gain| OpenLotLine::new(&self.assets, gain).ok())
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Fiscal year `year` under the config's tax rules, valued with stored prices
+    fn tax(
+        &self,
+        year: i32,
+        method: Option<CostBasisMethod>,
+        quote: Option<AssetId>,
+        csv: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        let mut rules = self.config.tax.rules()?;
+        if let Some(method) = method {
+            rules.method = method;
+        }
+        if let Some(quote) = quote {
+            rules.vs_asset_id = quote;
+        }
+        let prices = self.stored_prices()?;
+        let report =
+            TaxReport::generate(&self.transactions()?, &self.assets, &prices, &rules, year)?;
+        for error in &report.unpriced {
+            eprintln!("valued at 0: {error}");
+        }
+
+        let disposals = report
+            .disposals
+            .iter()
+            .map(|disposal| TaxDisposalRow::new(&self.assets, disposal))
+            .collect::<Vec<_>>();
+        let income = report
+            .income
+            .iter()
+            .map(|income| TaxIncomeRow::new(&self.assets, income))
+            .collect::<Vec<_>>();
+        let holdings = report
+            .holdings
+            .iter()
+            .map(|holding| TaxHoldingRow::new(&self.assets, holding))
+            .collect::<Vec<_>>();
+        if let Some(dir) = csv {
+            std::fs::create_dir_all(dir)?;
+            write_csv(&dir.join("disposals.csv"), disposals.to_df()?)?;
+            write_csv(&dir.join("income.csv"), income.to_df()?)?;
+            write_csv(&dir.join("holdings.csv"), holdings.to_df()?)?;
+            eprintln!(
+                "wrote disposals.csv, income.csv and holdings.csv to {}",
+                dir.display()
+            );
+        }
+
+        self.print(&TaxReportView {
+            year: report.year,
+            start: report.start,
+            end: report.end,
+            quote: rules.vs_asset_id.0.clone(),
+            method: rules.method.as_str().to_string(),
+            proceeds: report.total_proceeds(),
+            cost: report.total_cost(),
+            short_term_gain: report.gain(HoldingTerm::Short),
+            long_term_gain: report.gain(HoldingTerm::Long),
+            exempt_gain: report.exempt_gain(),
+            taxable_gain: report.taxable_gain(),
+            income: report.total_income(),
+            disposals: Rows(disposals),
+            income_events: Rows(income),
+            holdings: Rows(holdings),
+        })
+    }
+
+    async fn fetch_prices(
+        &mut self,
+        assets: Vec<AssetId>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        quote: &AssetId,
+    ) -> anyhow::Result<()> {
+        let today = Utc::now().date_naive();
+        let from = match from {
+            Some(from) => from,
+            None => match self.store.get_transactions()?.first() {
+                Some(tx) => tx.datetime.date_naive(),
+                None => today - DEFAULT_PRICE_HISTORY,
+            },
+        };
+        let to = to.unwrap_or(today);
+        let assets = match assets.is_empty() {
+            true => self.coingecko_assets(quote),
+            false => assets,
+        };
+
+        let coingecko = CoinGeckoSvc::new(self.assets.clone())?;
+        let mut lines = Vec::new();
+        let mut failed = 0;
+        for asset_id in &assets {
+            match coingecko
+                .fetch_price_history(&mut self.store, asset_id, quote, from, to)
+                .await
+            {
+                Ok(history) => lines.push(PriceHistoryLine {
+                    asset: asset_id.0.clone(),
+                    quote: quote.0.clone(),
+                    points: history.points.len(),
+                    first: history.points.first().map(|p| p.datetime),
+                    last: history.points.last().map(|p| p.datetime),
+                }),
+                Err(err) => {
+                    eprintln!("failed to fetch {} prices: {err:#}", asset_id.0);
+                    failed += 1;
+                }
+            }
+        }
+        self.print(&Rows(lines))?;
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} price histories failed", assets.len());
+        }
+        Ok(())
+    }
+
+    /// Registry assets CoinGecko prices, other than fiat currencies and the quote itself
+    fn coingecko_assets(&self, quote: &AssetId) -> Vec<AssetId> {
+        let coingecko = lib_core::ProviderId::from(CoinGeckoSvc::service_id());
+        let mut assets = self
+            .assets
+            .assets()
+            .filter(|asset| asset.chain_id != "fiat" && &asset.id != quote)
+            .filter(|asset| self.assets.external_id(&asset.id, &coingecko).is_some())
+            .map(|asset| asset.id.clone())
+            .collect::<Vec<_>>();
+        assets.sort();
+        assets
+    }
+
+    fn stored_prices(&self) -> anyhow::Result<PriceBook> {
+        let mut prices = PriceBook::new();
+        self.store
+            .get_prices()?
+            .into_iter()
+            .for_each(|p| prices.insert(p));
+        Ok(prices)
+    }
+
+    /// Stored prices, topped up with current ones when CoinGecko is reachable
+    async fn current_prices(&self, quote: &AssetId) -> anyhow::Result<PriceBook> {
+        let mut prices = self.stored_prices()?;
+        let coingecko = CoinGeckoSvc::new(self.assets.clone())?;
+        match coingecko
+            .fetch_current_prices(std::slice::from_ref(quote))
+            .await
+        {
+            Ok(points) => points.into_iter().for_each(|p| prices.insert(p)),
+            Err(err) => eprintln!("using stored prices only, current prices failed: {err:#}"),
+        }
+        Ok(prices)
+    }
+
+    /// Stored prices, after fetching the days around `day` for each of `assets`
+    async fn historical_prices(
+        &mut self,
+        assets: &BTreeSet<AssetId>,
+        quote: &AssetId,
+        day: NaiveDate,
+    ) -> anyhow::Result<PriceBook> {
+        let coingecko = CoinGeckoSvc::new(self.assets.clone())?;
+        for asset_id in assets.iter().filter(|asset_id| *asset_id != quote) {
+            let fetched = coingecko
+                .fetch_price_history(
+                    &mut self.store,
+                    asset_id,
+                    quote,
+                    day.pred_opt().unwrap_or(day),
+                    day,
+                )
+                .await;
+            if let Err(err) = fetched {
+                eprintln!("failed to fetch {} prices: {err:#}", asset_id.0);
+            }
+        }
+        self.stored_prices()
+    }
+}
+
+/// A line of a printed table
+trait TableRow: Serialize {
+    const HEADERS: &'static [&'static str];
+    fn cells(&self) -> Vec<String>;
+}
+
+#[derive(Serialize)]
+#[serde(transparent)]
+struct Rows<T>(Vec<T>);
+
+impl<T: TableRow> Display for Rows<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut table = Table::new();
+        table.load_preset(comfy_table::presets::UTF8_FULL_CONDENSED);
+        table.set_header(T::HEADERS);
+        for row in &self.0 {
+            table.add_row(row.cells());
+        }
+        write!(f, "{table}")
+    }
+}
+
+fn write_csv(path: &Path, mut df: DataFrame) -> anyhow::Result<()> {
+    CsvWriter::new(std::fs::File::create(path)?).finish(&mut df)?;
+    Ok(())
+}
+
+fn opt_cell<T: Display>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |v| v.to_string())
+}
+
+#[derive(Serialize)]
+struct ImportLine {
+    provider: String,
+    fetched: usize,
+    saved: usize,
+    /// Fetched transactions that were already stored
+    duplicates: usize,
+    /// Stored transactions the provider doesn't report anymore
+    removed: usize,
+    quarantined: usize,
+}
+impl TableRow for ImportLine {
+    const HEADERS: &'static [&'static str] = &[
+        "provider",
+        "fetched",
+        "saved",
+        "duplicates",
+        "removed",
+        "quarantined",
+    ];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.provider.clone(),
+            self.fetched.to_string(),
+            self.saved.to_string(),
+            self.duplicates.to_string(),
+            self.removed.to_string(),
+            self.quarantined.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct SyncStatusLine {
+    provider: String,
+    enabled: bool,
+    last_synced: Option<DateTime<Utc>>,
+    newest_transaction: Option<DateTime<Utc>>,
+}
+impl TableRow for SyncStatusLine {
+    const HEADERS: &'static [&'static str] =
+        &["provider", "enabled", "last synced", "newest transaction"];
+    fn cells(&self) -> Vec<String> {
+        let or_never = |datetime: Option<DateTime<Utc>>| {
+            datetime.map_or("never".to_string(), |datetime| {
+                datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+            })
+        };
+        vec![
+            self.provider.clone(),
+            self.enabled.to_string(),
+            or_never(self.last_synced),
+            or_never(self.newest_transaction),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct TransferLine {
+    /// suggested, confirmed or rejected
+    status: String,
+    withdrawal: String,
+    deposit: String,
+    from: String,
+    to: String,
+    asset: String,
+    sent: f64,
+    received: f64,
+    sent_at: DateTime<Utc>,
+    received_at: DateTime<Utc>,
+    by_hash: bool,
+}
+impl TransferLine {
+    fn new(assets: &AssetRegistry, transfer: &TransferMatch) -> anyhow::Result<Self> {
+        let decimals = assets.get(&transfer.sent.asset_id)?.decimals;
+        Ok(TransferLine {
+            status: match transfer.confirmed {
+                true => "confirmed".to_string(),
+                false => "suggested".to_string(),
+            },
+            withdrawal: transfer.withdrawal_id.0.clone(),
+            deposit: transfer.deposit_id.0.clone(),
+            from: account_label(&transfer.from),
+            to: account_label(&transfer.to),
+            asset: transfer.sent.asset_id.0.clone(),
+            sent: transfer.sent.to_f64(decimals),
+            received: transfer.received.to_f64(decimals),
+            sent_at: transfer.sent_at,
+            received_at: transfer.received_at,
+            by_hash: transfer.by_hash,
+        })
+    }
+}
+impl TableRow for TransferLine {
+    const HEADERS: &'static [&'static str] = &[
+        "status",
+        "withdrawal",
+        "deposit",
+        "from",
+        "to",
+        "asset",
+        "sent",
+        "received",
+        "sent at",
+        "received after",
+        "by hash",
+    ];
+    fn cells(&self) -> Vec<String> {
+        let delay = self.received_at - self.sent_at;
+        vec![
+            self.status.clone(),
+            self.withdrawal.clone(),
+            self.deposit.clone(),
+            self.from.clone(),
+            self.to.clone(),
+            self.asset.clone(),
+            self.sent.to_string(),
+            self.received.to_string(),
+            self.sent_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            format!("{}m", delay.num_minutes()),
+            self.by_hash.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct BalanceLine {
+    account: String,
+    provider: String,
+    asset: String,
+    balance: f64,
+}
+impl TableRow for BalanceLine {
+    const HEADERS: &'static [&'static str] = &["account", "provider", "asset", "balance"];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.account.clone(),
+            self.provider.clone(),
+            self.asset.clone(),
+            self.balance.to_string(),
+        ]
+    }
+}
+
+impl TableRow for PositionRow {
+    const HEADERS: &'static [&'static str] = &[
+        "provider",
+        "position",
+        "product",
+        "asset",
+        "amount",
+        "start",
+        "end",
+        "auto-renew",
+    ];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.provider.clone(),
+            self.position_id.clone(),
+            self.product_id.clone(),
+            self.asset.clone(),
+            self.amount.to_string(),
+            self.start_date.date_naive().to_string(),
+            opt_cell(self.end_date.map(|d| d.date_naive())),
+            self.auto_renew.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct PriceHistoryLine {
+    asset: String,
+    quote: String,
+    points: usize,
+    first: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+}
+impl TableRow for PriceHistoryLine {
+    const HEADERS: &'static [&'static str] = &["asset", "quote", "points", "first", "last"];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.asset.clone(),
+            self.quote.clone(),
+            self.points.to_string(),
+            opt_cell(self.first),
+            opt_cell(self.last),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct Share {
+    name: String,
+    value: f64,
+}
+impl TableRow for Share {
+    const HEADERS: &'static [&'static str] = &["name", "value"];
+    fn cells(&self) -> Vec<String> {
+        vec![self.name.clone(), format!("{:.2}", self.value)]
+    }
+}
+
+/// Values per group, largest first
+fn shares<K: Eq + Hash>(values: HashMap<K, f64>, name: impl Fn(&K) -> String) -> Rows<Share> {
+    let mut shares = values
+        .iter()
+        .map(|(key, value)| Share {
+            name: name(key),
+            value: *value,
+        })
+        .collect::<Vec<_>>();
+    shares.sort_by(|a, b| b.value.total_cmp(&a.value));
+    Rows(shares)
+}
+
+#[derive(Serialize)]
+struct NetWorthReport {
+    quote: String,
+    datetime: DateTime<Utc>,
+    total: f64,
+    by_asset: Rows<Share>,
+    by_provider: Rows<Share>,
+    by_product: Rows<Share>,
+}
+impl Display for NetWorthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "By asset:\n{}", self.by_asset)?;
+        writeln!(f, "By provider:\n{}", self.by_provider)?;
+        writeln!(f, "By product:\n{}", self.by_product)?;
+        write!(
+            f,
+            "Net worth at {}: {:.2} {}",
+            self.datetime, self.total, self.quote
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct DisposalLine {
+    datetime: DateTime<Utc>,
+    transaction: String,
+    kind: String,
+    asset: String,
+    amount: f64,
+    proceeds: f64,
+    cost: f64,
+    gain: f64,
+}
+impl DisposalLine {
+    fn new(assets: &AssetRegistry, disposal: &Disposal) -> anyhow::Result<Self> {
+        let decimals = assets.get(&disposal.amount.asset_id)?.decimals;
+        Ok(DisposalLine {
+            datetime: disposal.datetime,
+            transaction: disposal.tx_id.0.clone(),
+            kind: disposal.kind.as_str().to_string(),
+            asset: disposal.amount.asset_id.0.clone(),
+            amount: disposal.amount.to_f64(decimals),
+            proceeds: disposal.proceeds,
+            cost: disposal.cost,
+            gain: disposal.gain(),
+        })
+    }
+}
+impl TableRow for DisposalLine {
+    const HEADERS: &'static [&'static str] = &[
+        "date",
+        "transaction",
+        "kind",
+        "asset",
+        "amount",
+        "proceeds",
+        "cost",
+        "gain",
+    ];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+            self.transaction.clone(),
+            self.kind.clone(),
+            self.asset.clone(),
+            self.amount.to_string(),
+            format!("{:.2}", self.proceeds),
+            format!("{:.2}", self.cost),
+            format!("{:.2}", self.gain),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct OpenLotLine {
+    acquired_at: DateTime<Utc>,
+    transaction: String,
+    kind: String,
+    asset: String,
+    amount: f64,
+    cost: f64,
+    value: Option<f64>,
+    gain: Option<f64>,
+}
+impl OpenLotLine {
+    fn new(assets: &AssetRegistry, unrealized: &UnrealizedGain) -> anyhow::Result<Self> {
+        let lot = &unrealized.lot;
+        let decimals = assets.get(&lot.amount.asset_id)?.decimals;
+        Ok(OpenLotLine {
+            acquired_at: lot.acquired_at,
+            transaction: lot.tx_id.0.clone(),
+            kind: lot.kind.as_str().to_string(),
+            asset: lot.amount.asset_id.0.clone(),
+            amount: lot.amount.to_f64(decimals),
+            cost: lot.cost,
+            value: unrealized.value,
+            gain: unrealized.gain(),
+        })
+    }
+}
+impl TableRow for OpenLotLine {
+    const HEADERS: &'static [&'static str] = &[
+        "acquired",
+        "transaction",
+        "kind",
+        "asset",
+        "amount",
+        "cost",
+        "value",
+        "gain",
+    ];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.acquired_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            self.transaction.clone(),
+            self.kind.clone(),
+            self.asset.clone(),
+            self.amount.to_string(),
+            format!("{:.2}", self.cost),
+            opt_cell(self.value.map(|v| format!("{v:.2}"))),
+            opt_cell(self.gain.map(|v| format!("{v:.2}"))),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct GainsReport {
+    method: String,
+    quote: String,
+    datetime: DateTime<Utc>,
+    realized: f64,
+    /// Of the open lots that could be priced
+    unrealized: f64,
+    disposals: Rows<DisposalLine>,
+    open_lots: Rows<OpenLotLine>,
+}
+impl Display for GainsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Disposals:\n{}", self.disposals)?;
+        writeln!(f, "Open lots:\n{}", self.open_lots)?;
+        write!(
+            f,
+            "Realized: {:.2} {quote}, unrealized at {}: {:.2} {quote} ({})",
+            self.realized,
+            self.datetime,
+            self.unrealized,
+            self.method,
+            quote = self.quote
+        )
+    }
+}
+
+impl TableRow for TaxDisposalRow {
+    const HEADERS: &'static [&'static str] = &[
+        "sold",
+        "transaction",
+        "kind",
+        "asset",
+        "amount",
+        "acquired",
+        "held",
+        "term",
+        "exempt",
+        "taxable",
+        "proceeds",
+        "cost",
+        "gain",
+    ];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.disposed_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            self.transaction.clone(),
+            self.kind.clone(),
+            self.asset.clone(),
+            opt_cell(self.amount),
+            opt_cell(self.acquired_at.map(|d| d.format("%Y-%m-%d %H:%M:%S"))),
+            opt_cell(self.holding_days.map(|days| format!("{days}d"))),
+            self.term.clone(),
+            self.exempt.to_string(),
+            self.taxable.to_string(),
+            format!("{:.2}", self.proceeds),
+            format!("{:.2}", self.cost),
+            format!("{:.2}", self.gain),
+        ]
+    }
+}
+
+impl TableRow for TaxIncomeRow {
+    const HEADERS: &'static [&'static str] = &["date", "transaction", "asset", "amount", "value"];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+            self.transaction.clone(),
+            self.asset.clone(),
+            opt_cell(self.amount),
+            format!("{:.2}", self.value),
+        ]
+    }
+}
+
+impl TableRow for TaxHoldingRow {
+    const HEADERS: &'static [&'static str] = &["asset", "amount", "cost", "value"];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.asset.clone(),
+            opt_cell(self.amount),
+            format!("{:.2}", self.cost),
+            opt_cell(self.value.map(|v| format!("{v:.2}"))),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct TaxReportView {
+    year: i32,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    quote: String,
+    method: String,
+    proceeds: f64,
+    cost: f64,
+    /// Exempt gains included
+    short_term_gain: f64,
+    long_term_gain: f64,
+    exempt_gain: f64,
+    taxable_gain: f64,
+    income: f64,
+    disposals: Rows<TaxDisposalRow>,
+    income_events: Rows<TaxIncomeRow>,
+    holdings: Rows<TaxHoldingRow>,
+}
+impl Display for TaxReportView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quote = &self.quote;
+        writeln!(f, "Disposals:\n{}", self.disposals)?;
+        writeln!(f, "Income:\n{}", self.income_events)?;
+        writeln!(f, "Holdings at {}:\n{}", self.end, self.holdings)?;
+        writeln!(
+            f,
+            "Fiscal year {} ({} to {}, {})",
+            self.year,
+            self.start.date_naive(),
+            self.end.date_naive(),
+            self.method
+        )?;
+        writeln!(
+            f,
+            "Proceeds: {:.2} {quote}, cost: {:.2} {quote}",
+            self.proceeds, self.cost
+        )?;
+        writeln!(
+            f,
+            "Short term gain: {:.2} {quote}, long term gain: {:.2} {quote}",
+            self.short_term_gain, self.long_term_gain
+        )?;
+        writeln!(
+            f,
+            "Exempt gain: {:.2} {quote}, taxable gain: {:.2} {quote}",
+            self.exempt_gain, self.taxable_gain
+        )?;
+        write!(f, "Income: {:.2} {quote}", self.income)
+    }
+}
+
+#[derive(Serialize)]
+struct YieldLine {
+    provider: String,
+    position: String,
+    product: String,
+    asset: String,
+    apy: f64,
+    epy: f64,
+    epy_value: Option<f64>,
+}
+impl TableRow for YieldLine {
+    const HEADERS: &'static [&'static str] = &[
+        "provider",
+        "position",
+        "product",
+        "asset",
+        "apy",
+        "epy",
+        "epy value",
+    ];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.provider.clone(),
+            self.position.clone(),
+            self.product.clone(),
+            self.asset.clone(),
+            format!("{:.2}%", self.apy * 100.0),
+            self.epy.to_string(),
+            opt_cell(self.epy_value.map(|v| format!("{v:.2}"))),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct YieldReport {
+    quote: String,
+    total: f64,
+    positions: Rows<YieldLine>,
+}
+impl Display for YieldReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.positions)?;
+        write!(f, "Earn per year: {:.2} {}", self.total, self.quote)
+    }
+}
@@ -0,0 +1,401 @@
+//! Provider instances, credentials references and paths, read from a TOML file.
+//! See `money-tracker.example.toml` for the format.
+
+use crate::adapters::binance::{BinanceSvc, TradePair};
+use crate::adapters::nexo::NexoSvc;
+use binance_client::BinanceClient;
+use chrono::{Datelike, NaiveDate, NaiveTime, TimeDelta};
+use lib_core::traits::IsProvider;
+use lib_core::{AssetId, AssetRegistry, CostBasisMethod, ProviderId, TaxRules};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Read when present and no other config file is given
+pub const DEFAULT_CONFIG_PATH: &str = "money-tracker.toml";
+const DEFAULT_DB_PATH: &str = ".cache/money-tracker.duckdb";
+const DEFAULT_NEXO_CSV_PATH: &str = ".cache/nexo_transactions.csv";
+const DEFAULT_BINANCE_API_KEY_ENV: &str = "BINANCE_API_KEY";
+const DEFAULT_BINANCE_SECRET_KEY_ENV: &str = "BINANCE_SECRET_KEY";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config: {0}")]
+    Parse(String),
+    #[error("invalid provider label {0:?}, expected a non-empty name without ':'")]
+    InvalidLabel(String),
+    #[error("duplicate provider label {0:?}")]
+    DuplicateLabel(String),
+    #[error("unknown provider {label:?}, configured: {configured}")]
+    UnknownProvider { label: String, configured: String },
+    #[error("{env} is not set, provider {label:?} needs it")]
+    MissingCredential { label: String, env: String },
+    #[error("invalid tax rules: {0}")]
+    InvalidTax(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Config {
+    #[serde(default = "default_database")]
+    pub database: PathBuf,
+    /// Asset registry data file, the builtin registry when absent
+    pub assets: Option<PathBuf>,
+    #[serde(default)]
+    pub providers: Vec<ProviderConfig>,
+    #[serde(default)]
+    pub tax: TaxConfig,
+}
+
+/// A provider instance, several instances of the same kind can be configured
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProviderConfig {
+    /// Unique name, used as the provider of the instance's accounts
+    pub label: ProviderId,
+    /// Disabled instances are skipped by `sync` but can still be imported by label
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub kind: ProviderKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ProviderKind {
+    Nexo {
+        /// A CSV export, or a directory of exports
+        csv_path: PathBuf,
+    },
+    Binance {
+        /// Environment variables holding the API credentials, secrets stay out of the file
+        #[serde(default = "default_binance_api_key_env")]
+        api_key_env: String,
+        #[serde(default = "default_binance_secret_key_env")]
+        secret_key_env: String,
+        /// Spot markets to import trades from, e.g. "ETH/USDT"
+        #[serde(default)]
+        trade_pairs: Vec<String>,
+        /// Oldest history to import, the Binance launch by default
+        history_start: Option<NaiveDate>,
+    },
+}
+
+/// Jurisdiction rules of the `tax` report
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TaxConfig {
+    /// Asset proceeds, costs and income are valued in
+    pub quote: AssetId,
+    /// Lot selection method: fifo, lifo, hifo or average
+    pub method: String,
+    /// First day of the fiscal year, as "MM-DD"
+    pub fiscal_year_start: String,
+    /// Lots held longer than this many days are long term
+    pub long_term_days: i64,
+    /// Gains on lots held longer than this many days are exempt
+    pub exempt_after_days: Option<i64>,
+    /// Whether withdrawals are taxed as sales at market value
+    pub taxable_withdrawals: bool,
+}
+
+impl Default for TaxConfig {
+    fn default() -> Self {
+        TaxConfig {
+            quote: AssetId::from("USD"),
+            method: CostBasisMethod::default().as_str().to_string(),
+            fiscal_year_start: "01-01".to_string(),
+            long_term_days: 365,
+            exempt_after_days: None,
+            taxable_withdrawals: false,
+        }
+    }
+}
+
+impl TaxConfig {
+    pub fn rules(&self) -> Result<TaxRules, ConfigError> {
+        let method = CostBasisMethod::from_str(&self.method)
+            .map_err(|e| ConfigError::InvalidTax(e.to_string()))?;
+        // Any non-leap year, a fiscal year can't start on Feb 29
+        let year_start =
+            NaiveDate::parse_from_str(&format!("2001-{}", self.fiscal_year_start), "%Y-%m-%d")
+                .map(|date| (date.month(), date.day()))
+                .map_err(|_| {
+                    ConfigError::InvalidTax(format!(
+                        "fiscal_year_start {:?}, expected MM-DD",
+                        self.fiscal_year_start
+                    ))
+                })?;
+        if self.long_term_days < 0 || self.exempt_after_days.is_some_and(|days| days < 0) {
+            return Err(ConfigError::InvalidTax(
+                "holding periods can't be negative".to_string(),
+            ));
+        }
+        Ok(TaxRules {
+            vs_asset_id: self.quote.clone(),
+            method,
+            year_start,
+            long_term_after: TimeDelta::days(self.long_term_days),
+            exempt_after: self.exempt_after_days.map(TimeDelta::days),
+            taxable_withdrawals: self.taxable_withdrawals,
+        })
+    }
+}
+
+fn default_database() -> PathBuf {
+    PathBuf::from(DEFAULT_DB_PATH)
+}
+fn enabled_by_default() -> bool {
+    true
+}
+fn default_binance_api_key_env() -> String {
+    DEFAULT_BINANCE_API_KEY_ENV.to_string()
+}
+fn default_binance_secret_key_env() -> String {
+    DEFAULT_BINANCE_SECRET_KEY_ENV.to_string()
+}
+
+impl Config {
+    /// Reads a config file, relative paths in it are relative to the file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let config = Config::from_toml(&content)?;
+        Ok(match path.parent() {
+            Some(dir) => config.relative_to(dir),
+            None => config,
+        })
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        let config: Config =
+            toml::from_str(content).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        let mut labels = HashSet::new();
+        for provider in &config.providers {
+            let label = &provider.label.0;
+            if label.is_empty() || label.contains(':') {
+                return Err(ConfigError::InvalidLabel(label.clone()));
+            }
+            if !labels.insert(label) {
+                return Err(ConfigError::DuplicateLabel(label.clone()));
+            }
+        }
+        config.tax.rules()?;
+        Ok(config)
+    }
+
+    /// Used without a config file: Nexo at the default export path, and Binance when
+    /// `BINANCE_API_KEY` is set
+    pub fn fallback() -> Self {
+        let mut providers = vec![ProviderConfig {
+            label: ProviderId::from("nexo"),
+            enabled: true,
+            kind: ProviderKind::Nexo {
+                csv_path: PathBuf::from(DEFAULT_NEXO_CSV_PATH),
+            },
+        }];
+        if std::env::var(DEFAULT_BINANCE_API_KEY_ENV).is_ok() {
+            providers.push(ProviderConfig {
+                label: ProviderId::from("binance"),
+                enabled: true,
+                kind: ProviderKind::Binance {
+                    api_key_env: default_binance_api_key_env(),
+                    secret_key_env: default_binance_secret_key_env(),
+                    trade_pairs: Vec::new(),
+                    history_start: None,
+                },
+            });
+        }
+        Config {
+            database: default_database(),
+            assets: None,
+            providers,
+            tax: TaxConfig::default(),
+        }
+    }
+
+    fn relative_to(mut self, dir: &Path) -> Self {
+        self.database = dir.join(&self.database);
+        self.assets = self.assets.map(|assets| dir.join(assets));
+        for provider in &mut self.providers {
+            if let ProviderKind::Nexo { csv_path } = &mut provider.kind {
+                *csv_path = dir.join(&csv_path);
+            }
+        }
+        self
+    }
+
+    pub fn enabled(&self) -> impl Iterator<Item = &ProviderConfig> {
+        self.providers.iter().filter(|provider| provider.enabled)
+    }
+
+    pub fn provider(&self, label: &str) -> Result<&ProviderConfig, ConfigError> {
+        self.providers
+            .iter()
+            .find(|provider| provider.label.0 == label)
+            .ok_or_else(|| ConfigError::UnknownProvider {
+                label: label.to_string(),
+                configured: self
+                    .providers
+                    .iter()
+                    .map(|p| p.label.0.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })
+    }
+}
+
+impl ProviderConfig {
+    pub fn build(&self, assets: &Arc<AssetRegistry>) -> anyhow::Result<Box<dyn IsProvider>> {
+        let provider: Box<dyn IsProvider> = match &self.kind {
+            ProviderKind::Nexo { csv_path } => {
+                Box::new(NexoSvc::new(self.label.clone(), csv_path, assets.clone())?)
+            }
+            ProviderKind::Binance {
+                api_key_env,
+                secret_key_env,
+                trade_pairs,
+                history_start,
+            } => {
+                let client = BinanceClient::with_credentials(
+                    self.env(api_key_env)?,
+                    self.env(secret_key_env)?,
+                );
+                let mut svc = BinanceSvc::new(self.label.clone(), client, assets.clone());
+                svc.trade_pairs = trade_pairs
+                    .iter()
+                    .map(|pair| TradePair::from_str(pair))
+                    .collect::<anyhow::Result<_>>()?;
+                if let Some(start) = history_start {
+                    svc.history_start = start.and_time(NaiveTime::MIN).and_utc();
+                }
+                Box::new(svc)
+            }
+        };
+        Ok(provider)
+    }
+
+    fn env(&self, name: &str) -> Result<String, ConfigError> {
+        std::env::var(name).map_err(|_| ConfigError::MissingCredential {
+            label: self.label.0.clone(),
+            env: name.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() -> anyhow::Result<()> {
+        let config = Config::from_toml(
+            r#"
+            [[providers]]
+            label = "nexo"
+            kind = "nexo"
+            csv_path = "exports/nexo"
+
+            [[providers]]
+            label = "binance-main"
+            kind = "binance"
+            trade_pairs = ["ETH/USDT"]
+
+            [[providers]]
+            label = "binance-sub"
+            kind = "binance"
+            enabled = false
+            api_key_env = "BINANCE_SUB_API_KEY"
+            secret_key_env = "BINANCE_SUB_SECRET_KEY"
+            history_start = "2021-01-01"
+
+            [tax]
+            quote = "EUR"
+            fiscal_year_start = "04-06"
+            exempt_after_days = 365
+            taxable_withdrawals = true
+            "#,
+        )?;
+        assert_eq!(config.database, PathBuf::from(DEFAULT_DB_PATH));
+        let rules = config.tax.rules()?;
+        assert_eq!(rules.vs_asset_id, AssetId::from("EUR"));
+        assert_eq!(rules.method, CostBasisMethod::Fifo);
+        assert_eq!(rules.year_start, (4, 6));
+        assert_eq!(rules.exempt_after, Some(TimeDelta::days(365)));
+        assert!(rules.taxable_withdrawals);
+        let labels = config
+            .enabled()
+            .map(|p| p.label.0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["nexo", "binance-main"]);
+        assert_eq!(
+            config.provider("binance-main")?.kind,
+            ProviderKind::Binance {
+                api_key_env: "BINANCE_API_KEY".to_string(),
+                secret_key_env: "BINANCE_SECRET_KEY".to_string(),
+                trade_pairs: vec!["ETH/USDT".to_string()],
+                history_start: None,
+            }
+        );
+
+        let config = config.relative_to(Path::new("/home/me/money"));
+        assert_eq!(
+            config.provider("nexo")?.kind,
+            ProviderKind::Nexo {
+                csv_path: PathBuf::from("/home/me/money/exports/nexo")
+            }
+        );
+        assert!(matches!(
+            config.provider("aax"),
+            Err(ConfigError::UnknownProvider { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let duplicate = r#"
+            [[providers]]
+            label = "nexo"
+            kind = "nexo"
+            csv_path = "a.csv"
+
+            [[providers]]
+            label = "nexo"
+            kind = "nexo"
+            csv_path = "b.csv"
+        "#;
+        assert!(matches!(
+            Config::from_toml(duplicate),
+            Err(ConfigError::DuplicateLabel(label)) if label == "nexo"
+        ));
+        let unknown_kind = r#"
+            [[providers]]
+            label = "aax"
+            kind = "aax"
+        "#;
+        assert!(matches!(
+            Config::from_toml(unknown_kind),
+            Err(ConfigError::Parse(_))
+        ));
+        for tax in [
+            "[tax]\nfiscal_year_start = \"02-29\"",
+            "[tax]\nfiscal_year_start = \"April\"",
+            "[tax]\nmethod = \"random\"",
+            "[tax]\nlong_term_days = -1",
+        ] {
+            assert!(matches!(
+                Config::from_toml(tax),
+                Err(ConfigError::InvalidTax(_))
+            ));
+        }
+    }
+}
@@ -1,17 +1,23 @@
 // #![feature(map_try_insert)] // for try_insert in models::AssetPrice
-// use crate::adapters::binance::BinanceSvc;
-// use crate::adapters::coingecko;
-use crate::adapters::nexo::NexoSvc;
-use adapters::coingecko::CoinGeckoSvc;
-use lib_core::Db;
-use lib_core::traits::IsProvider;
+use crate::cli::{Cli, USAGE};
+use crate::commands::App;
+use crate::config::{Config, DEFAULT_CONFIG_PATH};
+use lib_core::{AssetRegistry, Store};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
 
 pub mod adapters {
     pub mod binance;
     pub mod coingecko;
+    pub mod effects;
     pub mod nexo;
 }
+mod cli;
+mod commands;
+mod config;
 mod models;
+pub mod polars;
 
 // TODO - estimate value, epy
 // TODO - binance: other assets
@@ -27,111 +33,49 @@ mod models;
 // GOALS -
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
     dotenvy::dotenv_override().ok();
-    // std::env::set_var("RUST_BACKTRACE", "1");
 
-    let mut db = Db::new();
-
-    let providers: Vec<Box<dyn IsProvider>> = vec![
-        // Box::new(BinanceSvc::new()?)
-        Box::new(NexoSvc::new()?),
-    ];
-    for provider in providers {
-        // let positions = provider.fetch_positions().await?;
-        // for position in positions {
-        //     dbg!(position);
-        // }
-        let transactions = provider.fetch_transactions().await?;
-    }
-
-    let coingecko_svc = CoinGeckoSvc::new()?;
-    let prices = coingecko_svc.fetch_current_prices().await?;
-    dbg!(prices);
-
-    // bc.list_staking_positions().await?;
-    // data::fetch_assets();
-    // data::fetch_binance().await?;
-
-    // println!("PRODUCTS:");
-    // for _product in PRODUCTS.read().unwrap().map.iter() {
-    //     // println!("{}", product.1);
-    // }
-    // println!("POSITIONS:");
-    // for _pos in POSITIONS.read().unwrap().by_id.iter() {
-    //     // println!("{}", pos.1);
-    // }
-
-    // data::positions_groupby_currency();
-
-    // coingecko::CurrentPriceReq::fetch_prices().await?;
-
-    Ok(())
-}
-
-pub mod polars {
-    use polars::{frame::row::Row, prelude::*};
-
-    pub trait ToRow {
-        fn to_row(&self) -> Row;
-        fn schema() -> Schema;
-    }
-    pub trait VecExt {
-        fn to_rows(&self) -> Vec<Row>;
-    }
-    impl<T: ToRow> VecExt for Vec<T> {
-        fn to_rows(&self) -> Vec<Row> {
-            self.iter().map(|t| t.to_row().clone()).collect()
-        }
-    }
-
-    #[derive(Debug)]
-    struct Employee {
-        name: String,
-        age: u32,
-        salary: f64,
-    }
-    impl ToRow for Employee {
-        fn to_row(&self) -> Row {
-            Row::new(vec![
-                AnyValue::String(&self.name),
-                AnyValue::UInt32(self.age),
-                AnyValue::Float64(self.salary),
-            ])
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(2);
         }
-        fn schema() -> Schema {
-            let mut schema = Schema::new();
-            schema.with_column("name".into(), DataType::String);
-            schema.with_column("age".into(), DataType::UInt32);
-            schema.with_column("salary".into(), DataType::Float64);
-            schema
+    };
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            ExitCode::FAILURE
         }
     }
+}
 
-    #[test]
-    pub fn ext_test_polars() -> anyhow::Result<()> {
-        let employees = vec![
-            Employee {
-                name: "Alice".to_string(),
-                age: 30,
-                salary: 50000.0,
-            },
-            Employee {
-                name: "Bob".to_string(),
-                age: 35,
-                salary: 60000.0,
-            },
-            Employee {
-                name: "Charlie".to_string(),
-                age: 40,
-                salary: 70000.0,
-            },
-        ];
-
-        let df =
-            DataFrame::from_rows_iter_and_schema(employees.to_rows().iter(), &Employee::schema())?;
-        dbg!(df);
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let config = load_config(cli.config)?;
+    // the builtin registry unless a data file is given, see libs/lib-core/data/assets.toml
+    let assets = Arc::new(match &config.assets {
+        Some(path) => AssetRegistry::load(path)?,
+        None => AssetRegistry::builtin(),
+    });
+
+    let mut app = App {
+        store: Store::open(&config.database)?,
+        assets,
+        config,
+        json: cli.json,
+    };
+    app.run(cli.command).await
+}
 
-        Ok(())
+/// `--config`, then `MONEY_TRACKER_CONFIG`, then `money-tracker.toml` when present,
+/// otherwise the fallback providers
+fn load_config(path: Option<PathBuf>) -> anyhow::Result<Config> {
+    let path = path.or_else(|| std::env::var_os("MONEY_TRACKER_CONFIG").map(PathBuf::from));
+    match path {
+        Some(path) => Ok(Config::load(path)?),
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => Ok(Config::load(DEFAULT_CONFIG_PATH)?),
+        None => Ok(Config::fallback()),
     }
 }
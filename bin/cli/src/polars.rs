@@ -0,0 +1,569 @@
+//! Tidy, long-format DataFrames of transaction effects, positions, prices and balances.
+
+use chrono::{DateTime, Utc};
+use lib_core::history::AssetPricePoint;
+use lib_core::{
+    AccountId, AccountKind, AssetId, AssetRegistry, BalanceReplay, IncomeEvent, Position,
+    PriceBook, ProviderId, TaxableDisposal, TxEffect, YearEndHolding,
+};
+use polars::{frame::row::Row, prelude::*};
+use serde::Serialize;
+
+pub trait ToRow {
+    fn to_row(&self) -> Row<'_>;
+    fn schema() -> Schema;
+}
+pub trait VecExt {
+    fn to_rows(&self) -> Vec<Row<'_>>;
+    fn to_df(&self) -> PolarsResult<DataFrame>;
+}
+impl<T: ToRow> VecExt for Vec<T> {
+    fn to_rows(&self) -> Vec<Row<'_>> {
+        self.iter().map(|t| t.to_row().clone()).collect()
+    }
+    fn to_df(&self) -> PolarsResult<DataFrame> {
+        DataFrame::from_rows_iter_and_schema(self.to_rows().iter(), &T::schema())
+    }
+}
+
+const NO_TZ: &Option<TimeZone> = &None;
+
+fn timestamp(datetime: &DateTime<Utc>) -> AnyValue<'static> {
+    AnyValue::Datetime(datetime.timestamp_millis(), TimeUnit::Milliseconds, NO_TZ)
+}
+fn timestamp_dtype() -> DataType {
+    DataType::Datetime(TimeUnit::Milliseconds, None)
+}
+fn opt_f64(value: Option<f64>) -> AnyValue<'static> {
+    value.map_or(AnyValue::Null, AnyValue::Float64)
+}
+
+/// Short readable account name, e.g. "nexo:ETH", "nexo:ETH:income", "binance:ETH:binance-staking-ETH"
+pub fn account_label(account_id: &AccountId) -> String {
+    let prefix = format!("{}:{}", account_id.provider.0, account_id.asset.0);
+    match &account_id.kind {
+        AccountKind::Holding => prefix,
+        AccountKind::Product(product_id) => format!("{prefix}:{}", product_id.0),
+        AccountKind::External => format!("{prefix}:external"),
+        AccountKind::Fee => format!("{prefix}:fee"),
+        AccountKind::Income => format!("{prefix}:income"),
+    }
+}
+
+/// Amount of an asset in whole units, e.g. 1.5 ETH
+fn amount_f64(assets: &AssetRegistry, amount: &lib_core::Amount) -> anyhow::Result<f64> {
+    Ok(amount.to_f64(assets.get(&amount.asset_id)?.decimals))
+}
+
+/// A single transaction effect
+#[derive(Debug, Clone)]
+pub struct EffectRow {
+    pub datetime: DateTime<Utc>,
+    pub account: String,
+    pub provider: String,
+    pub asset: String,
+    pub delta: f64,
+}
+impl EffectRow {
+    pub fn new(assets: &AssetRegistry, effect: &TxEffect) -> anyhow::Result<Self> {
+        Ok(EffectRow {
+            datetime: effect.datetime,
+            account: account_label(&effect.account_id),
+            provider: effect.account_id.provider.0.clone(),
+            asset: effect.amount.asset_id.0.clone(),
+            delta: amount_f64(assets, &effect.amount)?,
+        })
+    }
+}
+impl ToRow for EffectRow {
+    fn to_row(&self) -> Row<'_> {
+        Row::new(vec![
+            timestamp(&self.datetime),
+            AnyValue::String(&self.account),
+            AnyValue::String(&self.provider),
+            AnyValue::String(&self.asset),
+            AnyValue::Float64(self.delta),
+        ])
+    }
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.with_column("timestamp".into(), timestamp_dtype());
+        schema.with_column("account".into(), DataType::String);
+        schema.with_column("provider".into(), DataType::String);
+        schema.with_column("asset".into(), DataType::String);
+        schema.with_column("delta".into(), DataType::Float64);
+        schema
+    }
+}
+
+/// A position as reported by its provider
+#[derive(Debug, Clone, Serialize)]
+pub struct PositionRow {
+    pub provider: String,
+    pub position_id: String,
+    pub product_id: String,
+    pub asset: String,
+    pub amount: f64,
+    pub start_date: DateTime<Utc>,
+    pub end_date: Option<DateTime<Utc>>,
+    pub auto_renew: bool,
+}
+impl PositionRow {
+    pub fn new(
+        assets: &AssetRegistry,
+        provider: &ProviderId,
+        position: &Position,
+    ) -> anyhow::Result<Self> {
+        Ok(PositionRow {
+            provider: provider.0.clone(),
+            position_id: position.id.0.clone(),
+            product_id: position.product_id.0.clone(),
+            asset: position.amount.asset_id.0.clone(),
+            amount: amount_f64(assets, &position.amount)?,
+            start_date: position.start_date,
+            end_date: position.end_date,
+            auto_renew: position.auto_renew,
+        })
+    }
+}
+impl ToRow for PositionRow {
+    fn to_row(&self) -> Row<'_> {
+        Row::new(vec![
+            AnyValue::String(&self.provider),
+            AnyValue::String(&self.position_id),
+            AnyValue::String(&self.product_id),
+            AnyValue::String(&self.asset),
+            AnyValue::Float64(self.amount),
+            timestamp(&self.start_date),
+            self.end_date.as_ref().map_or(AnyValue::Null, timestamp),
+            AnyValue::Boolean(self.auto_renew),
+        ])
+    }
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.with_column("provider".into(), DataType::String);
+        schema.with_column("position".into(), DataType::String);
+        schema.with_column("product".into(), DataType::String);
+        schema.with_column("asset".into(), DataType::String);
+        schema.with_column("amount".into(), DataType::Float64);
+        schema.with_column("start_date".into(), timestamp_dtype());
+        schema.with_column("end_date".into(), timestamp_dtype());
+        schema.with_column("auto_renew".into(), DataType::Boolean);
+        schema
+    }
+}
+
+impl ToRow for AssetPricePoint {
+    fn to_row(&self) -> Row<'_> {
+        Row::new(vec![
+            timestamp(&self.datetime),
+            AnyValue::String(&self.asset_id.0),
+            AnyValue::String(&self.vs_asset_id.0),
+            AnyValue::Float64(self.price),
+        ])
+    }
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.with_column("timestamp".into(), timestamp_dtype());
+        schema.with_column("asset".into(), DataType::String);
+        schema.with_column("vs_asset".into(), DataType::String);
+        schema.with_column("price".into(), DataType::Float64);
+        schema
+    }
+}
+
+/// Balance of an account right after a change, and its value if it could be priced
+#[derive(Debug, Clone)]
+pub struct BalanceRow {
+    pub datetime: DateTime<Utc>,
+    pub account: String,
+    pub provider: String,
+    pub asset: String,
+    pub delta: f64,
+    pub balance: f64,
+    pub value: Option<f64>,
+}
+impl ToRow for BalanceRow {
+    fn to_row(&self) -> Row<'_> {
+        Row::new(vec![
+            timestamp(&self.datetime),
+            AnyValue::String(&self.account),
+            AnyValue::String(&self.provider),
+            AnyValue::String(&self.asset),
+            AnyValue::Float64(self.delta),
+            AnyValue::Float64(self.balance),
+            opt_f64(self.value),
+        ])
+    }
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.with_column("timestamp".into(), timestamp_dtype());
+        schema.with_column("account".into(), DataType::String);
+        schema.with_column("provider".into(), DataType::String);
+        schema.with_column("asset".into(), DataType::String);
+        schema.with_column("delta".into(), DataType::Float64);
+        schema.with_column("balance".into(), DataType::Float64);
+        schema.with_column("value".into(), DataType::Float64);
+        schema
+    }
+}
+
+/// A disposal of a tax report, one row per lot it consumed
+#[derive(Debug, Clone, Serialize)]
+pub struct TaxDisposalRow {
+    pub disposed_at: DateTime<Utc>,
+    pub transaction: String,
+    pub kind: String,
+    pub asset: String,
+    /// None for assets missing from the registry
+    pub amount: Option<f64>,
+    /// None for units disposed of beyond the held lots
+    pub acquired_at: Option<DateTime<Utc>>,
+    pub holding_days: Option<i64>,
+    pub term: String,
+    pub exempt: bool,
+    /// False for disposals left out of the report's totals
+    pub taxable: bool,
+    pub proceeds: f64,
+    pub cost: f64,
+    pub gain: f64,
+}
+impl TaxDisposalRow {
+    pub fn new(assets: &AssetRegistry, disposal: &TaxableDisposal) -> Self {
+        TaxDisposalRow {
+            disposed_at: disposal.disposed_at,
+            transaction: disposal.tx_id.0.clone(),
+            kind: disposal.kind.as_str().to_string(),
+            asset: disposal.amount.asset_id.0.clone(),
+            amount: amount_f64(assets, &disposal.amount).ok(),
+            acquired_at: disposal.acquired_at,
+            holding_days: disposal.holding_period().map(|held| held.num_days()),
+            term: disposal.term.as_str().to_string(),
+            exempt: disposal.exempt,
+            taxable: disposal.taxable,
+            proceeds: disposal.proceeds,
+            cost: disposal.cost,
+            gain: disposal.gain(),
+        }
+    }
+}
+impl ToRow for TaxDisposalRow {
+    fn to_row(&self) -> Row<'_> {
+        Row::new(vec![
+            timestamp(&self.disposed_at),
+            AnyValue::String(&self.transaction),
+            AnyValue::String(&self.kind),
+            AnyValue::String(&self.asset),
+            opt_f64(self.amount),
+            self.acquired_at.as_ref().map_or(AnyValue::Null, timestamp),
+            self.holding_days.map_or(AnyValue::Null, AnyValue::Int64),
+            AnyValue::String(&self.term),
+            AnyValue::Boolean(self.exempt),
+            AnyValue::Boolean(self.taxable),
+            AnyValue::Float64(self.proceeds),
+            AnyValue::Float64(self.cost),
+            AnyValue::Float64(self.gain),
+        ])
+    }
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.with_column("disposed_at".into(), timestamp_dtype());
+        schema.with_column("transaction".into(), DataType::String);
+        schema.with_column("kind".into(), DataType::String);
+        schema.with_column("asset".into(), DataType::String);
+        schema.with_column("amount".into(), DataType::Float64);
+        schema.with_column("acquired_at".into(), timestamp_dtype());
+        schema.with_column("holding_days".into(), DataType::Int64);
+        schema.with_column("term".into(), DataType::String);
+        schema.with_column("exempt".into(), DataType::Boolean);
+        schema.with_column("taxable".into(), DataType::Boolean);
+        schema.with_column("proceeds".into(), DataType::Float64);
+        schema.with_column("cost".into(), DataType::Float64);
+        schema.with_column("gain".into(), DataType::Float64);
+        schema
+    }
+}
+
+/// Income of a tax report, valued when received
+#[derive(Debug, Clone, Serialize)]
+pub struct TaxIncomeRow {
+    pub datetime: DateTime<Utc>,
+    pub transaction: String,
+    pub asset: String,
+    /// None for assets missing from the registry
+    pub amount: Option<f64>,
+    pub value: f64,
+}
+impl TaxIncomeRow {
+    pub fn new(assets: &AssetRegistry, income: &IncomeEvent) -> Self {
+        TaxIncomeRow {
+            datetime: income.datetime,
+            transaction: income.tx_id.0.clone(),
+            asset: income.amount.asset_id.0.clone(),
+            amount: amount_f64(assets, &income.amount).ok(),
+            value: income.value,
+        }
+    }
+}
+impl ToRow for TaxIncomeRow {
+    fn to_row(&self) -> Row<'_> {
+        Row::new(vec![
+            timestamp(&self.datetime),
+            AnyValue::String(&self.transaction),
+            AnyValue::String(&self.asset),
+            opt_f64(self.amount),
+            AnyValue::Float64(self.value),
+        ])
+    }
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.with_column("timestamp".into(), timestamp_dtype());
+        schema.with_column("transaction".into(), DataType::String);
+        schema.with_column("asset".into(), DataType::String);
+        schema.with_column("amount".into(), DataType::Float64);
+        schema.with_column("value".into(), DataType::Float64);
+        schema
+    }
+}
+
+/// Holdings of an asset at the end of a fiscal year
+#[derive(Debug, Clone, Serialize)]
+pub struct TaxHoldingRow {
+    pub asset: String,
+    /// None for assets missing from the registry
+    pub amount: Option<f64>,
+    pub cost: f64,
+    pub value: Option<f64>,
+}
+impl TaxHoldingRow {
+    pub fn new(assets: &AssetRegistry, holding: &YearEndHolding) -> Self {
+        TaxHoldingRow {
+            asset: holding.amount.asset_id.0.clone(),
+            amount: amount_f64(assets, &holding.amount).ok(),
+            cost: holding.cost,
+            value: holding.value,
+        }
+    }
+}
+impl ToRow for TaxHoldingRow {
+    fn to_row(&self) -> Row<'_> {
+        Row::new(vec![
+            AnyValue::String(&self.asset),
+            opt_f64(self.amount),
+            AnyValue::Float64(self.cost),
+            opt_f64(self.value),
+        ])
+    }
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.with_column("asset".into(), DataType::String);
+        schema.with_column("amount".into(), DataType::Float64);
+        schema.with_column("cost".into(), DataType::Float64);
+        schema.with_column("value".into(), DataType::Float64);
+        schema
+    }
+}
+
+/// Builds the long-format (timestamp, account, provider, asset, delta, balance, value) frame
+/// of a replayed transaction log, one row per balance change, oldest first
+pub struct BalanceFrameBuilder<'a> {
+    assets: &'a AssetRegistry,
+    prices: Option<(&'a PriceBook, AssetId)>,
+    counter_accounts: bool,
+}
+
+impl<'a> BalanceFrameBuilder<'a> {
+    pub fn new(assets: &'a AssetRegistry) -> Self {
+        BalanceFrameBuilder {
+            assets,
+            prices: None,
+            counter_accounts: false,
+        }
+    }
+
+    /// Fills the value column with balances priced in `vs_asset_id`
+    pub fn with_prices(mut self, prices: &'a PriceBook, vs_asset_id: AssetId) -> Self {
+        self.prices = Some((prices, vs_asset_id));
+        self
+    }
+
+    /// Also includes external, fee and income accounts
+    pub fn with_counter_accounts(mut self) -> Self {
+        self.counter_accounts = true;
+        self
+    }
+
+    /// Rows of the accounts in registered assets, the others are skipped with a warning
+    pub fn rows(&self, replay: &BalanceReplay) -> Vec<BalanceRow> {
+        let mut rows = Vec::new();
+        for account_id in replay.accounts() {
+            if account_id.is_counter_account() && !self.counter_accounts {
+                continue;
+            }
+            let decimals = match self.assets.get(&account_id.asset) {
+                Ok(asset) => asset.decimals,
+                Err(error) => {
+                    eprintln!("left out of the export: {error}");
+                    continue;
+                }
+            };
+            let account = account_label(account_id);
+            for point in replay.history(account_id) {
+                let balance = point.balance.to_f64(decimals);
+                let value = self.prices.as_ref().and_then(|(prices, vs_asset_id)| {
+                    let quote = prices.price(&account_id.asset, vs_asset_id, point.datetime);
+                    quote.ok().map(|quote| balance * quote.price)
+                });
+                rows.push(BalanceRow {
+                    datetime: point.datetime,
+                    account: account.clone(),
+                    provider: account_id.provider.0.clone(),
+                    asset: account_id.asset.0.clone(),
+                    delta: point.delta.to_f64(decimals),
+                    balance,
+                    value,
+                });
+            }
+        }
+        rows.sort_by(|a, b| (a.datetime, &a.account).cmp(&(b.datetime, &b.account)));
+        rows
+    }
+
+    pub fn build(&self, replay: &BalanceReplay) -> anyhow::Result<DataFrame> {
+        Ok(self.rows(replay).to_df()?)
+    }
+}
+
+/// Nexo ETH savings funded with 2 ETH on April 1st and paid 0.1 ETH interest on the 2nd
+#[cfg(test)]
+fn sample_transactions() -> Vec<lib_core::Transaction> {
+    use chrono::TimeZone;
+    use lib_core::{Amount, Transaction, TransactionId};
+
+    let nexo = ProviderId::from("nexo");
+    let eth = AssetId::from("ETH");
+    let savings = AccountId::new(nexo.clone(), eth.clone());
+    let (outside, income) = (
+        AccountId::external(nexo.clone(), eth.clone()),
+        AccountId::income(nexo, eth.clone()),
+    );
+    let transfer = |from: &AccountId, to: &AccountId, units: i128, day| {
+        let datetime = Utc.with_ymd_and_hms(2024, 4, day, 0, 0, 0).unwrap();
+        let effect = |account_id: &AccountId, units| TxEffect {
+            account_id: account_id.clone(),
+            amount: Amount::new(eth.clone(), units * 100_000_000_000_000_000),
+            datetime,
+        };
+        Transaction {
+            id: TransactionId::default(),
+            tx_hash: None,
+            inputs: vec![effect(from, -units)],
+            outputs: vec![effect(to, units)],
+            datetime,
+        }
+    };
+    vec![
+        transfer(&outside, &savings, 20, 1),
+        transfer(&income, &savings, 1, 2),
+    ]
+}
+
+#[test]
+fn test_effect_frame() -> anyhow::Result<()> {
+    let assets = AssetRegistry::builtin();
+    let txs = sample_transactions();
+    let effects = txs
+        .iter()
+        .flat_map(|tx| tx.effects())
+        .map(|effect| EffectRow::new(&assets, effect))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let df = effects.to_df()?;
+    assert_eq!(
+        df.get_column_names(),
+        ["timestamp", "account", "provider", "asset", "delta"]
+    );
+    assert_eq!(df.height(), 4);
+
+    // running sum of the savings deltas is the replayed balance
+    let savings = df.filter(&df.column("account")?.str()?.equal("nexo:ETH"))?;
+    let running = savings
+        .column("delta")?
+        .f64()?
+        .into_no_null_iter()
+        .scan(0.0, |balance, delta| {
+            *balance += delta;
+            Some(*balance)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(running, [2.0, 2.1]);
+
+    let balances = BalanceFrameBuilder::new(&assets).build(&BalanceReplay::replay(&txs)?)?;
+    assert_eq!(
+        balances.get_column_names(),
+        [
+            "timestamp",
+            "account",
+            "provider",
+            "asset",
+            "delta",
+            "balance",
+            "value"
+        ]
+    );
+    let replayed = balances
+        .column("balance")?
+        .f64()?
+        .into_no_null_iter()
+        .collect::<Vec<_>>();
+    assert_eq!(replayed, running);
+    Ok(())
+}
+
+#[test]
+fn test_balance_frame() -> anyhow::Result<()> {
+    use chrono::TimeZone;
+
+    let eth = AssetId::from("ETH");
+    let mut txs = sample_transactions();
+    // an asset missing from the registry is left out rather than failing the frame
+    let mut unlisted = txs[0].clone();
+    unlisted.id = lib_core::TransactionId::from("nexo:unlisted");
+    for effect in unlisted
+        .inputs
+        .iter_mut()
+        .chain(unlisted.outputs.iter_mut())
+    {
+        effect.account_id.asset = AssetId::from("NOT-LISTED");
+        effect.amount.asset_id = AssetId::from("NOT-LISTED");
+    }
+    txs.insert(1, unlisted);
+    let replay = BalanceReplay::replay(&txs)?;
+
+    let mut prices = PriceBook::new();
+    prices.insert(AssetPricePoint {
+        datetime: Utc.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap(),
+        asset_id: eth.clone(),
+        vs_asset_id: AssetId::from("USD"),
+        price: 3000.0,
+    });
+    let assets = AssetRegistry::builtin();
+
+    let df = BalanceFrameBuilder::new(&assets)
+        .with_prices(&prices, AssetId::from("USD"))
+        .build(&replay)?;
+    assert_eq!(df.shape(), (2, 7));
+    let balances = df
+        .column("balance")?
+        .f64()?
+        .into_no_null_iter()
+        .collect::<Vec<_>>();
+    assert_eq!(balances, [2.0, 2.1]);
+    let values = df.column("value")?.f64()?.into_iter().collect::<Vec<_>>();
+    assert_eq!(values, [Some(6000.0), Some(6300.0)]);
+
+    let all = BalanceFrameBuilder::new(&assets)
+        .with_counter_accounts()
+        .build(&replay)?;
+    assert_eq!(all.height(), 4);
+    Ok(())
+}
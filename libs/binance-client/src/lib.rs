@@ -1,8 +1,27 @@
+use crate::local_utils::window_params;
 use crate::payloads::{ListResp, StakingPositionResp};
-use payloads::{FlexEarnPos, LockedEarnPos, StakingProduct};
+use chrono::{DateTime, TimeDelta, Utc};
+use payloads::{
+    AccountInfo, AssetBalance, ConvertRecord, ConvertTradeFlow, DepositRecord, DustConversion,
+    DustLog, EarnRedemption, EarnReward, EarnSubscription, FlexEarnPos, LockedEarnPos,
+    StakingProduct, StakingRecord, Trade, WithdrawalRecord,
+};
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
 use signing::RequestSigner;
 use utils::prelude::{RequestBuilderExt, *};
+use utils::time::windows;
+
+/// Longest time range most history endpoints accept in a single query
+pub const MAX_HISTORY_WINDOW: TimeDelta = TimeDelta::days(90);
+/// Convert history only accepts 30 days per query
+pub const MAX_CONVERT_WINDOW: TimeDelta = TimeDelta::days(30);
+
+const SPOT_API: &str = "https://api.binance.com/api/v3";
+const CAPITAL_PAGE_SIZE: usize = 1000;
+const EARN_PAGE_SIZE: usize = 100;
+const TRADES_PAGE_SIZE: usize = 1000;
+const CONVERT_PAGE_SIZE: usize = 1000;
 
 // const API_BASE: OnceCell<Url> = OnceCell::new(|| Url::parse("https://api.binance.com").unwrap());
 // const API_KEY: OnceCell<String> = OnceCell::new(|| std::env::var("BINANCE_API_KEY").unwrap());
@@ -23,13 +42,21 @@ impl IsApiClient for BinanceClient {
     }
 }
 impl BinanceClient {
+    /// Reads credentials from `BINANCE_API_KEY` and `BINANCE_SECRET_KEY`
     pub fn new() -> anyhow::Result<Self> {
-        Ok(BinanceClient {
+        Ok(Self::with_credentials(
+            std::env::var("BINANCE_API_KEY")?,
+            std::env::var("BINANCE_SECRET_KEY")?,
+        ))
+    }
+
+    pub fn with_credentials(api_key: impl Into<String>, api_secret: impl Into<String>) -> Self {
+        BinanceClient {
             http_client: reqwest::Client::new(),
             base_url: "https://api.binance.com/sapi/v1".to_string(),
-            api_key: std::env::var("BINANCE_API_KEY")?,
-            api_secret: std::env::var("BINANCE_SECRET_KEY")?,
-        })
+            api_key: api_key.into(),
+            api_secret: api_secret.into(),
+        }
     }
 
     pub async fn list_staking_products(&self) -> anyhow::Result<Vec<StakingProduct>> {
@@ -60,6 +87,245 @@ impl BinanceClient {
 
         Ok(resp.rows)
     }
+
+    /// Spot wallet balances, zero balances omitted
+    pub async fn list_spot_balances(&self) -> anyhow::Result<Vec<AssetBalance>> {
+        let req = self
+            .get(&format!("{SPOT_API}/account"))
+            .query(&[("omitZeroBalances", "true")])
+            .sign(self)?;
+        let resp = req.fetch_json::<AccountInfo>().await?;
+        Ok(resp.balances)
+    }
+    pub async fn list_funding_balances(&self) -> anyhow::Result<Vec<AssetBalance>> {
+        let req = self.post("/asset/get-funding-asset").sign(self)?;
+        let resp = req.fetch_json::<Vec<AssetBalance>>().await?;
+        Ok(resp)
+    }
+
+    pub async fn list_deposits(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<DepositRecord>> {
+        self.fetch_capital_history("/capital/deposit/hisrec", start, end)
+            .await
+    }
+    pub async fn list_withdrawals(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<WithdrawalRecord>> {
+        self.fetch_capital_history("/capital/withdraw/history", start, end)
+            .await
+    }
+
+    /// Spot trades of a single symbol (e.g. "ETHUSDT") from a trade id on, paged by trade id
+    pub async fn list_trades(&self, symbol: &str, from_id: u64) -> anyhow::Result<Vec<Trade>> {
+        let mut trades = Vec::new();
+        let mut from_id = from_id;
+        loop {
+            let req = self
+                .get(&format!("{SPOT_API}/myTrades"))
+                .query(&[("symbol", symbol)])
+                .query(&[("fromId", from_id), ("limit", TRADES_PAGE_SIZE as u64)])
+                .sign(self)?;
+            let page = req.fetch_json::<Vec<Trade>>().await?;
+            let page_len = page.len();
+            if let Some(last) = page.last() {
+                from_id = last.id + 1;
+            }
+            trades.extend(page);
+            if page_len < TRADES_PAGE_SIZE {
+                return Ok(trades);
+            }
+        }
+    }
+
+    /// Convert history has no paging, windows that fill a page are split in halves and
+    /// queried again until each fits in one
+    pub async fn list_converts(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<ConvertRecord>> {
+        let mut converts = Vec::new();
+        let mut pending = windows(start, end, MAX_CONVERT_WINDOW).collect::<Vec<_>>();
+        pending.reverse();
+        while let Some((from, to)) = pending.pop() {
+            let req = self
+                .get("/convert/tradeFlow")
+                .query(&window_params(from, to))
+                .query(&[("limit", CONVERT_PAGE_SIZE)])
+                .sign(self)?;
+            let resp = req.fetch_json::<ConvertTradeFlow>().await?;
+            let mid = from + (to - from) / 2;
+            if resp.list.len() >= CONVERT_PAGE_SIZE && mid > from {
+                pending.push((mid, to));
+                pending.push((from, mid));
+                continue;
+            }
+            converts.extend(resp.list);
+        }
+        Ok(converts)
+    }
+
+    /// Small balances converted to BNB
+    pub async fn list_dust_conversions(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<DustConversion>> {
+        let mut conversions = Vec::new();
+        for (from, to) in windows(start, end, MAX_HISTORY_WINDOW) {
+            let req = self
+                .get("/asset/dribblet")
+                .query(&window_params(from, to))
+                .sign(self)?;
+            let resp = req.fetch_json::<DustLog>().await?;
+            conversions.extend(resp.user_asset_dribblets);
+        }
+        Ok(conversions)
+    }
+
+    pub async fn list_flexible_subscriptions(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<EarnSubscription>> {
+        let path = "/simple-earn/flexible/history/subscriptionRecord";
+        self.fetch_earn_history(path, &[], start, end).await
+    }
+    pub async fn list_flexible_redemptions(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<EarnRedemption>> {
+        let path = "/simple-earn/flexible/history/redemptionRecord";
+        self.fetch_earn_history(path, &[], start, end).await
+    }
+    pub async fn list_flexible_rewards(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<EarnReward>> {
+        let path = "/simple-earn/flexible/history/rewardsRecord";
+        let mut rewards = Vec::new();
+        for reward_type in ["BONUS", "REALTIME", "REWARDS"] {
+            let query = [("type", reward_type)];
+            let page: Vec<EarnReward> = self.fetch_earn_history(path, &query, start, end).await?;
+            rewards.extend(page.into_iter().map(|mut reward| {
+                reward
+                    .reward_type
+                    .get_or_insert_with(|| reward_type.to_string());
+                reward
+            }));
+        }
+        Ok(rewards)
+    }
+    pub async fn list_locked_subscriptions(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<EarnSubscription>> {
+        let path = "/simple-earn/locked/history/subscriptionRecord";
+        self.fetch_earn_history(path, &[], start, end).await
+    }
+    pub async fn list_locked_redemptions(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<EarnRedemption>> {
+        let path = "/simple-earn/locked/history/redemptionRecord";
+        self.fetch_earn_history(path, &[], start, end).await
+    }
+    pub async fn list_locked_rewards(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<EarnReward>> {
+        let path = "/simple-earn/locked/history/rewardsRecord";
+        self.fetch_earn_history(path, &[], start, end).await
+    }
+
+    pub async fn list_staking_rewards(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<StakingRecord>> {
+        let mut rewards = Vec::new();
+        for (from, to) in windows(start, end, MAX_HISTORY_WINDOW) {
+            for current in 1.. {
+                let req = self
+                    .get("/staking/stakingRecord")
+                    .query(&[("product", "STAKING"), ("txnType", "INTEREST")])
+                    .query(&window_params(from, to))
+                    .query(&[("current", current), ("size", EARN_PAGE_SIZE)])
+                    .sign(self)?;
+                let page = req.fetch_json::<Vec<StakingRecord>>().await?;
+                let page_len = page.len();
+                rewards.extend(page);
+                if page_len < EARN_PAGE_SIZE {
+                    break;
+                }
+            }
+        }
+        Ok(rewards)
+    }
+
+    /// Deposit and withdrawal history, paged by offset within each window
+    async fn fetch_capital_history<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<T>> {
+        let mut records = Vec::new();
+        for (from, to) in windows(start, end, MAX_HISTORY_WINDOW) {
+            for offset in (0..).step_by(CAPITAL_PAGE_SIZE) {
+                let req = self
+                    .get(path)
+                    .query(&window_params(from, to))
+                    .query(&[("offset", offset), ("limit", CAPITAL_PAGE_SIZE)])
+                    .sign(self)?;
+                let page = req.fetch_json::<Vec<T>>().await?;
+                let page_len = page.len();
+                records.extend(page);
+                if page_len < CAPITAL_PAGE_SIZE {
+                    break;
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Simple Earn history, paged by page number within each window
+    async fn fetch_earn_history<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<T>> {
+        let mut records = Vec::new();
+        for (from, to) in windows(start, end, MAX_HISTORY_WINDOW) {
+            for current in 1.. {
+                let req = self
+                    .get(path)
+                    .query(query)
+                    .query(&window_params(from, to))
+                    .query(&[("current", current), ("size", EARN_PAGE_SIZE)])
+                    .sign(self)?;
+                let page = req.fetch_json::<ListResp<T>>().await?;
+                let page_len = page.rows.len();
+                records.extend(page.rows);
+                if page_len < EARN_PAGE_SIZE {
+                    break;
+                }
+            }
+        }
+        Ok(records)
+    }
 }
 
 #[derive(Debug, derive_more::Display, Deserialize)]
@@ -70,7 +336,10 @@ pub struct BinanceApiErrResp {
 }
 
 pub mod payloads {
-    use crate::local_utils::{de_from_str, de_str_to_datetime, de_u_to_datetime};
+    use crate::local_utils::{
+        de_from_str, de_lenient_decimal, de_lenient_u64, de_str_to_datetime, de_u_to_datetime,
+        de_utc_datetime_str,
+    };
     use chrono::{DateTime, Utc};
     use serde::{Deserialize, Serialize};
     use std::ops::Deref;
@@ -81,6 +350,223 @@ pub mod payloads {
         pub total: u64,
     }
 
+    #[derive(Deserialize, Debug)]
+    pub struct AccountInfo {
+        pub balances: Vec<AssetBalance>,
+    }
+    /// Balance of a spot or funding wallet
+    #[derive(Deserialize, Debug)]
+    pub struct AssetBalance {
+        pub asset: String,
+        pub free: String,
+        /// In open orders
+        pub locked: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct DepositRecord {
+        pub id: String,
+        pub amount: String,
+        pub coin: String,
+        pub network: String,
+        /// 0 pending, 6 credited but cannot withdraw, 1 success
+        pub status: u8,
+        #[serde(rename = "txId", default)]
+        pub tx_id: String,
+        #[serde(rename = "insertTime", deserialize_with = "de_u_to_datetime")]
+        pub insert_time: DateTime<Utc>,
+    }
+    impl DepositRecord {
+        /// Whether the funds reached the account
+        pub fn is_credited(&self) -> bool {
+            matches!(self.status, 1 | 6)
+        }
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct WithdrawalRecord {
+        pub id: String,
+        /// Amount sent, excluding the fee
+        pub amount: String,
+        #[serde(rename = "transactionFee")]
+        pub transaction_fee: String,
+        pub coin: String,
+        pub network: String,
+        /// 6 completed, see Binance docs for the others
+        pub status: u8,
+        #[serde(rename = "txId", default)]
+        pub tx_id: String,
+        #[serde(rename = "applyTime", deserialize_with = "de_utc_datetime_str")]
+        pub apply_time: DateTime<Utc>,
+    }
+    impl WithdrawalRecord {
+        pub fn is_completed(&self) -> bool {
+            self.status == 6
+        }
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Trade {
+        pub symbol: String,
+        pub id: u64,
+        #[serde(rename = "orderId")]
+        pub order_id: u64,
+        /// Base asset bought or sold
+        pub qty: String,
+        /// Quote asset paid or received
+        #[serde(rename = "quoteQty")]
+        pub quote_qty: String,
+        pub commission: String,
+        #[serde(rename = "commissionAsset")]
+        pub commission_asset: String,
+        #[serde(deserialize_with = "de_u_to_datetime")]
+        pub time: DateTime<Utc>,
+        #[serde(rename = "isBuyer")]
+        pub is_buyer: bool,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct ConvertTradeFlow {
+        pub list: Vec<ConvertRecord>,
+    }
+    #[derive(Deserialize, Debug)]
+    pub struct ConvertRecord {
+        #[serde(rename = "orderId")]
+        pub order_id: u64,
+        #[serde(rename = "orderStatus")]
+        pub order_status: String,
+        #[serde(rename = "fromAsset")]
+        pub from_asset: String,
+        #[serde(rename = "fromAmount")]
+        pub from_amount: String,
+        #[serde(rename = "toAsset")]
+        pub to_asset: String,
+        #[serde(rename = "toAmount")]
+        pub to_amount: String,
+        #[serde(rename = "createTime", deserialize_with = "de_u_to_datetime")]
+        pub create_time: DateTime<Utc>,
+    }
+    impl ConvertRecord {
+        pub fn is_success(&self) -> bool {
+            self.order_status == "SUCCESS"
+        }
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct DustLog {
+        #[serde(rename = "userAssetDribblets", default)]
+        pub user_asset_dribblets: Vec<DustConversion>,
+    }
+    /// One dust conversion, possibly of several assets at once
+    #[derive(Deserialize, Debug)]
+    pub struct DustConversion {
+        #[serde(rename = "transId", deserialize_with = "de_lenient_u64")]
+        pub trans_id: u64,
+        #[serde(rename = "operateTime", deserialize_with = "de_u_to_datetime")]
+        pub operate_time: DateTime<Utc>,
+        #[serde(rename = "userAssetDribbletDetails")]
+        pub details: Vec<DustDetail>,
+    }
+    #[derive(Deserialize, Debug)]
+    pub struct DustDetail {
+        #[serde(rename = "fromAsset")]
+        pub from_asset: String,
+        #[serde(deserialize_with = "de_lenient_decimal")]
+        pub amount: String,
+        /// BNB received, net of the service charge
+        #[serde(rename = "transferedAmount", deserialize_with = "de_lenient_decimal")]
+        pub transfered_amount: String,
+        /// BNB kept by Binance
+        #[serde(
+            rename = "serviceChargeAmount",
+            deserialize_with = "de_lenient_decimal"
+        )]
+        pub service_charge_amount: String,
+    }
+
+    /// Flexible or locked Simple Earn subscription
+    #[derive(Deserialize, Debug)]
+    pub struct EarnSubscription {
+        #[serde(rename = "purchaseId", default, deserialize_with = "de_opt_id")]
+        pub purchase_id: Option<String>,
+        /// `productId` for flexible products, `projectId` for locked ones
+        #[serde(rename = "productId", alias = "projectId")]
+        pub product_id: String,
+        /// Locked subscriptions only, later records of the position only name it
+        #[serde(rename = "positionId", default, deserialize_with = "de_opt_id")]
+        pub position_id: Option<String>,
+        pub asset: String,
+        pub amount: String,
+        #[serde(deserialize_with = "de_u_to_datetime")]
+        pub time: DateTime<Utc>,
+        #[serde(default)]
+        pub status: String,
+    }
+
+    /// Flexible or locked Simple Earn redemption
+    #[derive(Deserialize, Debug)]
+    pub struct EarnRedemption {
+        #[serde(rename = "redeemId", default, deserialize_with = "de_opt_id")]
+        pub redeem_id: Option<String>,
+        #[serde(rename = "productId", alias = "projectId", default)]
+        pub product_id: Option<String>,
+        #[serde(rename = "positionId", default, deserialize_with = "de_opt_id")]
+        pub position_id: Option<String>,
+        pub asset: String,
+        pub amount: String,
+        #[serde(deserialize_with = "de_u_to_datetime")]
+        pub time: DateTime<Utc>,
+        #[serde(default)]
+        pub status: String,
+    }
+
+    /// Flexible or locked Simple Earn reward
+    #[derive(Deserialize, Debug)]
+    pub struct EarnReward {
+        /// Flexible rewards only, locked ones name their position
+        #[serde(rename = "productId", alias = "projectId", default)]
+        pub product_id: Option<String>,
+        #[serde(rename = "positionId", default, deserialize_with = "de_opt_id")]
+        pub position_id: Option<String>,
+        /// Flexible rewards only, `BONUS`, `REALTIME` or `REWARDS`
+        #[serde(rename = "type", default)]
+        pub reward_type: Option<String>,
+        pub asset: String,
+        /// `rewards` for flexible products, `amount` for locked ones
+        #[serde(rename = "rewards", alias = "amount")]
+        pub amount: String,
+        #[serde(deserialize_with = "de_u_to_datetime")]
+        pub time: DateTime<Utc>,
+    }
+
+    /// Staking reward
+    #[derive(Deserialize, Debug)]
+    pub struct StakingRecord {
+        #[serde(rename = "positionId", default, deserialize_with = "de_opt_id")]
+        pub position_id: Option<String>,
+        /// Staking product, e.g. `BNB*90`
+        #[serde(rename = "project", default)]
+        pub product_id: Option<String>,
+        pub asset: String,
+        pub amount: String,
+        #[serde(deserialize_with = "de_u_to_datetime")]
+        pub time: DateTime<Utc>,
+        #[serde(default)]
+        pub status: String,
+    }
+
+    /// Ids are sometimes numbers and sometimes strings
+    fn de_opt_id<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let id = Option::<serde_json::Value>::deserialize(deserializer)?;
+        Ok(id.map(|id| match id {
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        }))
+    }
+
     #[derive(Deserialize, Debug)]
     pub struct StakingPositionResp {
         #[serde(rename = "positionId")]
@@ -89,8 +575,7 @@ pub mod payloads {
         pub product_id: String,
         #[serde(rename = "asset")]
         pub asset_id: String,
-        #[serde(deserialize_with = "de_from_str")]
-        pub amount: f64,
+        pub amount: String,
         #[serde(rename = "purchaseTime", deserialize_with = "de_u_to_datetime")]
         pub purchase_time: DateTime<Utc>,
         pub duration: u64, // TODO deserialize to duration
@@ -113,7 +598,8 @@ pub mod payloads {
         // pub redeemPeriod: String,
         // pub redeemingAmt: String,
         // pub canRedeemEarly: bool,
-        // pub renewable: bool,
+        #[serde(default)]
+        pub renewable: bool,
         // pub partialAmtDeliverDate: String,
         // pub status: String,
     }
@@ -126,8 +612,7 @@ pub mod payloads {
         pub project_id: String,
         #[serde(rename = "asset")]
         pub asset_id: String,
-        #[serde(deserialize_with = "de_from_str")]
-        pub amount: f64,
+        pub amount: String,
         #[serde(rename = "purchaseTime", deserialize_with = "de_str_to_datetime")]
         pub purchase_time: DateTime<Utc>,
         #[serde(rename = "duration", deserialize_with = "de_from_str")]
@@ -148,8 +633,8 @@ pub mod payloads {
 
     #[derive(Deserialize, Debug)]
     pub struct FlexEarnPos {
-        #[serde(rename = "totalAmount", deserialize_with = "de_from_str")]
-        pub total_amount: f64,
+        #[serde(rename = "totalAmount")]
+        pub total_amount: String,
         #[serde(rename = "tierAnnualPercentageRate")]
         pub tier_annual_percentage_rate: serde_json::Value,
         #[serde(
@@ -267,6 +752,7 @@ pub mod signing {
 pub mod local_utils {
     use chrono::{DateTime, Utc};
     use serde::{Deserialize, Deserializer, de};
+    use serde_json::value::RawValue;
 
     pub fn de_from_str<'de, D, Out>(deserializer: D) -> Result<Out, D::Error>
     where
@@ -299,6 +785,57 @@ pub mod local_utils {
         Ok(datetime)
     }
 
+    /// Timestamps like "2019-10-12 11:12:02", in UTC
+    pub fn de_utc_datetime_str<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let naive = chrono::NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S")
+            .map_err(de::Error::custom)?;
+        Ok(naive.and_utc())
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StrOrNum<T> {
+        Num(T),
+        Str(String),
+    }
+
+    /// Decimals that older endpoints return as JSON numbers and newer ones as strings,
+    /// kept as decimal strings. Numbers keep their JSON text, an f64 would round them.
+    pub fn de_lenient_decimal<'de, D>(deserializer: D) -> Result<String, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = Box::<RawValue>::deserialize(deserializer)?;
+        match raw.get() {
+            quoted if quoted.starts_with('"') => {
+                serde_json::from_str(quoted).map_err(de::Error::custom)
+            }
+            number => Ok(number.to_string()),
+        }
+    }
+
+    pub fn de_lenient_u64<'de, D>(deserializer: D) -> Result<u64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match StrOrNum::<u64>::deserialize(deserializer)? {
+            StrOrNum::Num(n) => Ok(n),
+            StrOrNum::Str(s) => s.parse().map_err(de::Error::custom),
+        }
+    }
+
+    /// startTime/endTime query params; Binance's endTime is inclusive
+    pub fn window_params(from: DateTime<Utc>, to: DateTime<Utc>) -> [(&'static str, i64); 2] {
+        [
+            ("startTime", from.timestamp_millis()),
+            ("endTime", to.timestamp_millis() - 1),
+        ]
+    }
+
     pub fn hex(_in: impl AsRef<[u8]>) -> Result<String, std::fmt::Error> {
         let mut s = String::new();
         for byte in _in.as_ref() {
@@ -324,6 +861,24 @@ pub mod local_utils {
             assert_eq!(hex, expected);
             Ok(())
         }
+
+        #[test]
+        fn test_lenient_decimal_is_exact() -> anyhow::Result<()> {
+            #[derive(Deserialize)]
+            struct Decimal(#[serde(deserialize_with = "de_lenient_decimal")] String);
+            let number = serde_json::from_str::<Decimal>("123456789.123456789012345678")?;
+            assert_eq!(number.0, "123456789.123456789012345678");
+            let string = serde_json::from_str::<Decimal>(r#""0.000000000000000001""#)?;
+            assert_eq!(string.0, "0.000000000000000001");
+            Ok(())
+        }
+
+        #[test]
+        fn test_window_params() {
+            let start = DateTime::from_timestamp(0, 0).unwrap();
+            let end = start + chrono::TimeDelta::days(200);
+            assert_eq!(window_params(start, end)[1].1, end.timestamp_millis() - 1);
+        }
     }
 }
 
@@ -345,10 +900,31 @@ pub mod tests {
     #[test]
     fn test_serde_responses() -> anyhow::Result<()> {
         let resp_list_earn_locked = r#"{"positionId": "123123","projectId": "Axs*90","asset": "AXS","amount": "122.09202928","purchaseTime": "1646182276000","duration": "60","accrualDays": "4","rewardAsset": "AXS","APY": "0.23","isRenewable": true,"isAutoRenew": true,"redeemDate": "1732182276000"}"#;
-        let _deser = serde_json::from_str::<LockedEarnPos>(&resp_list_earn_locked)?;
+        let _deser = serde_json::from_str::<LockedEarnPos>(resp_list_earn_locked)?;
 
         let resp_list_earn_flex = r#"{"totalAmount": "75.46000000","tierAnnualPercentageRate": {  "0-5BTC": 0.05,  "5-10BTC": 0.03},"latestAnnualPercentageRate": "0.02599895","yesterdayAirdropPercentageRate": "0.02599895","asset": "USDT","airDropAsset": "BETH","canRedeem": true,"collateralAmount": "232.23123213","productId": "USDT001","yesterdayRealTimeRewards": "0.10293829","cumulativeBonusRewards": "0.22759183","cumulativeRealTimeRewards": "0.22759183","cumulativeTotalRewards": "0.45459183","autoSubscribe": true}"#;
-        let _deser = serde_json::from_str::<FlexEarnPos>(&resp_list_earn_flex)?;
+        let _deser = serde_json::from_str::<FlexEarnPos>(resp_list_earn_flex)?;
+
+        let resp_dust = r#"{"total": 1,"userAssetDribblets": [{"operateTime": 1615985535000,"totalTransferedAmount": "0.00132256","totalServiceChargeAmount": "0.00002699","transId": 45178372831,"userAssetDribbletDetails": [{"transId": 4359321,"serviceChargeAmount": 0.000009,"amount": "0.0009","operateTime": 1615985535000,"transferedAmount": "0.000441","fromAsset": "USDT"}]}]}"#;
+        let dust = serde_json::from_str::<payloads::DustLog>(resp_dust)?;
+        assert_eq!(
+            dust.user_asset_dribblets[0].details[0].service_charge_amount,
+            "0.000009"
+        );
+
+        let resp_withdraw = r#"{"id": "b6ae22b3aa844210a7041aee7589627c","amount": "8.91000000","transactionFee": "0.004","coin": "USDT","status": 6,"address": "0x94df8b352de7f46f64b01d3666bf6e936e44ce60","txId": "0xb5ef8c13b968a406cc62a93a8bd80f9e9a906ef1b3fcf20a2e48573c17659268","applyTime": "2019-10-12 11:12:02","network": "ETH","transferType": 0}"#;
+        let withdrawal = serde_json::from_str::<payloads::WithdrawalRecord>(resp_withdraw)?;
+        assert!(withdrawal.is_completed());
+        assert_eq!(withdrawal.apply_time.timestamp(), 1570878722);
+
+        let resp_locked_reward = r#"{"positionId": "123123","time": 1646182276000,"asset": "AXS","lockPeriod": "30","amount": "1.20"}"#;
+        let reward = serde_json::from_str::<payloads::EarnReward>(resp_locked_reward)?;
+        assert_eq!(reward.amount, "1.20");
+        assert_eq!(reward.position_id.as_deref(), Some("123123"));
+
+        let resp_funding = r#"[{"asset": "USDT","free": "1","locked": "0","freeze": "0","withdrawing": "0","btcValuation": "0.00000091"}]"#;
+        let funding = serde_json::from_str::<Vec<payloads::AssetBalance>>(resp_funding)?;
+        assert_eq!(funding[0].free, "1");
 
         let position_id = PositionId { id: 123456 };
         let ser = serde_json::to_string(&position_id)?;
@@ -1,9 +1,13 @@
-use chrono::Utc;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
 use models::AssetPricePoint;
-use payloads::{CurrentPriceReq, CurrentPriceResp};
+use payloads::{CurrentPriceReq, CurrentPriceResp, HistoryResp, MarketChartResp};
 use serde::Deserialize;
 use utils::prelude::*;
 
+/// Longest range requested from market_chart/range at once.
+/// CoinGecko returns hourly points up to 90 days, daily points beyond.
+pub const MAX_CHART_RANGE: TimeDelta = TimeDelta::days(90);
+
 pub struct CoingeckoClient {
     pub base_url: String,
     pub http_client: reqwest::Client,
@@ -45,6 +49,46 @@ impl CoingeckoClient {
 
         Ok(prices_out)
     }
+
+    /// Prices of `coin_id` in `vs_asset_id` over `[from, to)`, split into chunks of [`MAX_CHART_RANGE`]
+    pub async fn fetch_market_chart_range(
+        &self,
+        coin_id: &str,
+        vs_asset_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<AssetPricePoint>> {
+        let mut prices_out = Vec::new();
+        for (chunk_from, chunk_to) in utils::time::windows(from, to, MAX_CHART_RANGE) {
+            let req = self
+                .get(&format!("/coins/{coin_id}/market_chart/range"))
+                .query(&[("vs_currency", vs_asset_id)])
+                .query(&[
+                    ("from", chunk_from.timestamp()),
+                    ("to", chunk_to.timestamp()),
+                ]);
+            let resp = req.fetch_json::<MarketChartResp>().await?;
+            prices_out.extend(resp.to_price_points(coin_id, vs_asset_id));
+        }
+        // chunk bounds overlap by a point
+        prices_out.dedup_by_key(|p| p.time);
+        Ok(prices_out)
+    }
+
+    /// Prices of `coin_id` at 00:00 UTC on `date`, in every currency CoinGecko quotes.
+    /// `None` when CoinGecko has no market data for that day, i.e. the coin wasn't listed yet.
+    pub async fn fetch_history(
+        &self,
+        coin_id: &str,
+        date: NaiveDate,
+    ) -> anyhow::Result<Option<Vec<AssetPricePoint>>> {
+        let req = self.get(&format!("/coins/{coin_id}/history")).query(&[
+            ("date", date.format("%d-%m-%Y").to_string()),
+            ("localization", "false".to_string()),
+        ]);
+        let resp = req.fetch_json::<HistoryResp>().await?;
+        Ok(resp.to_price_points(coin_id, date))
+    }
 }
 
 #[derive(Deserialize, thiserror::Error, Debug)]
@@ -75,6 +119,9 @@ pub mod payloads {
         }
     }
     impl CurrentPriceReq {
+        pub fn new(ids: Vec<String>, vs_assets: Vec<String>) -> Self {
+            CurrentPriceReq { ids, vs_assets }.or_default()
+        }
         pub fn or_default(self) -> Self {
             Self {
                 ids: self.ids.or(Self::default().ids),
@@ -85,11 +132,65 @@ pub mod payloads {
 
     #[derive(Deserialize, Debug)]
     pub struct CurrentPriceResp(pub HashMap<String, HashMap<String, f64>>);
+
+    /// Response of /coins/{id}/market_chart/range, points are `[unix_ms, value]`
+    #[derive(Deserialize, Debug)]
+    pub struct MarketChartResp {
+        pub prices: Vec<(f64, f64)>,
+    }
+    impl MarketChartResp {
+        pub fn to_price_points(&self, coin_id: &str, vs_asset_id: &str) -> Vec<AssetPricePoint> {
+            self.prices
+                .iter()
+                .filter_map(|&(ms, price)| {
+                    Some(AssetPricePoint {
+                        asset_id: coin_id.to_string(),
+                        vs_asset_id: vs_asset_id.to_string(),
+                        price,
+                        time: DateTime::from_timestamp_millis(ms as i64)?,
+                    })
+                })
+                .collect()
+        }
+    }
+
+    /// Response of /coins/{id}/history, `market_data` is missing for days before the coin was listed
+    #[derive(Deserialize, Debug)]
+    pub struct HistoryResp {
+        pub id: String,
+        pub market_data: Option<HistoryMarketData>,
+    }
+    #[derive(Deserialize, Debug)]
+    pub struct HistoryMarketData {
+        pub current_price: HashMap<String, f64>,
+    }
+    impl HistoryResp {
+        pub fn to_price_points(
+            &self,
+            coin_id: &str,
+            date: NaiveDate,
+        ) -> Option<Vec<AssetPricePoint>> {
+            let market_data = self.market_data.as_ref()?;
+            let time = date.and_time(chrono::NaiveTime::MIN).and_utc();
+            let points = market_data
+                .current_price
+                .iter()
+                .map(|(vs_asset_id, &price)| AssetPricePoint {
+                    asset_id: coin_id.to_string(),
+                    vs_asset_id: vs_asset_id.clone(),
+                    price,
+                    time,
+                })
+                .collect();
+            Some(points)
+        }
+    }
 }
 
 pub mod models {
     use chrono::{DateTime, Utc};
 
+    #[derive(Debug, Clone)]
     pub struct AssetPricePoint {
         pub asset_id: String,
         pub vs_asset_id: String,
@@ -121,3 +222,49 @@ pub mod local_utils {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::payloads::{HistoryResp, MarketChartResp};
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    #[test]
+    fn test_serde_market_chart() -> anyhow::Result<()> {
+        let json = r#"{
+            "prices": [[1713484800000, 3065.12], [1713488400000, 3071.5]],
+            "market_caps": [[1713484800000, 368000000000.0]],
+            "total_volumes": [[1713484800000, 19000000000.0]]
+        }"#;
+        let resp: MarketChartResp = serde_json::from_str(json)?;
+        let points = resp.to_price_points("ethereum", "usd");
+        assert_eq!(points.len(), 2);
+        assert_eq!(
+            points[0].time,
+            Utc.with_ymd_and_hms(2024, 4, 19, 0, 0, 0).unwrap()
+        );
+        assert_eq!(points[1].price, 3071.5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serde_history() -> anyhow::Result<()> {
+        let date = NaiveDate::from_ymd_opt(2024, 4, 19).unwrap();
+        let json = r#"{
+            "id": "ethereum", "symbol": "eth", "name": "Ethereum",
+            "market_data": { "current_price": { "usd": 3065.12, "eur": 2875.4 }, "market_cap": { "usd": 1.0 } }
+        }"#;
+        let resp: HistoryResp = serde_json::from_str(json)?;
+        let points = resp.to_price_points("ethereum", date).unwrap();
+        assert_eq!(points.len(), 2);
+        assert!(
+            points
+                .iter()
+                .all(|p| p.time == Utc.with_ymd_and_hms(2024, 4, 19, 0, 0, 0).unwrap())
+        );
+
+        // not listed yet on that day
+        let resp: HistoryResp = serde_json::from_str(r#"{ "id": "ethereum" }"#)?;
+        assert!(resp.to_price_points("ethereum", date).is_none());
+        Ok(())
+    }
+}
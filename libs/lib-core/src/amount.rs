@@ -0,0 +1,186 @@
+//! Signed fixed-point amounts expressed in an asset's smallest unit.
+
+use crate::types::AssetId;
+use serde::{Deserialize, Serialize};
+
+/// Quantity of an asset in base units (wei, satoshis, ...).
+/// Positive for inflows, negative for outflows.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Amount {
+    pub asset_id: AssetId,
+    /// Serialized as a decimal string since JSON numbers can't hold every i128
+    #[serde(with = "units_as_str")]
+    pub units: i128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AmountError {
+    #[error("cannot combine amounts of different assets: {0:?} and {1:?}")]
+    AssetMismatch(AssetId, AssetId),
+    #[error("amount overflow")]
+    Overflow,
+    #[error("invalid amount: {0}")]
+    Invalid(String),
+}
+
+impl Amount {
+    pub fn new(asset_id: AssetId, units: i128) -> Self {
+        Amount { asset_id, units }
+    }
+
+    pub fn zero(asset_id: AssetId) -> Self {
+        Amount::new(asset_id, 0)
+    }
+
+    /// Converts a floating point quantity (as returned by most APIs) to base units
+    pub fn from_f64(asset_id: AssetId, value: f64, decimals: u8) -> Result<Self, AmountError> {
+        let scaled = (value * 10f64.powi(decimals as i32)).round();
+        if !scaled.is_finite() || scaled.abs() >= i128::MAX as f64 {
+            return Err(AmountError::Invalid(value.to_string()));
+        }
+        Ok(Amount::new(asset_id, scaled as i128))
+    }
+
+    /// Parses a decimal string such as "-12.345" exactly, without going through f64
+    pub fn from_decimal_str(asset_id: AssetId, s: &str, decimals: u8) -> Result<Self, AmountError> {
+        let invalid = || AmountError::Invalid(s.to_string());
+        let trimmed = s.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+        if int_part.is_empty() && frac_part.is_empty()
+            || !int_part
+                .chars()
+                .chain(frac_part.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        // extra fractional digits beyond the asset's precision must be zeroes
+        let (frac_kept, frac_dropped) = frac_part.split_at(frac_part.len().min(decimals as usize));
+        if frac_dropped.chars().any(|c| c != '0') {
+            return Err(invalid());
+        }
+
+        let mut units: i128 = 0;
+        let padding = std::iter::repeat_n('0', decimals as usize - frac_kept.len());
+        for c in int_part.chars().chain(frac_kept.chars()).chain(padding) {
+            let digit = c.to_digit(10).ok_or_else(invalid)? as i128;
+            units = units
+                .checked_mul(10)
+                .and_then(|u| u.checked_add(digit))
+                .ok_or(AmountError::Overflow)?;
+        }
+        Ok(Amount::new(asset_id, if negative { -units } else { units }))
+    }
+
+    /// Lossy conversion to a human-scale quantity, for display and pricing
+    pub fn to_f64(&self, decimals: u8) -> f64 {
+        self.units as f64 / 10f64.powi(decimals as i32)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.units == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.units < 0
+    }
+
+    pub fn checked_add(&self, other: &Amount) -> Result<Amount, AmountError> {
+        self.ensure_same_asset(other)?;
+        let units = self
+            .units
+            .checked_add(other.units)
+            .ok_or(AmountError::Overflow)?;
+        Ok(Amount::new(self.asset_id.clone(), units))
+    }
+
+    pub fn checked_sub(&self, other: &Amount) -> Result<Amount, AmountError> {
+        self.checked_add(&other.checked_neg()?)
+    }
+
+    pub fn checked_neg(&self) -> Result<Amount, AmountError> {
+        let units = self.units.checked_neg().ok_or(AmountError::Overflow)?;
+        Ok(Amount::new(self.asset_id.clone(), units))
+    }
+
+    pub fn checked_abs(&self) -> Result<Amount, AmountError> {
+        let units = self.units.checked_abs().ok_or(AmountError::Overflow)?;
+        Ok(Amount::new(self.asset_id.clone(), units))
+    }
+
+    fn ensure_same_asset(&self, other: &Amount) -> Result<(), AmountError> {
+        if self.asset_id != other.asset_id {
+            return Err(AmountError::AssetMismatch(
+                self.asset_id.clone(),
+                other.asset_id.clone(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+mod units_as_str {
+    use serde::{Deserialize, Deserializer, Serializer, de};
+
+    pub fn serialize<S: Serializer>(units: &i128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(units)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i128, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_decimal_str() -> anyhow::Result<()> {
+        let eth = |s| Amount::from_decimal_str(AssetId::from("ETH"), s, 18);
+        assert_eq!(eth("1.5")?.units, 1_500_000_000_000_000_000);
+        assert_eq!(eth("-0.000000000000000001")?.units, -1);
+        assert_eq!(eth("42")?.units, 42_000_000_000_000_000_000);
+        assert_eq!(
+            eth("1.10000000000000000000")?.units,
+            1_100_000_000_000_000_000
+        );
+        assert!(eth("1.0000000000000000001").is_err());
+        assert!(eth("1e18").is_err());
+        assert!(eth("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checked_arithmetic() -> anyhow::Result<()> {
+        let a = Amount::new(AssetId::from("ETH"), 10);
+        let b = Amount::new(AssetId::from("ETH"), 25);
+        assert_eq!(a.checked_sub(&b)?, Amount::new(AssetId::from("ETH"), -15));
+        assert_eq!(
+            Amount::new(AssetId::from("ETH"), i128::MAX).checked_add(&a),
+            Err(AmountError::Overflow)
+        );
+        assert!(matches!(
+            a.checked_add(&Amount::new(AssetId::from("BTC"), 1)),
+            Err(AmountError::AssetMismatch(..))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serde_units_as_string() -> anyhow::Result<()> {
+        let amount = Amount::new(AssetId::from("ETH"), -(u64::MAX as i128) * 1000);
+        let json = serde_json::to_string(&amount)?;
+        assert_eq!(
+            json,
+            r#"{"asset_id":"ETH","units":"-18446744073709551615000"}"#
+        );
+        assert_eq!(serde_json::from_str::<Amount>(&json)?, amount);
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+//! Registry of known assets and the symbols each provider uses for them.
+
+use crate::types::{Asset, AssetId, ExternalAssetId, ProviderId};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Assets known out of the box, see `data/assets.toml`
+const BUILTIN_ASSETS: &str = include_str!("../data/assets.toml");
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AssetError {
+    #[error("unknown asset {symbol:?} on {}", provider.0)]
+    UnknownSymbol {
+        provider: ProviderId,
+        symbol: String,
+    },
+    #[error("unknown asset {0:?}")]
+    UnknownAsset(AssetId),
+    #[error("asset {0:?} is defined twice")]
+    Duplicate(AssetId),
+    #[error("{asset_id:?} is already mapped to {existing:?}, cannot map it to {new:?}")]
+    ConflictingExternalId {
+        asset_id: AssetId,
+        existing: ExternalAssetId,
+        new: ExternalAssetId,
+    },
+    #[error("{external_id:?} already resolves to {asset_id:?}")]
+    ExternalIdTaken {
+        external_id: ExternalAssetId,
+        asset_id: AssetId,
+    },
+    #[error("invalid asset registry: {0}")]
+    Parse(String),
+}
+
+/// Every asset we know about, with its decimals and provider symbols.
+/// Adapters resolve provider symbols through it rather than keeping their own tables.
+#[derive(Debug, Clone, Default)]
+pub struct AssetRegistry {
+    assets: HashMap<AssetId, Asset>,
+    /// Reverse index of every asset's external ids
+    by_external_id: HashMap<ExternalAssetId, AssetId>,
+}
+
+/// Layout of the registry data file
+#[derive(Deserialize)]
+struct RegistryFile {
+    assets: Vec<AssetEntry>,
+}
+#[derive(Deserialize)]
+struct AssetEntry {
+    id: AssetId,
+    chain_id: String,
+    decimals: u8,
+    /// Provider id to the provider's symbol for this asset
+    #[serde(default)]
+    external_ids: BTreeMap<String, String>,
+}
+
+impl AssetRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The registry shipped with this crate
+    pub fn builtin() -> Self {
+        AssetRegistry::from_toml(BUILTIN_ASSETS).expect("builtin asset registry is valid")
+    }
+
+    /// Loads a registry data file, see `data/assets.toml` for the format
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("reading {}: {e}", path.display()))?;
+        Ok(AssetRegistry::from_toml(&content)?)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, AssetError> {
+        let file: RegistryFile =
+            toml::from_str(content).map_err(|e| AssetError::Parse(e.to_string()))?;
+        let mut registry = AssetRegistry::new();
+        for entry in file.assets {
+            let external_ids = entry
+                .external_ids
+                .iter()
+                .map(|(provider, symbol)| {
+                    let provider = ProviderId::from(provider.as_str());
+                    let ext_id = ExternalAssetId::with_issuer(&provider, symbol);
+                    (provider, ext_id)
+                })
+                .collect();
+            registry.insert(Asset {
+                id: entry.id,
+                chain_id: entry.chain_id,
+                decimals: entry.decimals,
+                external_ids,
+            })?;
+        }
+        Ok(registry)
+    }
+
+    pub fn insert(&mut self, asset: Asset) -> Result<(), AssetError> {
+        if self.assets.contains_key(&asset.id) {
+            return Err(AssetError::Duplicate(asset.id));
+        }
+        self.index_external_ids(&asset)?;
+        self.assets.insert(asset.id.clone(), asset);
+        Ok(())
+    }
+
+    /// Inserts `asset`, or merges its external ids into the known asset with the same id
+    pub fn upsert(&mut self, asset: Asset) -> Result<(), AssetError> {
+        let Some(known) = self.assets.get(&asset.id) else {
+            return self.insert(asset);
+        };
+        let mut merged = known.clone();
+        merged.merge(&asset)?;
+        self.index_external_ids(&merged)?;
+        self.assets.insert(merged.id.clone(), merged);
+        Ok(())
+    }
+
+    /// Checks no other asset claims the same external ids before indexing them
+    fn index_external_ids(&mut self, asset: &Asset) -> Result<(), AssetError> {
+        for ext_id in asset.external_ids.values() {
+            if let Some(asset_id) = self.by_external_id.get(ext_id)
+                && *asset_id != asset.id
+            {
+                return Err(AssetError::ExternalIdTaken {
+                    external_id: ext_id.clone(),
+                    asset_id: asset_id.clone(),
+                });
+            }
+        }
+        for ext_id in asset.external_ids.values() {
+            self.by_external_id.insert(ext_id.clone(), asset.id.clone());
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, asset_id: &AssetId) -> Result<&Asset, AssetError> {
+        self.assets
+            .get(asset_id)
+            .ok_or_else(|| AssetError::UnknownAsset(asset_id.clone()))
+    }
+
+    /// The asset an external id refers to
+    pub fn lookup(&self, external_id: &ExternalAssetId) -> Option<&AssetId> {
+        self.by_external_id.get(external_id)
+    }
+
+    /// The asset `provider` calls `symbol`
+    pub fn resolve(&self, provider: &ProviderId, symbol: &str) -> Result<&Asset, AssetError> {
+        let external_id = ExternalAssetId::with_issuer(provider, symbol);
+        let unknown = || AssetError::UnknownSymbol {
+            provider: provider.clone(),
+            symbol: symbol.to_string(),
+        };
+        let asset_id = self.lookup(&external_id).ok_or_else(unknown)?;
+        self.assets.get(asset_id).ok_or_else(unknown)
+    }
+
+    /// The symbol `provider` uses for `asset_id`, if it lists that asset
+    pub fn external_id(&self, asset_id: &AssetId, provider: &ProviderId) -> Option<&str> {
+        let asset = self.assets.get(asset_id)?;
+        asset
+            .external_ids
+            .get(provider)
+            .map(|ext_id| ext_id.id.as_str())
+    }
+
+    pub fn assets(&self) -> impl Iterator<Item = &Asset> {
+        self.assets.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_provider_symbols() -> anyhow::Result<()> {
+        let registry = AssetRegistry::builtin();
+        let (nexo, coingecko) = (ProviderId::from("nexo"), ProviderId::from("coingecko"));
+
+        let eth = registry.resolve(&nexo, "ETH")?;
+        assert_eq!(eth.id, AssetId::from("ETH"));
+        assert_eq!(eth.decimals, 18);
+        assert_eq!(registry.resolve(&coingecko, "ethereum")?.id, eth.id);
+        assert_eq!(registry.external_id(&eth.id, &coingecko), Some("ethereum"));
+        assert_eq!(eth.amount_from_f64(1.5)?.units, 1_500_000_000_000_000_000);
+
+        assert!(matches!(
+            registry.resolve(&coingecko, "ETH"),
+            Err(AssetError::UnknownSymbol { .. })
+        ));
+        Ok(())
+    }
+
+    fn asset(id: &str, ext_ids: &[(&str, &str)]) -> Asset {
+        let external_ids = ext_ids
+            .iter()
+            .map(|(provider, symbol)| {
+                let provider = ProviderId::from(*provider);
+                (
+                    provider.clone(),
+                    ExternalAssetId::with_issuer(&provider, symbol),
+                )
+            })
+            .collect();
+        Asset {
+            id: AssetId::from(id),
+            chain_id: "bitcoin".to_string(),
+            decimals: 8,
+            external_ids,
+        }
+    }
+
+    #[test]
+    fn test_external_ids_are_provider_scoped() {
+        let binance_btc = ExternalAssetId::with_issuer(&ProviderId::from("binance"), "BTC");
+        let nexo_btc = ExternalAssetId::with_issuer(&ProviderId::from("nexo"), "BTC");
+        assert_ne!(binance_btc, nexo_btc);
+        let index = HashMap::from([(binance_btc.clone(), 1), (nexo_btc, 2)]);
+        assert_eq!(index[&binance_btc], 1);
+
+        let registry = AssetRegistry::builtin();
+        assert_eq!(registry.lookup(&binance_btc), Some(&AssetId::from("BTC")));
+        let coingecko_btc = ExternalAssetId::with_issuer(&ProviderId::from("coingecko"), "BTC");
+        assert_eq!(registry.lookup(&coingecko_btc), None);
+    }
+
+    #[test]
+    fn test_merge_detects_conflicts() -> anyhow::Result<()> {
+        let mut btc = asset("BTC", &[("binance", "BTC")]);
+        btc.merge(&asset("BTC", &[("binance", "BTC"), ("nexo", "BTC")]))?;
+        assert_eq!(btc.external_ids.len(), 2);
+        assert!(matches!(
+            btc.merge(&asset("BTC", &[("nexo", "XBT")])),
+            Err(AssetError::ConflictingExternalId { .. })
+        ));
+
+        let mut registry = AssetRegistry::new();
+        registry.insert(btc)?;
+        registry.upsert(asset("BTC", &[("coingecko", "bitcoin")]))?;
+        assert_eq!(
+            registry
+                .resolve(&ProviderId::from("coingecko"), "bitcoin")?
+                .id,
+            AssetId::from("BTC")
+        );
+        assert!(matches!(
+            registry.insert(asset("WBTC", &[("binance", "BTC")])),
+            Err(AssetError::ExternalIdTaken { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_duplicate_assets() {
+        let toml = r#"
+            [[assets]]
+            id = "ETH"
+            chain_id = "ethereum"
+            decimals = 18
+
+            [[assets]]
+            id = "ETH"
+            chain_id = "arbitrum"
+            decimals = 18
+        "#;
+        assert_eq!(
+            AssetRegistry::from_toml(toml).unwrap_err(),
+            AssetError::Duplicate(AssetId::from("ETH"))
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Replays the transaction log to derive the balance of every account over time.
+
+use crate::amount::{Amount, AmountError};
+use crate::types::{AccountId, Transaction};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// Balance of an account right after a transaction touched it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalancePoint {
+    pub datetime: DateTime<Utc>,
+    pub delta: Amount,
+    pub balance: Amount,
+}
+
+/// A holding account dipping below zero, which usually means history is missing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegativeBalance {
+    pub account_id: AccountId,
+    pub datetime: DateTime<Utc>,
+    pub balance: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ReplayError {
+    #[error("transactions must be sorted by date: {current} comes after {previous}")]
+    OutOfOrder {
+        previous: DateTime<Utc>,
+        current: DateTime<Utc>,
+    },
+    #[error(transparent)]
+    Amount(#[from] AmountError),
+}
+
+/// Running balances of every account touched by a transaction log
+#[derive(Debug, Clone, Default)]
+pub struct BalanceReplay {
+    history: HashMap<AccountId, Vec<BalancePoint>>,
+    negative_balances: Vec<NegativeBalance>,
+    last_datetime: Option<DateTime<Utc>>,
+}
+
+impl BalanceReplay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replays transactions sorted by date, oldest first
+    pub fn replay<'a>(
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Result<Self, ReplayError> {
+        let mut replay = BalanceReplay::new();
+        for tx in transactions {
+            replay.apply(tx)?;
+        }
+        Ok(replay)
+    }
+
+    /// Applies the next transaction of the log. Its effects are dated at the transaction's time.
+    pub fn apply(&mut self, tx: &Transaction) -> Result<(), ReplayError> {
+        if let Some(previous) = self.last_datetime
+            && tx.datetime < previous
+        {
+            return Err(ReplayError::OutOfOrder {
+                previous,
+                current: tx.datetime,
+            });
+        }
+        self.last_datetime = Some(tx.datetime);
+
+        for effect in tx.effects() {
+            let points = self.history.entry(effect.account_id.clone()).or_default();
+            let balance = match points.last() {
+                Some(last) => last.balance.checked_add(&effect.amount)?,
+                None => effect.amount.clone(),
+            };
+            let was_negative = points.last().is_some_and(|p| p.balance.is_negative());
+            if balance.is_negative() && !was_negative && !effect.account_id.is_counter_account() {
+                self.negative_balances.push(NegativeBalance {
+                    account_id: effect.account_id.clone(),
+                    datetime: tx.datetime,
+                    balance: balance.clone(),
+                });
+            }
+            points.push(BalancePoint {
+                datetime: tx.datetime,
+                delta: effect.amount.clone(),
+                balance,
+            });
+        }
+        Ok(())
+    }
+
+    /// Balance of `account_id` at `at`, including transactions at that exact time.
+    /// None if the account had no activity yet.
+    pub fn balance_at(&self, account_id: &AccountId, at: DateTime<Utc>) -> Option<&Amount> {
+        let points = self.history.get(account_id)?;
+        let idx = points.partition_point(|p| p.datetime <= at);
+        idx.checked_sub(1).map(|i| &points[i].balance)
+    }
+
+    /// Balances at `at` of every account active by then
+    pub fn balances_at(&self, at: DateTime<Utc>) -> HashMap<AccountId, Amount> {
+        self.history
+            .keys()
+            .filter_map(|account_id| {
+                let balance = self.balance_at(account_id, at)?;
+                Some((account_id.clone(), balance.clone()))
+            })
+            .collect()
+    }
+
+    /// Latest balance of every account
+    pub fn current_balances(&self) -> HashMap<AccountId, Amount> {
+        self.history
+            .iter()
+            .filter_map(|(account_id, points)| {
+                let last = points.last()?;
+                Some((account_id.clone(), last.balance.clone()))
+            })
+            .collect()
+    }
+
+    /// Every balance change of `account_id`, oldest first
+    pub fn history(&self, account_id: &AccountId) -> &[BalancePoint] {
+        self.history.get(account_id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = &AccountId> {
+        self.history.keys()
+    }
+
+    /// Times a holding account went negative
+    pub fn negative_balances(&self) -> &[NegativeBalance] {
+        &self.negative_balances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AssetId, ProviderId, TransactionId, TxEffect};
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 4, d, 0, 0, 0).unwrap()
+    }
+
+    /// Moves `units` of ETH from `from` to `to` on day `d`
+    fn transfer(from: &AccountId, to: &AccountId, units: i128, d: u32) -> Transaction {
+        let effect = |account_id: &AccountId, units| TxEffect {
+            account_id: account_id.clone(),
+            amount: Amount::new(AssetId::from("ETH"), units),
+            datetime: day(d),
+        };
+        Transaction {
+            id: TransactionId::default(),
+            tx_hash: None,
+            inputs: vec![effect(from, -units)],
+            outputs: vec![effect(to, units)],
+            datetime: day(d),
+        }
+    }
+
+    #[test]
+    fn test_replay_balances() -> anyhow::Result<()> {
+        let nexo = ProviderId::from("nexo");
+        let outside = AccountId::external(nexo.clone(), AssetId::from("ETH"));
+        let savings = AccountId::new(nexo.clone(), AssetId::from("ETH"));
+        let income = AccountId::income(nexo, AssetId::from("ETH"));
+
+        let txs = [
+            transfer(&outside, &savings, 100, 1),
+            transfer(&income, &savings, 5, 10),
+            transfer(&savings, &outside, 30, 20),
+        ];
+        let replay = BalanceReplay::replay(&txs)?;
+
+        assert_eq!(
+            replay.balance_at(&savings, day(1)).map(|a| a.units),
+            Some(100)
+        );
+        assert_eq!(
+            replay.balance_at(&savings, day(15)).map(|a| a.units),
+            Some(105)
+        );
+        assert_eq!(replay.balance_at(&income, day(5)), None);
+        assert_eq!(replay.current_balances()[&savings].units, 75);
+        assert_eq!(replay.current_balances()[&outside].units, -70);
+        assert_eq!(replay.balances_at(day(5)).len(), 2);
+        assert_eq!(replay.history(&savings).len(), 3);
+        // counter-accounts going negative is expected
+        assert!(replay.negative_balances().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_detects_negative_balance_and_unsorted_input() {
+        let nexo = ProviderId::from("nexo");
+        let outside = AccountId::external(nexo.clone(), AssetId::from("ETH"));
+        let savings = AccountId::new(nexo, AssetId::from("ETH"));
+
+        let replay = BalanceReplay::replay(&[transfer(&savings, &outside, 10, 2)]).unwrap();
+        assert_eq!(replay.negative_balances().len(), 1);
+        assert_eq!(replay.negative_balances()[0].account_id, savings);
+
+        let unsorted = [
+            transfer(&outside, &savings, 10, 2),
+            transfer(&outside, &savings, 10, 1),
+        ];
+        assert!(matches!(
+            BalanceReplay::replay(&unsorted),
+            Err(ReplayError::OutOfOrder { .. })
+        ));
+    }
+}
@@ -0,0 +1,696 @@
+//! Cost basis of holdings: lots valued at acquisition from historical prices, consumed by
+//! disposals with a selectable method, giving realized and unrealized gains.
+//!
+//! Lots are pooled per asset across providers, so transfers between tracked accounts must be
+//! merged beforehand (see [`crate::merge_transfers`]) not to count as disposals.
+
+use crate::amount::{Amount, AmountError};
+use crate::assets::AssetRegistry;
+use crate::pricing::PriceBook;
+use crate::types::{AccountKind, AssetId, Transaction, TransactionId};
+use crate::valuation::UnpricedError;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum CostBasisError {
+    #[error("unknown cost basis method {0:?}, expected fifo, lifo, hifo or average")]
+    UnknownMethod(String),
+    #[error(transparent)]
+    Amount(#[from] AmountError),
+}
+
+/// Which lots a disposal consumes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CostBasisMethod {
+    /// Oldest lots first
+    #[default]
+    Fifo,
+    /// Newest lots first
+    Lifo,
+    /// Lots with the highest unit cost first
+    Hifo,
+    /// Oldest lots first, all units costing the pool's average
+    Average,
+}
+
+impl CostBasisMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CostBasisMethod::Fifo => "fifo",
+            CostBasisMethod::Lifo => "lifo",
+            CostBasisMethod::Hifo => "hifo",
+            CostBasisMethod::Average => "average",
+        }
+    }
+}
+
+impl FromStr for CostBasisMethod {
+    type Err = CostBasisError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fifo" => Ok(CostBasisMethod::Fifo),
+            "lifo" => Ok(CostBasisMethod::Lifo),
+            "hifo" => Ok(CostBasisMethod::Hifo),
+            "average" | "avg" => Ok(CostBasisMethod::Average),
+            _ => Err(CostBasisError::UnknownMethod(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcquisitionKind {
+    /// Bought with another asset
+    Purchase,
+    /// Interest, rewards, cashback...
+    Income,
+    /// Received from outside the tracked accounts
+    Deposit,
+}
+
+impl AcquisitionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AcquisitionKind::Purchase => "purchase",
+            AcquisitionKind::Income => "income",
+            AcquisitionKind::Deposit => "deposit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposalKind {
+    /// Traded for another asset
+    Sale,
+    /// Paid as a fee
+    Fee,
+    /// Sent outside the tracked accounts
+    Withdrawal,
+}
+
+impl DisposalKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisposalKind::Sale => "sale",
+            DisposalKind::Fee => "fee",
+            DisposalKind::Withdrawal => "withdrawal",
+        }
+    }
+}
+
+/// Units of an asset acquired by a transaction and still held
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lot {
+    pub tx_id: TransactionId,
+    pub acquired_at: DateTime<Utc>,
+    pub kind: AcquisitionKind,
+    /// Units still held
+    pub amount: Amount,
+    /// Cost of the units still held, in the quote asset
+    pub cost: f64,
+}
+
+impl Lot {
+    fn unit_cost(&self) -> f64 {
+        self.cost / self.amount.units as f64
+    }
+}
+
+/// An asset entering the tracked accounts, valued in the quote asset
+#[derive(Debug, Clone, PartialEq)]
+pub struct Acquisition {
+    pub tx_id: TransactionId,
+    pub datetime: DateTime<Utc>,
+    pub kind: AcquisitionKind,
+    pub amount: Amount,
+    pub value: f64,
+}
+
+/// The part of a lot a disposal consumed
+#[derive(Debug, Clone, PartialEq)]
+pub struct LotSlice {
+    /// The transaction that acquired the lot
+    pub tx_id: TransactionId,
+    pub acquired_at: DateTime<Utc>,
+    pub amount: Amount,
+    pub cost: f64,
+}
+
+/// An asset leaving the tracked accounts, with the lots it consumed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disposal {
+    pub tx_id: TransactionId,
+    pub datetime: DateTime<Utc>,
+    pub kind: DisposalKind,
+    pub amount: Amount,
+    pub proceeds: f64,
+    pub cost: f64,
+    pub lots: Vec<LotSlice>,
+    /// Units disposed of beyond the held lots, history is likely missing. They cost nothing.
+    pub uncovered: Amount,
+}
+
+impl Disposal {
+    pub fn gain(&self) -> f64 {
+        self.proceeds - self.cost
+    }
+}
+
+/// An open lot valued at a point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnrealizedGain {
+    pub lot: Lot,
+    /// None if the asset couldn't be priced
+    pub value: Option<f64>,
+    /// Why the lot couldn't be valued
+    pub unpriced: Option<UnpricedError>,
+}
+
+impl UnrealizedGain {
+    pub fn gain(&self) -> Option<f64> {
+        self.value.map(|value| value - self.lot.cost)
+    }
+}
+
+/// Lots, acquisitions and disposals of a transaction log, valued in `vs_asset_id`
+#[derive(Debug, Clone)]
+pub struct CostBasis {
+    pub method: CostBasisMethod,
+    pub vs_asset_id: AssetId,
+    pub acquisitions: Vec<Acquisition>,
+    pub disposals: Vec<Disposal>,
+    /// Acquisitions and disposals that couldn't be priced or are in unregistered assets,
+    /// valued at zero
+    pub unpriced: Vec<UnpricedError>,
+    /// Open lots of each asset, in acquisition order
+    lots: HashMap<AssetId, Vec<Lot>>,
+}
+
+impl CostBasis {
+    pub fn new(method: CostBasisMethod, vs_asset_id: AssetId) -> Self {
+        CostBasis {
+            method,
+            vs_asset_id,
+            acquisitions: Vec::new(),
+            disposals: Vec::new(),
+            unpriced: Vec::new(),
+            lots: HashMap::new(),
+        }
+    }
+
+    /// Applies transactions sorted by date, oldest first
+    pub fn compute<'a>(
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+        method: CostBasisMethod,
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+        vs_asset_id: &AssetId,
+    ) -> Result<Self, CostBasisError> {
+        let mut cost_basis = CostBasis::new(method, vs_asset_id.clone());
+        for tx in transactions {
+            cost_basis.apply(tx, assets, prices)?;
+        }
+        Ok(cost_basis)
+    }
+
+    /// Turns what a transaction adds to the tracked accounts into lots, and what it takes
+    /// out into disposals. Moves between tracked accounts cancel out. Trades against the
+    /// quote asset are valued at the traded amount, everything else at the market price.
+    pub fn apply(
+        &mut self,
+        tx: &Transaction,
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+    ) -> Result<(), CostBasisError> {
+        let mut net: BTreeMap<AssetId, Amount> = BTreeMap::new();
+        for effect in tx.effects() {
+            if effect.account_id.is_counter_account() {
+                continue;
+            }
+            let asset_id = &effect.amount.asset_id;
+            let total = net
+                .entry(asset_id.clone())
+                .or_insert_with(|| Amount::zero(asset_id.clone()));
+            *total = total.checked_add(&effect.amount)?;
+        }
+        net.retain(|_, amount| !amount.is_zero());
+        let quote = net.remove(&self.vs_asset_id);
+        let quote_value = match (&quote, net.len()) {
+            (Some(quote), 1) => match assets.get(&self.vs_asset_id) {
+                Ok(asset) => Some(quote.checked_abs()?.to_f64(asset.decimals)),
+                Err(err) => {
+                    self.unpriced.push(err.into());
+                    None
+                }
+            },
+            _ => None,
+        };
+        let acquires = net.values().chain(&quote).any(|a| !a.is_negative());
+        let disposes = net.values().chain(&quote).any(Amount::is_negative);
+
+        for (asset_id, amount) in net {
+            let units = amount.checked_abs()?;
+            let value = match quote_value {
+                Some(value) => value,
+                None => self.market_value(&units, assets, prices, tx.datetime),
+            };
+            if amount.is_negative() {
+                // a fee paid in a third asset is still a fee when the transaction is a trade
+                let kind = if only_fees(tx, &asset_id) {
+                    DisposalKind::Fee
+                } else if acquires {
+                    DisposalKind::Sale
+                } else {
+                    DisposalKind::Withdrawal
+                };
+                self.dispose(tx, kind, units, value);
+            } else {
+                let income = tx.effects().any(|effect| {
+                    effect.account_id.kind == AccountKind::Income
+                        && effect.amount.asset_id == asset_id
+                });
+                let kind = if income {
+                    AcquisitionKind::Income
+                } else if disposes {
+                    AcquisitionKind::Purchase
+                } else {
+                    AcquisitionKind::Deposit
+                };
+                self.acquire(tx, kind, units, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn market_value(
+        &mut self,
+        amount: &Amount,
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+        datetime: DateTime<Utc>,
+    ) -> f64 {
+        match value_at(amount, assets, prices, &self.vs_asset_id, datetime) {
+            Ok(value) => value,
+            Err(err) => {
+                self.unpriced.push(err);
+                0.0
+            }
+        }
+    }
+
+    fn acquire(&mut self, tx: &Transaction, kind: AcquisitionKind, amount: Amount, value: f64) {
+        self.acquisitions.push(Acquisition {
+            tx_id: tx.id.clone(),
+            datetime: tx.datetime,
+            kind,
+            amount: amount.clone(),
+            value,
+        });
+        self.lots
+            .entry(amount.asset_id.clone())
+            .or_default()
+            .push(Lot {
+                tx_id: tx.id.clone(),
+                acquired_at: tx.datetime,
+                kind,
+                amount,
+                cost: value,
+            });
+    }
+
+    fn dispose(&mut self, tx: &Transaction, kind: DisposalKind, amount: Amount, proceeds: f64) {
+        let asset_id = amount.asset_id.clone();
+        let lots = self.lots.entry(asset_id.clone()).or_default();
+        if self.method == CostBasisMethod::Average {
+            let units = lots.iter().map(|lot| lot.amount.units as f64).sum::<f64>();
+            let cost = lots.iter().map(|lot| lot.cost).sum::<f64>();
+            for lot in lots.iter_mut() {
+                lot.cost = cost * lot.amount.units as f64 / units;
+            }
+        }
+        let mut order = (0..lots.len()).collect::<Vec<_>>();
+        match self.method {
+            CostBasisMethod::Fifo | CostBasisMethod::Average => {}
+            CostBasisMethod::Lifo => order.reverse(),
+            CostBasisMethod::Hifo => {
+                order.sort_by(|a, b| lots[*b].unit_cost().total_cmp(&lots[*a].unit_cost()))
+            }
+        }
+
+        let mut left = amount.units;
+        let mut slices = Vec::new();
+        for idx in order {
+            if left == 0 {
+                break;
+            }
+            let lot = &mut lots[idx];
+            let take = left.min(lot.amount.units);
+            let cost = lot.cost * take as f64 / lot.amount.units as f64;
+            lot.cost -= cost;
+            lot.amount.units -= take;
+            left -= take;
+            slices.push(LotSlice {
+                tx_id: lot.tx_id.clone(),
+                acquired_at: lot.acquired_at,
+                amount: Amount::new(asset_id.clone(), take),
+                cost,
+            });
+        }
+        lots.retain(|lot| lot.amount.units > 0);
+
+        self.disposals.push(Disposal {
+            tx_id: tx.id.clone(),
+            datetime: tx.datetime,
+            kind,
+            amount,
+            proceeds,
+            cost: slices.iter().map(|slice| slice.cost).sum(),
+            lots: slices,
+            uncovered: Amount::new(asset_id, left),
+        });
+    }
+
+    /// Lots still held, by asset then acquisition date
+    pub fn open_lots(&self) -> Vec<&Lot> {
+        let mut lots = self.lots.values().flatten().collect::<Vec<_>>();
+        lots.sort_by(|a, b| {
+            (&a.amount.asset_id.0, a.acquired_at).cmp(&(&b.amount.asset_id.0, b.acquired_at))
+        });
+        lots
+    }
+
+    /// Open lots valued at `datetime`
+    pub fn unrealized(
+        &self,
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+        datetime: DateTime<Utc>,
+    ) -> Vec<UnrealizedGain> {
+        self.open_lots()
+            .into_iter()
+            .map(|lot| {
+                let value = value_at(&lot.amount, assets, prices, &self.vs_asset_id, datetime);
+                UnrealizedGain {
+                    lot: lot.clone(),
+                    value: value.as_ref().ok().copied(),
+                    unpriced: value.err(),
+                }
+            })
+            .collect()
+    }
+
+    /// Gains of every disposal
+    pub fn realized_gain(&self) -> f64 {
+        self.disposals
+            .iter()
+            .map(Disposal::gain)
+            .fold(0.0, |total, gain| total + gain)
+    }
+}
+
+/// Market value of `amount` in `vs_asset_id` at `datetime`
+fn value_at(
+    amount: &Amount,
+    assets: &AssetRegistry,
+    prices: &PriceBook,
+    vs_asset_id: &AssetId,
+    datetime: DateTime<Utc>,
+) -> Result<f64, UnpricedError> {
+    let decimals = assets.get(&amount.asset_id)?.decimals;
+    let quote = prices.price(&amount.asset_id, vs_asset_id, datetime)?;
+    Ok(amount.to_f64(decimals) * quote.price)
+}
+
+/// Whether `asset_id` only leaves to fee accounts
+fn only_fees(tx: &Transaction, asset_id: &AssetId) -> bool {
+    tx.outputs
+        .iter()
+        .filter(|effect| {
+            effect.account_id.is_counter_account() && &effect.amount.asset_id == asset_id
+        })
+        .all(|effect| effect.account_id.kind == AccountKind::Fee)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::AssetPricePoint;
+    use crate::types::{AccountId, ProviderId, TxEffect};
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 4, d, 0, 0, 0).unwrap()
+    }
+
+    fn amount(asset: &str, value: f64) -> Amount {
+        let decimals = AssetRegistry::builtin()
+            .get(&AssetId::from(asset))
+            .unwrap()
+            .decimals;
+        Amount::from_f64(AssetId::from(asset), value, decimals).unwrap()
+    }
+
+    /// A transaction moving each amount from an account to another on Nexo
+    fn tx(d: u32, moves: &[(AccountKind, AccountKind, Amount)]) -> Transaction {
+        let nexo = ProviderId::from("nexo");
+        let effect = |kind: &AccountKind, amount: Amount| TxEffect {
+            account_id: AccountId {
+                provider: nexo.clone(),
+                asset: amount.asset_id.clone(),
+                kind: kind.clone(),
+            },
+            amount,
+            datetime: day(d),
+        };
+        Transaction {
+            id: TransactionId::native(&nexo, d),
+            inputs: moves
+                .iter()
+                .map(|(from, _, amount)| effect(from, amount.checked_neg().unwrap()))
+                .collect(),
+            outputs: moves
+                .iter()
+                .map(|(_, to, amount)| effect(to, amount.clone()))
+                .collect(),
+            datetime: day(d),
+            tx_hash: None,
+        }
+    }
+
+    /// Buys 1 ETH for 100 USD, receives 1 more worth 300 and buys 1 for 200, then sells
+    /// 1.5 ETH for 600 USD
+    fn trades() -> Vec<Transaction> {
+        use AccountKind::{External, Holding};
+        let buy = |d, usd| {
+            tx(
+                d,
+                &[
+                    (Holding, External, amount("USD", usd)),
+                    (External, Holding, amount("ETH", 1.0)),
+                ],
+            )
+        };
+        vec![
+            buy(1, 100.0),
+            tx(2, &[(External, Holding, amount("ETH", 1.0))]),
+            buy(3, 200.0),
+            tx(
+                4,
+                &[
+                    (Holding, External, amount("ETH", 1.5)),
+                    (External, Holding, amount("USD", 600.0)),
+                ],
+            ),
+        ]
+    }
+
+    fn eth_prices(prices: &[(u32, f64)]) -> PriceBook {
+        let mut book = PriceBook::new();
+        for (d, price) in prices {
+            book.insert(AssetPricePoint {
+                datetime: day(*d),
+                asset_id: AssetId::from("ETH"),
+                vs_asset_id: AssetId::from("USD"),
+                price: *price,
+            });
+        }
+        book
+    }
+
+    #[test]
+    fn test_lot_methods() -> anyhow::Result<()> {
+        let assets = AssetRegistry::builtin();
+        let prices = eth_prices(&[(2, 300.0), (10, 500.0)]);
+        let usd = AssetId::from("USD");
+        let round = |value: f64| (value * 1e6).round() / 1e6;
+
+        for (method, cost, open_cost) in [
+            (CostBasisMethod::Fifo, 250.0, 350.0),
+            (CostBasisMethod::Lifo, 350.0, 250.0),
+            (CostBasisMethod::Hifo, 400.0, 200.0),
+            (CostBasisMethod::Average, 300.0, 300.0),
+        ] {
+            let basis = CostBasis::compute(&trades(), method, &assets, &prices, &usd)?;
+            assert!(basis.unpriced.is_empty());
+            let kinds = basis
+                .acquisitions
+                .iter()
+                .map(|a| a.kind)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                kinds,
+                [
+                    AcquisitionKind::Purchase,
+                    AcquisitionKind::Deposit,
+                    AcquisitionKind::Purchase
+                ]
+            );
+            let [sale] = &basis.disposals[..] else {
+                panic!("expected one disposal");
+            };
+            assert_eq!(sale.kind, DisposalKind::Sale);
+            assert_eq!(sale.proceeds, 600.0);
+            assert_eq!(round(sale.cost), cost, "{method:?}");
+            assert!(sale.uncovered.is_zero());
+            assert_eq!(round(basis.realized_gain()), 600.0 - cost);
+
+            let unrealized = basis.unrealized(&assets, &prices, day(10));
+            let open_units = unrealized.iter().map(|u| u.lot.amount.units).sum::<i128>();
+            assert_eq!(open_units, amount("ETH", 1.5).units);
+            let open = unrealized.iter().map(|u| u.lot.cost).sum::<f64>();
+            assert_eq!(round(open), open_cost, "{method:?}");
+            let gain = unrealized
+                .iter()
+                .filter_map(UnrealizedGain::gain)
+                .sum::<f64>();
+            assert_eq!(round(gain), 750.0 - open_cost);
+        }
+        assert_eq!("HIFO".parse::<CostBasisMethod>()?, CostBasisMethod::Hifo);
+        assert!("lofi".parse::<CostBasisMethod>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_income_fees_and_missing_history() -> anyhow::Result<()> {
+        use AccountKind::{External, Fee, Holding, Income, Product};
+        let assets = AssetRegistry::builtin();
+        let prices = eth_prices(&[(1, 1000.0), (2, 2000.0), (3, 3000.0)]);
+        let term = Product("nexo-term-ETH".into());
+        let transactions = [
+            tx(1, &[(Income, Holding, amount("ETH", 1.0))]),
+            // moving to a product is neither a disposal nor an acquisition
+            tx(2, &[(Holding, term.clone(), amount("ETH", 1.0))]),
+            tx(
+                3,
+                &[
+                    (term, External, amount("ETH", 0.5)),
+                    (Holding, Fee, amount("ETH", 0.1)),
+                ],
+            ),
+            // more than held, and no BTC price
+            tx(3, &[(Holding, External, amount("BTC", 0.1))]),
+        ];
+        let basis = CostBasis::compute(
+            &transactions,
+            CostBasisMethod::Fifo,
+            &assets,
+            &prices,
+            &AssetId::from("USD"),
+        )?;
+
+        let [income] = &basis.acquisitions[..] else {
+            panic!("expected one acquisition");
+        };
+        assert_eq!(
+            (income.kind, income.value),
+            (AcquisitionKind::Income, 1000.0)
+        );
+        let [withdrawal, btc] = &basis.disposals[..] else {
+            panic!("expected two disposals");
+        };
+        // the fee leaves with the withdrawal
+        assert_eq!(withdrawal.kind, DisposalKind::Withdrawal);
+        assert_eq!(withdrawal.amount, amount("ETH", 0.6));
+        assert_eq!(withdrawal.lots[0].acquired_at, day(1));
+        assert_eq!(
+            (withdrawal.proceeds.round(), withdrawal.cost.round()),
+            (1800.0, 600.0)
+        );
+        assert_eq!(btc.uncovered, amount("BTC", 0.1));
+        assert_eq!(basis.unpriced.len(), 1);
+
+        let fee = tx(4, &[(Holding, Fee, amount("ETH", 0.1))]);
+        let mut basis = basis;
+        basis.apply(&fee, &assets, &prices)?;
+        assert_eq!(basis.disposals[2].kind, DisposalKind::Fee);
+        assert_eq!(basis.open_lots()[0].amount, amount("ETH", 0.3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_trade_fee_in_a_third_asset() -> anyhow::Result<()> {
+        use AccountKind::{External, Fee, Holding};
+        let assets = AssetRegistry::builtin();
+        let mut prices = eth_prices(&[(1, 1000.0)]);
+        prices.insert(AssetPricePoint {
+            datetime: day(1),
+            asset_id: AssetId::from("BNB"),
+            vs_asset_id: AssetId::from("USD"),
+            price: 500.0,
+        });
+        // buys ETH with USDT, paying the fee in BNB
+        let trade = tx(
+            1,
+            &[
+                (Holding, External, amount("USDT", 1000.0)),
+                (External, Holding, amount("ETH", 1.0)),
+                (Holding, Fee, amount("BNB", 0.01)),
+            ],
+        );
+        let usd = AssetId::from("USD");
+        let basis = CostBasis::compute(&[trade], CostBasisMethod::Fifo, &assets, &prices, &usd)?;
+        let kinds = basis
+            .disposals
+            .iter()
+            .map(|d| (d.amount.asset_id.0.as_str(), d.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [("BNB", DisposalKind::Fee), ("USDT", DisposalKind::Sale)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unregistered_asset_is_unpriced() -> anyhow::Result<()> {
+        use AccountKind::{External, Holding};
+        let assets = AssetRegistry::builtin();
+        let prices = eth_prices(&[(1, 1000.0)]);
+        let unknown = Amount::new(AssetId::from("NOT-LISTED"), 42);
+        let transactions = [
+            tx(1, &[(External, Holding, amount("ETH", 1.0))]),
+            tx(2, &[(External, Holding, unknown.clone())]),
+        ];
+        let usd = AssetId::from("USD");
+        let basis =
+            CostBasis::compute(&transactions, CostBasisMethod::Fifo, &assets, &prices, &usd)?;
+        assert_eq!(basis.acquisitions.len(), 2);
+        let [UnpricedError::Asset(_)] = &basis.unpriced[..] else {
+            panic!("expected the unregistered asset, got {:?}", basis.unpriced);
+        };
+
+        let unrealized = basis.unrealized(&assets, &prices, day(2));
+        let values = unrealized
+            .iter()
+            .map(|u| (u.lot.amount.asset_id.0.as_str(), u.value))
+            .collect::<Vec<_>>();
+        assert_eq!(values, [("ETH", Some(1000.0)), ("NOT-LISTED", None)]);
+        assert!(matches!(
+            unrealized[1].unpriced,
+            Some(UnpricedError::Asset(_))
+        ));
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@ use crate::types::{AssetId, Position};
 use chrono::{DateTime, Utc};
 
 /// A single point in asset price history
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AssetPricePoint {
     pub datetime: DateTime<Utc>,
     pub asset_id: AssetId,
@@ -17,6 +17,13 @@ pub struct AssetPricePoint {
 pub struct AssetPriceHistory {
     pub points: Vec<AssetPricePoint>,
 }
+impl AssetPriceHistory {
+    /// Sorts the points by datetime
+    pub fn new(mut points: Vec<AssetPricePoint>) -> Self {
+        points.sort_by_key(|p| p.datetime);
+        AssetPriceHistory { points }
+    }
+}
 
 /// Collection of position snapshots over time
 #[derive(Debug, Clone)]
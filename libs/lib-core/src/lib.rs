@@ -3,11 +3,40 @@
 //! This library provides the foundational domain models and abstractions used
 //! across all provider adapters and client libraries.
 
+pub mod amount;
+pub mod assets;
+pub mod balances;
+pub mod cost_basis;
 pub mod history;
+pub mod pricing;
+pub mod store;
+pub mod sync;
+pub mod tax;
 pub mod traits;
+pub mod transfers;
 pub mod types;
+pub mod valuation;
+pub mod yields;
 
 // Re-export commonly used types for convenience
+pub use amount::{Amount, AmountError};
+pub use assets::{AssetError, AssetRegistry};
+pub use balances::{BalancePoint, BalanceReplay, NegativeBalance, ReplayError};
+pub use cost_basis::{
+    AcquisitionKind, CostBasis, CostBasisError, CostBasisMethod, Disposal, DisposalKind, Lot,
+    UnrealizedGain,
+};
 pub use history::*;
+pub use pricing::{Interpolation, PriceBook, PriceQuote, PriceRoute, PricingError};
+pub use store::{QuarantinedTx, SaveOutcome, Store};
+pub use sync::{SyncBatch, SyncCursor, SyncOutcome, SyncState};
+pub use tax::{
+    HoldingTerm, IncomeEvent, TaxError, TaxReport, TaxRules, TaxableDisposal, YearEndHolding,
+};
 pub use traits::*;
+pub use transfers::{
+    DecisionKind, TransferDecision, TransferError, TransferMatch, TransferMatcher, merge_transfers,
+};
 pub use types::*;
+pub use valuation::{Holding, Unpriced, UnpricedError, Valuation};
+pub use yields::{PositionYield, RealizedYield, RealizedYields, YieldProjection};
@@ -0,0 +1,300 @@
+//! Price lookups over recorded price histories, with interpolation and cross rates.
+
+use crate::history::{AssetPriceHistory, AssetPricePoint};
+use crate::types::AssetId;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::HashMap;
+
+/// How to price a datetime that falls between two recorded points
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Use the closest point
+    Nearest,
+    /// Weight the points on either side by their distance
+    #[default]
+    Linear,
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum PricingError {
+    #[error("no price of {asset_id:?} in {vs_asset_id:?} within {max_staleness} of {datetime}")]
+    NoPrice {
+        asset_id: AssetId,
+        vs_asset_id: AssetId,
+        datetime: DateTime<Utc>,
+        max_staleness: TimeDelta,
+    },
+}
+
+/// How a quote was derived from the recorded pairs
+#[derive(Debug, Clone, PartialEq)]
+pub enum PriceRoute {
+    /// An asset priced in itself
+    Identity,
+    /// Recorded as asset/vs
+    Direct,
+    /// Recorded as vs/asset and inverted
+    Inverse,
+    /// asset/via multiplied by via/vs
+    Triangulated { via: AssetId },
+}
+
+/// A price at a given datetime, with the recorded points it was derived from
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceQuote {
+    pub asset_id: AssetId,
+    pub vs_asset_id: AssetId,
+    pub datetime: DateTime<Utc>,
+    pub price: f64,
+    pub route: PriceRoute,
+    pub sources: Vec<AssetPricePoint>,
+}
+impl PriceQuote {
+    /// Largest distance between the requested datetime and a source point
+    pub fn staleness(&self) -> TimeDelta {
+        self.sources
+            .iter()
+            .map(|p| (p.datetime - self.datetime).abs())
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// Price histories indexed by (asset, vs asset)
+#[derive(Debug, Clone)]
+pub struct PriceBook {
+    histories: HashMap<(AssetId, AssetId), Vec<AssetPricePoint>>,
+    max_staleness: TimeDelta,
+    interpolation: Interpolation,
+    pivots: Vec<AssetId>,
+}
+
+impl Default for PriceBook {
+    fn default() -> Self {
+        PriceBook {
+            histories: HashMap::new(),
+            max_staleness: TimeDelta::days(1),
+            interpolation: Interpolation::default(),
+            pivots: ["USD", "USDT", "USDC"].map(AssetId::from).to_vec(),
+        }
+    }
+}
+
+impl PriceBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Points further than this from the requested datetime are not used
+    pub fn with_max_staleness(mut self, max_staleness: TimeDelta) -> Self {
+        self.max_staleness = max_staleness;
+        self
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Quote assets tried, in order, when a pair has no recorded prices
+    pub fn with_pivots(mut self, pivots: impl IntoIterator<Item = AssetId>) -> Self {
+        self.pivots = pivots.into_iter().collect();
+        self
+    }
+
+    pub fn insert(&mut self, point: AssetPricePoint) {
+        let points = self
+            .histories
+            .entry((point.asset_id.clone(), point.vs_asset_id.clone()))
+            .or_default();
+        let idx = points.partition_point(|p| p.datetime <= point.datetime);
+        points.insert(idx, point);
+    }
+
+    pub fn insert_history(&mut self, history: AssetPriceHistory) {
+        for point in history.points {
+            self.insert(point);
+        }
+    }
+
+    /// Price of one `asset_id` in `vs_asset_id` at `datetime`
+    pub fn price(
+        &self,
+        asset_id: &AssetId,
+        vs_asset_id: &AssetId,
+        datetime: DateTime<Utc>,
+    ) -> Result<PriceQuote, PricingError> {
+        let quote = |price, route, sources| PriceQuote {
+            asset_id: asset_id.clone(),
+            vs_asset_id: vs_asset_id.clone(),
+            datetime,
+            price,
+            route,
+            sources,
+        };
+        if asset_id == vs_asset_id {
+            return Ok(quote(1.0, PriceRoute::Identity, Vec::new()));
+        }
+        if let Some((price, route, sources)) = self.pair_price(asset_id, vs_asset_id, datetime) {
+            return Ok(quote(price, route, sources));
+        }
+
+        for via in &self.pivots {
+            if via == asset_id || via == vs_asset_id {
+                continue;
+            }
+            let Some((first, _, mut sources)) = self.pair_price(asset_id, via, datetime) else {
+                continue;
+            };
+            let Some((second, _, more_sources)) = self.pair_price(via, vs_asset_id, datetime)
+            else {
+                continue;
+            };
+            sources.extend(more_sources);
+            let route = PriceRoute::Triangulated { via: via.clone() };
+            return Ok(quote(first * second, route, sources));
+        }
+
+        Err(PricingError::NoPrice {
+            asset_id: asset_id.clone(),
+            vs_asset_id: vs_asset_id.clone(),
+            datetime,
+            max_staleness: self.max_staleness,
+        })
+    }
+
+    /// Price from the recorded pair, or from the inverse pair
+    fn pair_price(
+        &self,
+        asset_id: &AssetId,
+        vs_asset_id: &AssetId,
+        datetime: DateTime<Utc>,
+    ) -> Option<(f64, PriceRoute, Vec<AssetPricePoint>)> {
+        let direct = self
+            .histories
+            .get(&(asset_id.clone(), vs_asset_id.clone()))
+            .and_then(|points| self.lookup(points, datetime));
+        if let Some((price, sources)) = direct {
+            return Some((price, PriceRoute::Direct, sources));
+        }
+
+        let inverse = self
+            .histories
+            .get(&(vs_asset_id.clone(), asset_id.clone()))
+            .and_then(|points| self.lookup(points, datetime));
+        match inverse {
+            Some((price, sources)) if price != 0.0 => {
+                Some((1.0 / price, PriceRoute::Inverse, sources))
+            }
+            _ => None,
+        }
+    }
+
+    /// Price at `datetime` from points sorted by datetime
+    fn lookup(
+        &self,
+        points: &[AssetPricePoint],
+        datetime: DateTime<Utc>,
+    ) -> Option<(f64, Vec<AssetPricePoint>)> {
+        let idx = points.partition_point(|p| p.datetime < datetime);
+        let fresh = |p: &&AssetPricePoint| (p.datetime - datetime).abs() <= self.max_staleness;
+        let before = idx.checked_sub(1).and_then(|i| points.get(i)).filter(fresh);
+        let after = points.get(idx).filter(fresh);
+
+        match (before, after) {
+            (_, Some(after)) if after.datetime == datetime => {
+                Some((after.price, vec![after.clone()]))
+            }
+            (Some(before), Some(after)) => match self.interpolation {
+                Interpolation::Linear => {
+                    let span = (after.datetime - before.datetime).num_milliseconds() as f64;
+                    let elapsed = (datetime - before.datetime).num_milliseconds() as f64;
+                    let price = before.price + (after.price - before.price) * elapsed / span;
+                    Some((price, vec![before.clone(), after.clone()]))
+                }
+                Interpolation::Nearest => {
+                    let nearest = if datetime - before.datetime <= after.datetime - datetime {
+                        before
+                    } else {
+                        after
+                    };
+                    Some((nearest.price, vec![nearest.clone()]))
+                }
+            },
+            (Some(point), None) | (None, Some(point)) => Some((point.price, vec![point.clone()])),
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 4, day, hour, 0, 0).unwrap()
+    }
+
+    fn point(asset: &str, vs: &str, datetime: DateTime<Utc>, price: f64) -> AssetPricePoint {
+        AssetPricePoint {
+            datetime,
+            asset_id: AssetId::from(asset),
+            vs_asset_id: AssetId::from(vs),
+            price,
+        }
+    }
+
+    fn book() -> PriceBook {
+        let mut book = PriceBook::new().with_max_staleness(TimeDelta::hours(12));
+        book.insert(point("ETH", "USD", at(19, 12), 3100.0));
+        book.insert(point("ETH", "USD", at(19, 0), 3000.0));
+        book.insert(point("EUR", "USD", at(19, 0), 1.25));
+        book
+    }
+
+    #[test]
+    fn test_interpolation_and_staleness() {
+        let (eth, usd) = (AssetId::from("ETH"), AssetId::from("USD"));
+        let book = book();
+
+        let quote = book.price(&eth, &usd, at(19, 3)).unwrap();
+        assert_eq!(quote.price, 3025.0);
+        assert_eq!(quote.route, PriceRoute::Direct);
+        assert_eq!(quote.sources.len(), 2);
+        assert_eq!(quote.staleness(), TimeDelta::hours(9));
+
+        let nearest = book.clone().with_interpolation(Interpolation::Nearest);
+        let quote = nearest.price(&eth, &usd, at(19, 3)).unwrap();
+        assert_eq!(quote.price, 3000.0);
+        assert_eq!(quote.sources, [point("ETH", "USD", at(19, 0), 3000.0)]);
+
+        // only one side is fresh enough
+        assert_eq!(book.price(&eth, &usd, at(20, 0)).unwrap().price, 3100.0);
+        assert!(matches!(
+            book.price(&eth, &usd, at(21, 0)),
+            Err(PricingError::NoPrice { .. })
+        ));
+    }
+
+    #[test]
+    fn test_inverse_and_triangulated() {
+        let (eth, usd, eur) = (
+            AssetId::from("ETH"),
+            AssetId::from("USD"),
+            AssetId::from("EUR"),
+        );
+        let book = book();
+
+        let quote = book.price(&usd, &eur, at(19, 0)).unwrap();
+        assert_eq!(quote.price, 0.8);
+        assert_eq!(quote.route, PriceRoute::Inverse);
+
+        let quote = book.price(&eth, &eur, at(19, 0)).unwrap();
+        assert_eq!(quote.price, 2400.0);
+        assert_eq!(quote.route, PriceRoute::Triangulated { via: usd });
+        assert_eq!(quote.sources.len(), 2);
+
+        assert_eq!(book.price(&eur, &eur, at(1, 0)).unwrap().price, 1.0);
+    }
+}
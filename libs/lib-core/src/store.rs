@@ -0,0 +1,990 @@
+//! Local-first persistence of accounts, transactions and their effects in DuckDB.
+
+use crate::amount::Amount;
+use crate::history::{AssetPriceHistory, AssetPricePoint};
+use crate::sync::{SyncBatch, SyncOutcome, SyncState};
+use crate::transfers::{DecisionKind, TransferDecision};
+use crate::types::{
+    AccountId, AssetId, ProviderId, Transaction, TransactionId, TxEffect, TxValidationError,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use duckdb::{Connection, OptionalExt, params};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS accounts (
+    id TEXT PRIMARY KEY,
+    provider TEXT NOT NULL,
+    asset TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS transactions (
+    id TEXT PRIMARY KEY,
+    date TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS transaction_effects (
+    transaction_id TEXT NOT NULL REFERENCES transactions(id),
+    side TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    account_id TEXT NOT NULL REFERENCES accounts(id),
+    asset TEXT NOT NULL,
+    amount HUGEINT NOT NULL,
+    date TIMESTAMP NOT NULL,
+    PRIMARY KEY (transaction_id, side, idx)
+);
+
+CREATE TABLE IF NOT EXISTS transaction_hashes (
+    transaction_id TEXT PRIMARY KEY,
+    hash TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS quarantined_transactions (
+    id TEXT PRIMARY KEY,
+    date TIMESTAMP NOT NULL,
+    reason TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    provider TEXT
+);
+
+CREATE TABLE IF NOT EXISTS asset_prices (
+    asset TEXT NOT NULL,
+    vs_asset TEXT NOT NULL,
+    date TIMESTAMP NOT NULL,
+    price DOUBLE NOT NULL,
+    PRIMARY KEY (asset, vs_asset, date)
+);
+
+CREATE TABLE IF NOT EXISTS asset_price_days (
+    asset TEXT NOT NULL,
+    vs_asset TEXT NOT NULL,
+    day DATE NOT NULL,
+    PRIMARY KEY (asset, vs_asset, day)
+);
+
+CREATE TABLE IF NOT EXISTS sync_state (
+    provider TEXT PRIMARY KEY,
+    cursor TEXT NOT NULL,
+    last_synced TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS transfer_decisions (
+    withdrawal_id TEXT NOT NULL,
+    deposit_id TEXT NOT NULL,
+    decision TEXT NOT NULL,
+    decided_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (withdrawal_id, deposit_id)
+);
+"#;
+
+/// Effects of each transaction, split into (inputs, outputs)
+type EffectsByTx = HashMap<TransactionId, (Vec<TxEffect>, Vec<TxEffect>)>;
+
+const SIDE_INPUT: &str = "input";
+const SIDE_OUTPUT: &str = "output";
+
+/// Result of [`Store::save_or_quarantine`]
+#[derive(Debug)]
+pub enum SaveOutcome {
+    Saved(TransactionId),
+    /// A transaction with the same id was already stored, it's left as is
+    Duplicate(TransactionId),
+    /// The transaction failed validation and was set aside for review
+    Quarantined(TransactionId, TxValidationError),
+}
+
+/// A transaction that failed validation, kept with the reason it was rejected
+#[derive(Debug, Clone)]
+pub struct QuarantinedTx {
+    pub id: TransactionId,
+    pub reason: String,
+    pub transaction: Transaction,
+}
+
+/// Handle on the local DuckDB database
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Opens (or creates) the database file at `path` and ensures the schema exists
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let store = Store {
+            conn: Connection::open(path)?,
+        };
+        store.init()?;
+        Ok(store)
+    }
+
+    /// Opens a throwaway in-memory database, mostly useful for tests
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        let store = Store {
+            conn: Connection::open_in_memory()?,
+        };
+        store.init()?;
+        Ok(store)
+    }
+
+    /// Creates the tables if missing, and prunes transactions left empty by syncs
+    pub fn init(&self) -> anyhow::Result<()> {
+        self.conn.execute_batch(SCHEMA)?;
+        // transactions removed by a sync, see `remove_transaction`
+        self.conn.execute(
+            "DELETE FROM transactions WHERE id NOT IN (SELECT transaction_id FROM transaction_effects)",
+            [],
+        )?;
+        self.conn.execute(
+            "DELETE FROM transaction_hashes WHERE transaction_id NOT IN (SELECT id FROM transactions)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn save_account(&self, account_id: &AccountId) -> anyhow::Result<()> {
+        insert_account(&self.conn, account_id)
+    }
+
+    pub fn get_accounts(&self) -> anyhow::Result<Vec<AccountId>> {
+        let mut stmt = self.conn.prepare("SELECT id FROM accounts ORDER BY id")?;
+        let keys = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        keys.iter().map(|key| account_from_key(key)).collect()
+    }
+
+    /// Saves a transaction along with its effects and the accounts they touch, under its id.
+    /// Returns whether it was new, a transaction already stored under that id is left as is.
+    /// Fails with a [`TxValidationError`] if the transaction doesn't balance.
+    pub fn save_transaction(&mut self, tx: &Transaction) -> anyhow::Result<bool> {
+        tx.validate()?;
+        self.save_valid(tx)
+    }
+
+    /// Saves valid transactions, and quarantines the others instead of failing
+    pub fn save_or_quarantine(&mut self, tx: &Transaction) -> anyhow::Result<SaveOutcome> {
+        match tx.validate() {
+            Ok(()) if self.save_valid(tx)? => Ok(SaveOutcome::Saved(tx.id.clone())),
+            Ok(()) => Ok(SaveOutcome::Duplicate(tx.id.clone())),
+            Err(err) => {
+                let id = self.quarantine_transaction(tx, &err.to_string())?;
+                Ok(SaveOutcome::Quarantined(id, err))
+            }
+        }
+    }
+
+    fn save_valid(&mut self, tx: &Transaction) -> anyhow::Result<bool> {
+        let db_tx = self.conn.transaction()?;
+        let inserted = insert_valid_transaction(&db_tx, tx)?;
+        db_tx.commit()?;
+        Ok(inserted)
+    }
+
+    /// Sets a transaction aside for review, without touching balances
+    pub fn quarantine_transaction(
+        &self,
+        tx: &Transaction,
+        reason: &str,
+    ) -> anyhow::Result<TransactionId> {
+        let provider = tx.effects().next().map(|e| e.account_id.provider.clone());
+        insert_quarantined(&self.conn, provider.as_ref(), tx, reason)
+    }
+
+    pub fn get_quarantined(&self) -> anyhow::Result<Vec<QuarantinedTx>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, reason, payload FROM quarantined_transactions ORDER BY date, id",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(id, reason, payload)| {
+                Ok(QuarantinedTx {
+                    id: TransactionId(id),
+                    reason,
+                    transaction: serde_json::from_str(&payload)?,
+                })
+            })
+            .collect()
+    }
+
+    pub fn get_transaction(&self, id: &TransactionId) -> anyhow::Result<Option<Transaction>> {
+        let datetime: Option<DateTime<Utc>> = self
+            .conn
+            .query_row(
+                "SELECT date FROM transactions WHERE id = ?",
+                params![id.0],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(datetime) = datetime else {
+            return Ok(None);
+        };
+
+        let mut effects = self.load_effects(Some(id))?;
+        let Some((inputs, outputs)) = effects.remove(id) else {
+            return Ok(None);
+        };
+        let tx_hash = self
+            .conn
+            .query_row(
+                "SELECT hash FROM transaction_hashes WHERE transaction_id = ?",
+                params![id.0],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(Some(Transaction {
+            id: id.clone(),
+            inputs,
+            outputs,
+            datetime,
+            tx_hash,
+        }))
+    }
+
+    /// Returns every stored transaction, oldest first
+    pub fn get_transactions(&self) -> anyhow::Result<Vec<Transaction>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT t.id, t.date, h.hash FROM transactions t
+             LEFT JOIN transaction_hashes h ON h.transaction_id = t.id
+             ORDER BY t.date, t.id",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    TransactionId(row.get::<_, String>(0)?),
+                    row.get::<_, DateTime<Utc>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut effects = self.load_effects(None)?;
+        // transactions without effects were removed by a sync
+        let transactions = rows
+            .into_iter()
+            .filter_map(|(id, datetime, tx_hash)| {
+                let (inputs, outputs) = effects.remove(&id)?;
+                Some(Transaction {
+                    id,
+                    inputs,
+                    outputs,
+                    datetime,
+                    tx_hash,
+                })
+            })
+            .collect();
+        Ok(transactions)
+    }
+
+    /// Stores a provider's sync in one go: transactions already stored are skipped, invalid
+    /// ones are quarantined, and the cursor is saved. With `prune`, stored transactions
+    /// dated within the batch's span that it doesn't have anymore are removed.
+    pub fn save_sync_batch(
+        &mut self,
+        provider: &ProviderId,
+        batch: &SyncBatch,
+        synced_at: DateTime<Utc>,
+        prune: bool,
+    ) -> anyhow::Result<SyncOutcome> {
+        let db_tx = self.conn.transaction()?;
+        let mut gone = Vec::new();
+        if let Some((start, end)) = batch.span().filter(|_| prune) {
+            let fetched = batch
+                .transactions
+                .iter()
+                .map(|tx| &tx.id)
+                .collect::<HashSet<_>>();
+            gone = provider_transaction_ids(&db_tx, provider, start, end)?
+                .into_iter()
+                .filter(|id| !fetched.contains(id))
+                .collect();
+            for id in &gone {
+                remove_transaction(&db_tx, id)?;
+            }
+            db_tx.execute(
+                "DELETE FROM quarantined_transactions
+                 WHERE provider = ? AND date BETWEEN ? AND ?",
+                params![provider.0, start, end],
+            )?;
+        }
+
+        let mut outcome = SyncOutcome {
+            removed: gone.len(),
+            ..SyncOutcome::default()
+        };
+        for tx in &batch.transactions {
+            match tx.validate() {
+                Ok(()) if insert_valid_transaction(&db_tx, tx)? => outcome.saved += 1,
+                Ok(()) => outcome.duplicates += 1,
+                Err(err) => {
+                    insert_quarantined(&db_tx, Some(provider), tx, &err.to_string())?;
+                    outcome.quarantined += 1;
+                }
+            }
+        }
+        db_tx.execute(
+            "INSERT OR REPLACE INTO sync_state (provider, cursor, last_synced) VALUES (?, ?, ?)",
+            params![provider.0, serde_json::to_string(&batch.cursor)?, synced_at],
+        )?;
+        db_tx.commit()?;
+        Ok(outcome)
+    }
+
+    pub fn get_sync_state(&self, provider: &ProviderId) -> anyhow::Result<Option<SyncState>> {
+        let states = self.get_sync_states()?;
+        Ok(states.into_iter().find(|state| &state.provider == provider))
+    }
+
+    /// The last successful sync of every provider
+    pub fn get_sync_states(&self) -> anyhow::Result<Vec<SyncState>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT provider, cursor, last_synced FROM sync_state ORDER BY provider")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, DateTime<Utc>>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(provider, cursor, last_synced)| {
+                Ok(SyncState {
+                    provider: ProviderId(provider),
+                    cursor: serde_json::from_str(&cursor)?,
+                    last_synced,
+                })
+            })
+            .collect()
+    }
+
+    /// Saves price points, replacing any stored for the same pair and datetime
+    pub fn save_prices(&mut self, points: &[AssetPricePoint]) -> anyhow::Result<()> {
+        let db_tx = self.conn.transaction()?;
+        for point in points {
+            db_tx.execute(
+                "INSERT OR REPLACE INTO asset_prices (asset, vs_asset, date, price) VALUES (?, ?, ?, ?)",
+                params![
+                    asset_key(&point.asset_id)?,
+                    asset_key(&point.vs_asset_id)?,
+                    point.datetime,
+                    point.price,
+                ],
+            )?;
+        }
+        db_tx.commit()?;
+        Ok(())
+    }
+
+    /// Stored prices of `asset_id` in `vs_asset_id` within `[from, to]`
+    pub fn get_price_history(
+        &self,
+        asset_id: &AssetId,
+        vs_asset_id: &AssetId,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> anyhow::Result<AssetPriceHistory> {
+        let mut stmt = self.conn.prepare(
+            "SELECT date, price FROM asset_prices
+             WHERE asset = ? AND vs_asset = ? AND date BETWEEN ? AND ?
+             ORDER BY date",
+        )?;
+        let points = stmt
+            .query_map(
+                params![asset_key(asset_id)?, asset_key(vs_asset_id)?, from, to],
+                |row| {
+                    Ok(AssetPricePoint {
+                        datetime: row.get(0)?,
+                        asset_id: asset_id.clone(),
+                        vs_asset_id: vs_asset_id.clone(),
+                        price: row.get(1)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(AssetPriceHistory::new(points))
+    }
+
+    /// Every stored price, oldest first
+    pub fn get_prices(&self) -> anyhow::Result<Vec<AssetPricePoint>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT asset, vs_asset, date, price FROM asset_prices ORDER BY date, asset, vs_asset",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, DateTime<Utc>>(2)?,
+                    row.get::<_, f64>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(asset, vs_asset, datetime, price)| {
+                Ok(AssetPricePoint {
+                    datetime,
+                    asset_id: asset_from_key(&asset)?,
+                    vs_asset_id: asset_from_key(&vs_asset)?,
+                    price,
+                })
+            })
+            .collect()
+    }
+
+    /// Records that prices were fetched for these days, even if the source had none
+    pub fn mark_price_days_fetched(
+        &mut self,
+        asset_id: &AssetId,
+        vs_asset_id: &AssetId,
+        days: &[NaiveDate],
+    ) -> anyhow::Result<()> {
+        let db_tx = self.conn.transaction()?;
+        for day in days {
+            db_tx.execute(
+                "INSERT INTO asset_price_days (asset, vs_asset, day) VALUES (?, ?, ?) ON CONFLICT DO NOTHING",
+                params![asset_key(asset_id)?, asset_key(vs_asset_id)?, day],
+            )?;
+        }
+        db_tx.commit()?;
+        Ok(())
+    }
+
+    /// Days within `[from, to]` whose prices were never fetched, oldest first
+    pub fn missing_price_days(
+        &self,
+        asset_id: &AssetId,
+        vs_asset_id: &AssetId,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> anyhow::Result<Vec<NaiveDate>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT day FROM asset_price_days WHERE asset = ? AND vs_asset = ? AND day BETWEEN ? AND ?",
+        )?;
+        let fetched = stmt
+            .query_map(
+                params![asset_key(asset_id)?, asset_key(vs_asset_id)?, from, to],
+                |row| row.get::<_, NaiveDate>(0),
+            )?
+            .collect::<Result<std::collections::HashSet<_>, _>>()?;
+
+        Ok(from
+            .iter_days()
+            .take_while(|day| *day <= to)
+            .filter(|day| !fetched.contains(day))
+            .collect())
+    }
+
+    /// Loads effects grouped by transaction
+    fn load_effects(&self, tx_id: Option<&TransactionId>) -> anyhow::Result<EffectsByTx> {
+        let mut stmt = self.conn.prepare(
+            "SELECT transaction_id, side, account_id, asset, amount, date FROM transaction_effects
+             WHERE ?::TEXT IS NULL OR transaction_id = ?
+             ORDER BY transaction_id, side, idx",
+        )?;
+        let filter = tx_id.map(|id| id.0.as_str());
+        let rows = stmt
+            .query_map(params![filter, filter], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, i128>(4)?,
+                    row.get::<_, DateTime<Utc>>(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut grouped = EffectsByTx::new();
+        for (tx_id, side, account, asset, units, datetime) in rows {
+            let effect = TxEffect {
+                account_id: account_from_key(&account)?,
+                amount: Amount::new(asset_from_key(&asset)?, units),
+                datetime,
+            };
+            let entry = grouped.entry(TransactionId(tx_id)).or_default();
+            match side.as_str() {
+                SIDE_INPUT => entry.0.push(effect),
+                SIDE_OUTPUT => entry.1.push(effect),
+                other => anyhow::bail!("unknown transaction effect side: {other}"),
+            }
+        }
+        Ok(grouped)
+    }
+
+    /// Records a user decision on a withdrawal and deposit pair. Confirming a pair drops
+    /// the other confirmed pairs sharing one of its transactions.
+    pub fn save_transfer_decision(
+        &mut self,
+        decision: &TransferDecision,
+        decided_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let db_tx = self.conn.transaction()?;
+        let (withdrawal, deposit) = (&decision.withdrawal_id.0, &decision.deposit_id.0);
+        if decision.kind == DecisionKind::Confirmed {
+            db_tx.execute(
+                "DELETE FROM transfer_decisions
+                 WHERE decision = ? AND (withdrawal_id = ? OR deposit_id = ?)",
+                params![DecisionKind::Confirmed.as_str(), withdrawal, deposit],
+            )?;
+        }
+        db_tx.execute(
+            "INSERT OR REPLACE INTO transfer_decisions (withdrawal_id, deposit_id, decision, decided_at)
+             VALUES (?, ?, ?, ?)",
+            params![withdrawal, deposit, decision.kind.as_str(), decided_at],
+        )?;
+        db_tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_transfer_decisions(&self) -> anyhow::Result<Vec<TransferDecision>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT withdrawal_id, deposit_id, decision FROM transfer_decisions
+             ORDER BY decided_at, withdrawal_id, deposit_id",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(withdrawal_id, deposit_id, kind)| {
+                Ok(TransferDecision {
+                    withdrawal_id: TransactionId(withdrawal_id),
+                    deposit_id: TransactionId(deposit_id),
+                    kind: kind.parse()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Inserts a transaction unless one is stored under its id, returns whether it was
+fn insert_transaction(conn: &Connection, tx: &Transaction) -> anyhow::Result<bool> {
+    let stored: i64 = conn.query_row(
+        "SELECT count(*) FROM transaction_effects WHERE transaction_id = ?",
+        params![tx.id.0],
+        |row| row.get(0),
+    )?;
+    if stored > 0 {
+        return Ok(false);
+    }
+    // the row of a removed transaction may still be there, see `remove_transaction`
+    conn.execute(
+        "INSERT INTO transactions (id, date) VALUES (?, ?)
+         ON CONFLICT (id) DO UPDATE SET date = excluded.date",
+        params![tx.id.0, tx.datetime],
+    )?;
+    for (side, effects) in [(SIDE_INPUT, &tx.inputs), (SIDE_OUTPUT, &tx.outputs)] {
+        for (idx, effect) in effects.iter().enumerate() {
+            insert_account(conn, &effect.account_id)?;
+            conn.execute(
+                "INSERT INTO transaction_effects (transaction_id, side, idx, account_id, asset, amount, date)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    tx.id.0,
+                    side,
+                    idx as i64,
+                    account_key(&effect.account_id)?,
+                    asset_key(&effect.amount.asset_id)?,
+                    effect.amount.units,
+                    effect.datetime,
+                ],
+            )?;
+        }
+    }
+    if let Some(tx_hash) = &tx.tx_hash {
+        conn.execute(
+            "INSERT OR REPLACE INTO transaction_hashes (transaction_id, hash) VALUES (?, ?)",
+            params![tx.id.0, tx_hash],
+        )?;
+    }
+    Ok(true)
+}
+
+/// Inserts a validated transaction like [`insert_transaction`], and drops the quarantined
+/// version of it, if any
+fn insert_valid_transaction(conn: &Connection, tx: &Transaction) -> anyhow::Result<bool> {
+    let inserted = insert_transaction(conn, tx)?;
+    conn.execute(
+        "DELETE FROM quarantined_transactions WHERE id = ?",
+        params![tx.id.0],
+    )?;
+    Ok(inserted)
+}
+
+fn insert_quarantined(
+    conn: &Connection,
+    provider: Option<&ProviderId>,
+    tx: &Transaction,
+    reason: &str,
+) -> anyhow::Result<TransactionId> {
+    conn.execute(
+        "INSERT OR REPLACE INTO quarantined_transactions (id, date, reason, payload, provider)
+         VALUES (?, ?, ?, ?, ?)",
+        params![
+            tx.id.0,
+            tx.datetime,
+            reason,
+            serde_json::to_string(tx)?,
+            provider.map(|p| p.0.as_str())
+        ],
+    )?;
+    Ok(tx.id.clone())
+}
+
+/// Ids of the transactions touching `provider`'s accounts dated from `start` to `end`
+fn provider_transaction_ids(
+    conn: &Connection,
+    provider: &ProviderId,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> anyhow::Result<Vec<TransactionId>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT e.transaction_id FROM transaction_effects e
+         JOIN accounts a ON a.id = e.account_id
+         JOIN transactions t ON t.id = e.transaction_id
+         WHERE a.provider = ? AND t.date BETWEEN ? AND ?",
+    )?;
+    let ids = stmt
+        .query_map(params![provider.0, start, end], |row| {
+            row.get::<_, String>(0).map(TransactionId)
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ids)
+}
+
+/// Deletes a transaction's effects.
+///
+/// DuckDB can't delete a row and the rows referencing it in the same transaction, so the
+/// emptied transaction row is left behind, skipped when reading and pruned by [`Store::init`].
+fn remove_transaction(conn: &Connection, id: &TransactionId) -> anyhow::Result<()> {
+    conn.execute(
+        "DELETE FROM transaction_effects WHERE transaction_id = ?",
+        params![id.0],
+    )?;
+    conn.execute(
+        "DELETE FROM transaction_hashes WHERE transaction_id = ?",
+        params![id.0],
+    )?;
+    Ok(())
+}
+
+fn insert_account(conn: &Connection, account_id: &AccountId) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO accounts (id, provider, asset) VALUES (?, ?, ?) ON CONFLICT DO NOTHING",
+        params![
+            account_key(account_id)?,
+            account_id.provider.0,
+            asset_key(&account_id.asset)?
+        ],
+    )?;
+    Ok(())
+}
+
+/// Accounts are keyed by their JSON form so they can be rebuilt losslessly
+fn account_key(account_id: &AccountId) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(account_id)?)
+}
+
+fn account_from_key(key: &str) -> anyhow::Result<AccountId> {
+    Ok(serde_json::from_str(key)?)
+}
+
+fn asset_key(asset_id: &AssetId) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(asset_id)?)
+}
+
+fn asset_from_key(key: &str) -> anyhow::Result<AssetId> {
+    Ok(serde_json::from_str(key)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn effect(account_id: &AccountId, units: i128) -> TxEffect {
+        TxEffect {
+            account_id: account_id.clone(),
+            amount: Amount::new(account_id.asset.clone(), units),
+            datetime: Utc.with_ymd_and_hms(2024, 4, 19, 5, 0, 0).unwrap(),
+        }
+    }
+
+    /// ETH sold for BTC, settled through the outside world
+    fn sample_tx() -> Transaction {
+        let nexo = ProviderId::from("nexo");
+        let btc = AssetId::from("BTC");
+        let eth_units = 1_500_000_000_000_000_000;
+        Transaction {
+            id: TransactionId::native(&nexo, "NXTswap1"),
+            tx_hash: None,
+            inputs: vec![
+                effect(
+                    &AccountId::new(nexo.clone(), AssetId::from("ETH")),
+                    -eth_units,
+                ),
+                effect(&AccountId::external(nexo.clone(), btc.clone()), -i128::MAX),
+            ],
+            outputs: vec![
+                effect(
+                    &AccountId::external(nexo.clone(), AssetId::from("ETH")),
+                    eth_units,
+                ),
+                effect(&AccountId::new(nexo, btc), i128::MAX),
+            ],
+            datetime: Utc.with_ymd_and_hms(2024, 4, 19, 5, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_init_creates_tables() -> anyhow::Result<()> {
+        let store = Store::open_in_memory()?;
+        let tables = store
+            .conn
+            .prepare("SELECT table_name FROM information_schema.tables ORDER BY table_name")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            tables,
+            [
+                "accounts",
+                "asset_price_days",
+                "asset_prices",
+                "quarantined_transactions",
+                "sync_state",
+                "transaction_effects",
+                "transaction_hashes",
+                "transactions",
+                "transfer_decisions"
+            ]
+        );
+
+        // init is idempotent
+        store.init()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_roundtrip_transaction() -> anyhow::Result<()> {
+        let mut store = Store::open_in_memory()?;
+        let mut tx = sample_tx();
+        tx.tx_hash = Some("0x9b1d44aa".to_string());
+
+        assert!(store.save_transaction(&tx)?);
+        let loaded = store.get_transaction(&tx.id)?.expect("saved transaction");
+        assert_eq!(serde_json::to_value(&loaded)?, serde_json::to_value(&tx)?);
+
+        // saving it again is a no-op
+        assert!(!store.save_transaction(&tx)?);
+        assert!(matches!(
+            store.save_or_quarantine(&tx)?,
+            SaveOutcome::Duplicate(id) if id == tx.id
+        ));
+        let all = store.get_transactions()?;
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].id, tx.id);
+
+        let accounts = store.get_accounts()?;
+        assert_eq!(accounts.len(), 4);
+        assert!(tx.effects().all(|e| accounts.contains(&e.account_id)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbalanced_transaction_is_quarantined() -> anyhow::Result<()> {
+        let mut store = Store::open_in_memory()?;
+        let mut tx = sample_tx();
+        tx.outputs.pop();
+
+        let err = store.save_transaction(&tx).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TxValidationError>(),
+            Some(TxValidationError::Unbalanced(_))
+        ));
+        assert!(store.get_transactions()?.is_empty());
+
+        let SaveOutcome::Quarantined(id, _) = store.save_or_quarantine(&tx)? else {
+            panic!("unbalanced transaction was saved");
+        };
+        let quarantined = store.get_quarantined()?;
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].id, id);
+        assert!(quarantined[0].reason.contains("unbalanced"));
+        assert!(store.get_transactions()?.is_empty());
+
+        // once fixed, the transaction leaves quarantine
+        let fixed = sample_tx();
+        assert_eq!(fixed.id, id);
+        assert!(matches!(
+            store.save_or_quarantine(&fixed)?,
+            SaveOutcome::Saved(_)
+        ));
+        assert!(store.get_quarantined()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_persists_across_reopen() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lib-core-store-{}", std::process::id()));
+        let path = dir.join("store.duckdb");
+        let _ = std::fs::remove_file(&path);
+
+        let tx = sample_tx();
+        Store::open(&path)?.save_transaction(&tx)?;
+        let reopened = Store::open(&path)?;
+        assert!(reopened.get_transaction(&tx.id)?.is_some());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_batches_dedup_and_remove() -> anyhow::Result<()> {
+        let mut store = Store::open_in_memory()?;
+        let nexo = ProviderId::from("nexo");
+        let at = |h| Utc.with_ymd_and_hms(2024, 4, 19, h, 0, 0).unwrap();
+        let tx_at = |h| Transaction {
+            id: TransactionId::native(&nexo, h),
+            datetime: at(h),
+            ..sample_tx()
+        };
+        let mut unbalanced = tx_at(3);
+        unbalanced.outputs.pop();
+        // another provider's transaction is left alone
+        let mut other = tx_at(5);
+        other.id = TransactionId::from("binance:5");
+        other
+            .inputs
+            .iter_mut()
+            .chain(other.outputs.iter_mut())
+            .for_each(|effect| {
+                effect.account_id.provider = ProviderId::from("binance");
+            });
+        store.save_transaction(&other)?;
+
+        let full = SyncBatch::full(vec![tx_at(1), tx_at(2), unbalanced]);
+        let outcome = store.save_sync_batch(&nexo, &full, at(10), false)?;
+        assert_eq!((outcome.saved, outcome.quarantined), (2, 1));
+        // importing again creates no duplicates
+        let outcome = store.save_sync_batch(&nexo, &full, at(11), false)?;
+        assert_eq!((outcome.saved, outcome.duplicates), (0, 2));
+        assert_eq!(store.get_transactions()?.len(), 3);
+        assert_eq!(store.get_quarantined()?.len(), 1);
+
+        let state = store.get_sync_state(&nexo)?.expect("synced");
+        assert_eq!(state.cursor.last_datetime, Some(at(3)));
+        assert_eq!(state.last_synced, at(11));
+
+        // resuming at 2h, the 2h transaction is gone and a new one came
+        let mut cursor = state.cursor.clone();
+        cursor.set_marker("rows", 3);
+        let transactions = vec![tx_at(4)];
+        let batch = SyncBatch {
+            from: Some(at(2)),
+            cursor: cursor.advance(&transactions),
+            transactions,
+        };
+        // nothing is removed unless pruning
+        let outcome = store.save_sync_batch(&nexo, &batch, at(12), false)?;
+        assert_eq!((outcome.removed, outcome.saved), (0, 1));
+        assert!(
+            store
+                .get_transaction(&TransactionId::native(&nexo, 2))?
+                .is_some()
+        );
+        let outcome = store.save_sync_batch(&nexo, &batch, at(12), true)?;
+        assert_eq!((outcome.removed, outcome.duplicates), (1, 1));
+        let dates = store.get_transactions()?.into_iter().map(|tx| tx.datetime);
+        assert_eq!(dates.collect::<Vec<_>>(), [at(1), at(4), at(5)]);
+        assert!(store.get_quarantined()?.is_empty());
+
+        let state = store.get_sync_state(&nexo)?.expect("synced");
+        assert_eq!(state.cursor.last_datetime, Some(at(4)));
+        assert_eq!(state.cursor.marker("rows"), Some("3"));
+        assert!(
+            store
+                .get_sync_state(&ProviderId::from("binance"))?
+                .is_none()
+        );
+
+        // a removed transaction can come back, and a source ending at 3h leaves 4h alone
+        let outcome = store.save_sync_batch(&nexo, &full, at(13), true)?;
+        assert_eq!((outcome.removed, outcome.saved), (0, 1));
+        let dates = store.get_transactions()?.into_iter().map(|tx| tx.datetime);
+        assert_eq!(dates.collect::<Vec<_>>(), [at(1), at(2), at(4), at(5)]);
+        // an empty source removes nothing
+        let empty = SyncBatch::full(Vec::new());
+        let outcome = store.save_sync_batch(&nexo, &empty, at(14), true)?;
+        assert_eq!(outcome.removed, 0);
+        // removed transactions are pruned on the next open
+        store.init()?;
+        let count = "SELECT count(*) FROM transactions";
+        let rows: i64 = store.conn.query_row(count, [], |row| row.get(0))?;
+        assert_eq!(rows, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_cache() -> anyhow::Result<()> {
+        let mut store = Store::open_in_memory()?;
+        let (eth, usd) = (AssetId::from("ETH"), AssetId::from("USD"));
+        let day = |d| NaiveDate::from_ymd_opt(2024, 4, d).unwrap();
+        let point = |d, h, price| AssetPricePoint {
+            datetime: Utc.with_ymd_and_hms(2024, 4, d, h, 0, 0).unwrap(),
+            asset_id: eth.clone(),
+            vs_asset_id: usd.clone(),
+            price,
+        };
+
+        store.save_prices(&[
+            point(20, 0, 3150.0),
+            point(19, 12, 3070.0),
+            point(19, 0, 3065.0),
+        ])?;
+        // refetching replaces the point
+        store.save_prices(&[point(19, 12, 3071.5)])?;
+        store.mark_price_days_fetched(&eth, &usd, &[day(19), day(20)])?;
+
+        let history = store.get_price_history(
+            &eth,
+            &usd,
+            Utc.with_ymd_and_hms(2024, 4, 19, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 4, 19, 23, 0, 0).unwrap(),
+        )?;
+        let prices = history.points.iter().map(|p| p.price).collect::<Vec<_>>();
+        assert_eq!(prices, [3065.0, 3071.5]);
+        assert_eq!(store.get_prices()?.len(), 3);
+
+        let missing = store.missing_price_days(&eth, &usd, day(18), day(21))?;
+        assert_eq!(missing, [day(18), day(21)]);
+        assert_eq!(
+            store.missing_price_days(&usd, &eth, day(19), day(19))?,
+            [day(19)]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Incremental sync: where each provider's last fetch stopped, and what it brought back.
+
+use crate::types::{ProviderId, Transaction};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Where a provider's last sync stopped, persisted in the store between runs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncCursor {
+    /// Date of the newest transaction fetched so far
+    pub last_datetime: Option<DateTime<Utc>>,
+    /// Provider specific positions, e.g. the last trade id of each market
+    #[serde(default)]
+    pub markers: BTreeMap<String, String>,
+}
+
+impl SyncCursor {
+    /// The cursor after fetching `transactions`
+    pub fn advance(mut self, transactions: &[Transaction]) -> Self {
+        let newest = transactions.iter().map(|tx| tx.datetime).max();
+        self.last_datetime = self.last_datetime.max(newest);
+        self
+    }
+
+    /// Keeps the cursor at or before `datetime`, the oldest record a sync couldn't map,
+    /// so that the next sync fetches it again instead of moving past it
+    pub fn hold_at(mut self, datetime: Option<DateTime<Utc>>) -> Self {
+        if let (Some(last), Some(datetime)) = (self.last_datetime, datetime) {
+            self.last_datetime = Some(last.min(datetime));
+        }
+        self
+    }
+
+    pub fn marker(&self, key: &str) -> Option<&str> {
+        self.markers.get(key).map(String::as_str)
+    }
+
+    pub fn set_marker(&mut self, key: impl Into<String>, value: impl ToString) {
+        self.markers.insert(key.into(), value.to_string());
+    }
+}
+
+/// Transactions fetched by a sync, with the cursor to resume from next time
+#[derive(Debug, Clone)]
+pub struct SyncBatch {
+    /// Start of the window the batch was fetched from, None for a full fetch
+    pub from: Option<DateTime<Utc>>,
+    pub transactions: Vec<Transaction>,
+    pub cursor: SyncCursor,
+}
+
+impl SyncBatch {
+    /// Everything the provider has
+    pub fn full(transactions: Vec<Transaction>) -> Self {
+        let cursor = SyncCursor::default().advance(&transactions);
+        SyncBatch {
+            from: None,
+            transactions,
+            cursor,
+        }
+    }
+
+    /// Dates the source is known to cover: from the start of the fetch window, or the oldest
+    /// transaction of a full fetch, to the newest transaction. None when the batch is empty.
+    pub fn span(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let dates = self.transactions.iter().map(|tx| tx.datetime);
+        let (oldest, newest) = (dates.clone().min()?, dates.max()?);
+        Some((self.from.map_or(oldest, |from| from.min(oldest)), newest))
+    }
+}
+
+/// The last successful sync of a provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncState {
+    pub provider: ProviderId,
+    pub cursor: SyncCursor,
+    pub last_synced: DateTime<Utc>,
+}
+
+/// Result of [`crate::Store::save_sync_batch`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncOutcome {
+    pub saved: usize,
+    /// Transactions that were already stored
+    pub duplicates: usize,
+    /// Stored transactions of the batch's span the provider doesn't report anymore, when pruning
+    pub removed: usize,
+    pub quarantined: usize,
+}
@@ -0,0 +1,493 @@
+//! Tax reports per fiscal year: disposals with their holding period, income events and
+//! year-end holdings, under jurisdiction rules given as [`TaxRules`].
+
+use crate::amount::Amount;
+use crate::assets::AssetRegistry;
+use crate::cost_basis::{
+    AcquisitionKind, CostBasis, CostBasisError, CostBasisMethod, Disposal, DisposalKind,
+};
+use crate::pricing::{PriceBook, PricingError};
+use crate::types::{AssetId, Transaction, TransactionId};
+use crate::valuation::UnpricedError;
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum TaxError {
+    #[error("invalid start of fiscal year {year}: {month:02}-{day:02}")]
+    InvalidYearStart { year: i32, month: u32, day: u32 },
+    #[error(transparent)]
+    CostBasis(#[from] CostBasisError),
+}
+
+/// How a jurisdiction taxes disposals
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaxRules {
+    pub vs_asset_id: AssetId,
+    pub method: CostBasisMethod,
+    /// First day of the fiscal year as (month, day). A fiscal year is named after the
+    /// calendar year it starts in.
+    pub year_start: (u32, u32),
+    /// Lots held longer than this are long term
+    pub long_term_after: TimeDelta,
+    /// Gains on lots held longer than this are exempt, e.g. a year in Germany.
+    /// None where holding longer doesn't exempt gains.
+    pub exempt_after: Option<TimeDelta>,
+    /// Whether withdrawals count as disposals at market value. Off by default: most are
+    /// moves to one's own wallets, or transfers between providers that weren't matched.
+    pub taxable_withdrawals: bool,
+}
+
+impl Default for TaxRules {
+    fn default() -> Self {
+        TaxRules {
+            vs_asset_id: AssetId::from("USD"),
+            method: CostBasisMethod::default(),
+            year_start: (1, 1),
+            long_term_after: TimeDelta::days(365),
+            exempt_after: None,
+            taxable_withdrawals: false,
+        }
+    }
+}
+
+impl TaxRules {
+    /// Start and end (excluded) of fiscal year `year`
+    pub fn fiscal_year(&self, year: i32) -> Result<(DateTime<Utc>, DateTime<Utc>), TaxError> {
+        let start = |year| {
+            let (month, day) = self.year_start;
+            NaiveDate::from_ymd_opt(year, month, day)
+                .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+                .ok_or(TaxError::InvalidYearStart { year, month, day })
+        };
+        Ok((start(year)?, start(year + 1)?))
+    }
+
+    fn term(&self, held: TimeDelta) -> HoldingTerm {
+        match held > self.long_term_after {
+            true => HoldingTerm::Long,
+            false => HoldingTerm::Short,
+        }
+    }
+
+    fn is_taxable(&self, kind: DisposalKind) -> bool {
+        kind != DisposalKind::Withdrawal || self.taxable_withdrawals
+    }
+
+    fn is_exempt(&self, held: TimeDelta) -> bool {
+        self.exempt_after
+            .is_some_and(|exempt_after| held > exempt_after)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoldingTerm {
+    Short,
+    Long,
+}
+
+impl HoldingTerm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HoldingTerm::Short => "short",
+            HoldingTerm::Long => "long",
+        }
+    }
+}
+
+/// The part of a disposal that consumed one lot
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaxableDisposal {
+    pub tx_id: TransactionId,
+    pub kind: DisposalKind,
+    pub amount: Amount,
+    /// None for units disposed of beyond the held lots
+    pub acquired_at: Option<DateTime<Utc>>,
+    pub disposed_at: DateTime<Utc>,
+    pub proceeds: f64,
+    pub cost: f64,
+    /// Short for units without an acquisition date
+    pub term: HoldingTerm,
+    pub exempt: bool,
+    /// False for withdrawals unless the rules tax them, left out of every total
+    pub taxable: bool,
+}
+
+impl TaxableDisposal {
+    pub fn gain(&self) -> f64 {
+        self.proceeds - self.cost
+    }
+
+    pub fn holding_period(&self) -> Option<TimeDelta> {
+        self.acquired_at
+            .map(|acquired_at| self.disposed_at - acquired_at)
+    }
+}
+
+/// Interest, rewards, airdrops... valued when received
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncomeEvent {
+    pub tx_id: TransactionId,
+    pub datetime: DateTime<Utc>,
+    pub amount: Amount,
+    pub value: f64,
+}
+
+/// What was held of an asset when the year ended
+#[derive(Debug, Clone, PartialEq)]
+pub struct YearEndHolding {
+    pub amount: Amount,
+    pub cost: f64,
+    /// None if the asset couldn't be priced
+    pub value: Option<f64>,
+}
+
+/// Everything taxable in a fiscal year, valued in the rules' quote asset
+#[derive(Debug, Clone)]
+pub struct TaxReport {
+    pub year: i32,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub rules: TaxRules,
+    pub disposals: Vec<TaxableDisposal>,
+    pub income: Vec<IncomeEvent>,
+    pub holdings: Vec<YearEndHolding>,
+    /// Prices of the year that were missing and unregistered assets, valued at zero
+    pub unpriced: Vec<UnpricedError>,
+}
+
+impl TaxReport {
+    /// Reports fiscal year `year` of a transaction log sorted by date. Earlier transactions
+    /// make up the lots the year's disposals consume.
+    pub fn generate(
+        transactions: &[Transaction],
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+        rules: &TaxRules,
+        year: i32,
+    ) -> Result<Self, TaxError> {
+        let (start, end) = rules.fiscal_year(year)?;
+        let basis = CostBasis::compute(
+            transactions.iter().take_while(|tx| tx.datetime < end),
+            rules.method,
+            assets,
+            prices,
+            &rules.vs_asset_id,
+        )?;
+        let in_year = |datetime: &DateTime<Utc>| (start..end).contains(datetime);
+
+        let mut unpriced = basis
+            .unpriced
+            .iter()
+            .filter(|err| match err {
+                UnpricedError::Pricing(PricingError::NoPrice { datetime, .. }) => in_year(datetime),
+                // undated, an unregistered asset is reported every year
+                UnpricedError::Asset(_) => true,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let disposals = basis
+            .disposals
+            .iter()
+            .filter(|disposal| in_year(&disposal.datetime))
+            .flat_map(|disposal| split_by_lot(disposal, rules))
+            .collect();
+        let income = basis
+            .acquisitions
+            .iter()
+            .filter(|a| a.kind == AcquisitionKind::Income && in_year(&a.datetime))
+            .map(|a| IncomeEvent {
+                tx_id: a.tx_id.clone(),
+                datetime: a.datetime,
+                amount: a.amount.clone(),
+                value: a.value,
+            })
+            .collect();
+
+        let mut held: BTreeMap<&AssetId, (Amount, f64)> = BTreeMap::new();
+        for lot in basis.open_lots() {
+            let asset_id = &lot.amount.asset_id;
+            let (amount, cost) = held
+                .entry(asset_id)
+                .or_insert_with(|| (Amount::zero(asset_id.clone()), 0.0));
+            *amount = amount
+                .checked_add(&lot.amount)
+                .map_err(CostBasisError::from)?;
+            *cost += lot.cost;
+        }
+        let mut holdings = Vec::new();
+        for (asset_id, (amount, cost)) in held {
+            let value = assets
+                .get(asset_id)
+                .map_err(UnpricedError::from)
+                .and_then(|asset| {
+                    let quote = prices.price(asset_id, &rules.vs_asset_id, end)?;
+                    Ok(amount.to_f64(asset.decimals) * quote.price)
+                });
+            let value = match value {
+                Ok(value) => Some(value),
+                Err(err) => {
+                    unpriced.push(err);
+                    None
+                }
+            };
+            holdings.push(YearEndHolding {
+                amount,
+                cost,
+                value,
+            });
+        }
+
+        Ok(TaxReport {
+            year,
+            start,
+            end,
+            rules: rules.clone(),
+            disposals,
+            income,
+            holdings,
+            unpriced,
+        })
+    }
+
+    pub fn total_proceeds(&self) -> f64 {
+        sum(self.taxable_disposals().map(|d| d.proceeds))
+    }
+
+    pub fn total_cost(&self) -> f64 {
+        sum(self.taxable_disposals().map(|d| d.cost))
+    }
+
+    /// Gains of the disposals held for `term`, exempt ones included
+    pub fn gain(&self, term: HoldingTerm) -> f64 {
+        let disposals = self.taxable_disposals().filter(|d| d.term == term);
+        sum(disposals.map(TaxableDisposal::gain))
+    }
+
+    pub fn exempt_gain(&self) -> f64 {
+        sum(self
+            .taxable_disposals()
+            .filter(|d| d.exempt)
+            .map(TaxableDisposal::gain))
+    }
+
+    pub fn taxable_gain(&self) -> f64 {
+        sum(self
+            .taxable_disposals()
+            .filter(|d| !d.exempt)
+            .map(TaxableDisposal::gain))
+    }
+
+    /// Disposals the totals are made of
+    fn taxable_disposals(&self) -> impl Iterator<Item = &TaxableDisposal> {
+        self.disposals.iter().filter(|d| d.taxable)
+    }
+
+    pub fn total_income(&self) -> f64 {
+        sum(self.income.iter().map(|i| i.value))
+    }
+}
+
+/// Sum that is 0 rather than -0 when empty
+fn sum(values: impl Iterator<Item = f64>) -> f64 {
+    values.fold(0.0, |total, value| total + value)
+}
+
+/// One line per lot consumed, and one for the units beyond them. Proceeds are shared
+/// pro rata.
+fn split_by_lot(disposal: &Disposal, rules: &TaxRules) -> Vec<TaxableDisposal> {
+    let share =
+        |amount: &Amount| disposal.proceeds * amount.units as f64 / disposal.amount.units as f64;
+    let line = |amount: &Amount, acquired_at: Option<DateTime<Utc>>, cost| {
+        let held = acquired_at.map(|acquired_at| disposal.datetime - acquired_at);
+        TaxableDisposal {
+            tx_id: disposal.tx_id.clone(),
+            kind: disposal.kind,
+            amount: amount.clone(),
+            acquired_at,
+            disposed_at: disposal.datetime,
+            proceeds: share(amount),
+            cost,
+            term: held.map_or(HoldingTerm::Short, |held| rules.term(held)),
+            exempt: held.is_some_and(|held| rules.is_exempt(held)),
+            taxable: rules.is_taxable(disposal.kind),
+        }
+    };
+    let mut lines = disposal
+        .lots
+        .iter()
+        .map(|slice| line(&slice.amount, Some(slice.acquired_at), slice.cost))
+        .collect::<Vec<_>>();
+    if !disposal.uncovered.is_zero() {
+        lines.push(line(&disposal.uncovered, None, 0.0));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::AssetPricePoint;
+    use crate::types::{AccountId, AccountKind, ProviderId, TxEffect};
+    use chrono::TimeZone;
+
+    fn at(y: i32, m: u32, d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, 12, 0, 0).unwrap()
+    }
+
+    fn eth(value: f64) -> Amount {
+        Amount::from_f64(AssetId::from("ETH"), value, 18).unwrap()
+    }
+
+    fn tx(
+        id: &str,
+        datetime: DateTime<Utc>,
+        from: AccountKind,
+        to: AccountKind,
+        amount: Amount,
+    ) -> Transaction {
+        let nexo = ProviderId::from("nexo");
+        let effect = |kind, amount| TxEffect {
+            account_id: AccountId {
+                provider: nexo.clone(),
+                asset: AssetId::from("ETH"),
+                kind,
+            },
+            amount,
+            datetime,
+        };
+        Transaction {
+            id: TransactionId::native(&nexo, id),
+            inputs: vec![effect(from, amount.checked_neg().unwrap())],
+            outputs: vec![effect(to, amount)],
+            datetime,
+            tx_hash: None,
+        }
+    }
+
+    #[test]
+    fn test_fiscal_year_report() -> anyhow::Result<()> {
+        use AccountKind::{External, Holding, Income};
+        let transactions = [
+            tx("D1", at(2022, 6, 1), External, Holding, eth(1.0)),
+            tx("D2", at(2023, 9, 1), External, Holding, eth(1.0)),
+            tx("I1", at(2023, 12, 1), Income, Holding, eth(0.1)),
+            // consumes all of D1 and half of D2 when FIFO
+            tx("W1", at(2024, 2, 1), Holding, External, eth(1.5)),
+            tx("W2", at(2024, 5, 1), Holding, External, eth(0.1)),
+        ];
+        let mut prices = PriceBook::new().with_max_staleness(TimeDelta::days(400));
+        for (datetime, price) in [
+            (at(2022, 6, 1), 1000.0),
+            (at(2023, 9, 1), 1600.0),
+            (at(2023, 12, 1), 2000.0),
+            (at(2024, 2, 1), 2200.0),
+        ] {
+            prices.insert(AssetPricePoint {
+                datetime,
+                asset_id: AssetId::from("ETH"),
+                vs_asset_id: AssetId::from("EUR"),
+                price,
+            });
+        }
+        // a fiscal year from April, gains exempt after a year, withdrawals sold at market
+        let rules = TaxRules {
+            vs_asset_id: AssetId::from("EUR"),
+            year_start: (4, 6),
+            exempt_after: Some(TimeDelta::days(365)),
+            taxable_withdrawals: true,
+            ..TaxRules::default()
+        };
+        let assets = AssetRegistry::builtin();
+        let report = TaxReport::generate(&transactions, &assets, &prices, &rules, 2023)?;
+        assert_eq!(
+            (report.start, report.end),
+            (
+                Utc.with_ymd_and_hms(2023, 4, 6, 0, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 4, 6, 0, 0, 0).unwrap()
+            )
+        );
+
+        let [long, short] = &report.disposals[..] else {
+            panic!(
+                "expected W1 to consume two lots, got {:?}",
+                report.disposals
+            );
+        };
+        assert_eq!(long.acquired_at, Some(at(2022, 6, 1)));
+        assert_eq!((long.term, long.exempt), (HoldingTerm::Long, true));
+        assert_eq!(long.holding_period(), Some(at(2024, 2, 1) - at(2022, 6, 1)));
+        assert_eq!((long.proceeds.round(), long.cost.round()), (2200.0, 1000.0));
+        assert_eq!((short.term, short.exempt), (HoldingTerm::Short, false));
+        assert_eq!(
+            (short.proceeds.round(), short.cost.round()),
+            (1100.0, 800.0)
+        );
+        assert_eq!(report.taxable_gain().round(), 300.0);
+        assert_eq!(report.exempt_gain().round(), 1200.0);
+        assert_eq!(report.gain(HoldingTerm::Long).round(), 1200.0);
+
+        let [income] = &report.income[..] else {
+            panic!("expected one income event");
+        };
+        assert_eq!(
+            (income.amount.clone(), income.value.round()),
+            (eth(0.1), 200.0)
+        );
+        assert_eq!(report.total_income().round(), 200.0);
+
+        // 0.5 of D2 and the income lot, W2 falls in the next year
+        let [holding] = &report.holdings[..] else {
+            panic!("expected ETH only");
+        };
+        assert_eq!(holding.amount, eth(0.6));
+        assert_eq!(holding.cost.round(), 1000.0);
+        assert_eq!(holding.value.map(f64::round), Some(1320.0));
+        assert!(report.unpriced.is_empty());
+
+        // an unregistered asset is held at no value rather than failing the report
+        let mut with_unknown = transactions.to_vec();
+        let mut deposit = tx("D3", at(2023, 10, 1), External, Holding, eth(1.0));
+        for effect in deposit.inputs.iter_mut().chain(deposit.outputs.iter_mut()) {
+            effect.amount.asset_id = AssetId::from("NOT-LISTED");
+            effect.account_id.asset = AssetId::from("NOT-LISTED");
+        }
+        with_unknown.insert(2, deposit);
+        let report = TaxReport::generate(&with_unknown, &assets, &prices, &rules, 2023)?;
+        let unknown = report
+            .holdings
+            .iter()
+            .find(|h| h.amount.asset_id == AssetId::from("NOT-LISTED"))
+            .expect("unregistered holding");
+        assert_eq!(unknown.value, None);
+        assert!(
+            report
+                .unpriced
+                .iter()
+                .all(|err| matches!(err, UnpricedError::Asset(_)))
+        );
+        assert!(!report.unpriced.is_empty());
+
+        // by default withdrawals are listed, but are moves rather than sales
+        let untaxed = TaxRules {
+            taxable_withdrawals: false,
+            ..rules.clone()
+        };
+        let report = TaxReport::generate(&transactions, &assets, &prices, &untaxed, 2023)?;
+        assert_eq!(report.disposals.len(), 2);
+        assert!(report.disposals.iter().all(|d| !d.taxable));
+        assert_eq!(report.taxable_gain(), 0.0);
+        assert_eq!(report.total_proceeds(), 0.0);
+        assert_eq!(report.total_income().round(), 200.0);
+
+        let next = TaxReport::generate(&transactions, &assets, &prices, &rules, 2024)?;
+        assert_eq!(next.disposals.len(), 1);
+        assert!(next.income.is_empty());
+        let invalid = TaxRules {
+            year_start: (2, 30),
+            ..TaxRules::default()
+        };
+        assert!(invalid.fiscal_year(2024).is_err());
+        Ok(())
+    }
+}
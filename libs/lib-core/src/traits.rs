@@ -2,7 +2,8 @@
 
 use crate::{
     AccountId,
-    types::{Position, ProviderId, Transaction},
+    sync::{SyncBatch, SyncCursor},
+    types::{Position, Product, ProviderId, Transaction},
 };
 
 /// Provider trait with name method (object-safe)
@@ -17,15 +18,26 @@ pub trait Issuer3 {
 
 /// Core provider interface for fetching positions and transactions
 #[async_trait::async_trait]
-pub trait IsProvider {
+pub trait IsProvider: Send + Sync {
     /// Returns the unique identifier for this provider
     fn provider_id(&self) -> ProviderId;
 
     /// Fetches all positions from the provider
     async fn fetch_positions(&self) -> anyhow::Result<Vec<Position>>;
 
+    /// Fetches the products the positions are invested in
+    async fn fetch_products(&self) -> anyhow::Result<Vec<Product>> {
+        Ok(Vec::new())
+    }
+
     /// Fetches all transactions from the provider
     async fn fetch_transactions(&self) -> anyhow::Result<Vec<Transaction>>;
+
+    /// Fetches the transactions that are new since `cursor`.
+    /// Providers that can't resume fetch everything.
+    async fn fetch_transactions_since(&self, _cursor: &SyncCursor) -> anyhow::Result<SyncBatch> {
+        Ok(SyncBatch::full(self.fetch_transactions().await?))
+    }
 }
 
 /// For types that have an associated account ID
@@ -0,0 +1,574 @@
+//! Transfers between providers: a withdrawal matched with the deposit it became elsewhere,
+//! so moving funds between tracked accounts isn't counted as leaving and re-entering.
+
+use crate::amount::{Amount, AmountError};
+use crate::types::{AccountId, AccountKind, Transaction, TransactionId, TxEffect};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TransferError {
+    #[error("unknown transfer decision {0:?}")]
+    UnknownDecision(String),
+    #[error("{} is not a withdrawal to outside its provider", id.0)]
+    NotAWithdrawal { id: TransactionId },
+    #[error("{} is not a deposit from outside its provider", id.0)]
+    NotADeposit { id: TransactionId },
+    #[error("{} and {} move different assets", withdrawal.0, deposit.0)]
+    AssetMismatch {
+        withdrawal: TransactionId,
+        deposit: TransactionId,
+    },
+    #[error(transparent)]
+    Amount(#[from] AmountError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecisionKind {
+    /// The pair is a transfer, whatever the matcher suggests
+    Confirmed,
+    /// The pair is not a transfer, the matcher won't suggest it again
+    Rejected,
+}
+
+impl DecisionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DecisionKind::Confirmed => "confirmed",
+            DecisionKind::Rejected => "rejected",
+        }
+    }
+}
+
+impl FromStr for DecisionKind {
+    type Err = TransferError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "confirmed" => Ok(DecisionKind::Confirmed),
+            "rejected" => Ok(DecisionKind::Rejected),
+            other => Err(TransferError::UnknownDecision(other.to_string())),
+        }
+    }
+}
+
+/// A user decision on a withdrawal and deposit pair, see [`crate::Store::save_transfer_decision`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferDecision {
+    pub withdrawal_id: TransactionId,
+    pub deposit_id: TransactionId,
+    pub kind: DecisionKind,
+}
+
+/// A withdrawal and the deposit it became
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferMatch {
+    pub withdrawal_id: TransactionId,
+    pub deposit_id: TransactionId,
+    /// Account the funds left
+    pub from: AccountId,
+    /// Account the funds arrived in
+    pub to: AccountId,
+    pub sent: Amount,
+    pub received: Amount,
+    pub sent_at: DateTime<Utc>,
+    pub received_at: DateTime<Utc>,
+    /// Both sides report the same on-chain hash
+    pub by_hash: bool,
+    /// Confirmed by the user rather than suggested
+    pub confirmed: bool,
+}
+
+impl TransferMatch {
+    /// What was lost on the way, e.g. a network fee. Negative if more arrived than was sent.
+    pub fn fee(&self) -> Result<Amount, AmountError> {
+        self.sent.checked_sub(&self.received)
+    }
+
+    pub fn delay(&self) -> TimeDelta {
+        self.received_at - self.sent_at
+    }
+}
+
+/// A transaction moving a single asset to or from outside its provider
+#[derive(Debug, Clone)]
+struct Leg<'a> {
+    tx: &'a Transaction,
+    /// The tracked account funds left or arrived in
+    account: AccountId,
+    /// Positive amount that left or arrived
+    amount: Amount,
+    tx_hash: Option<String>,
+}
+
+impl<'a> Leg<'a> {
+    fn withdrawal(tx: &'a Transaction) -> Option<Self> {
+        let sent = single_external(&tx.outputs, &tx.inputs)?;
+        let from = tracked_account(&tx.inputs, sent)?;
+        Some(Leg::new(tx, from, sent.amount.clone()))
+    }
+
+    fn deposit(tx: &'a Transaction) -> Option<Self> {
+        let received = single_external(&tx.inputs, &tx.outputs)?;
+        let to = tracked_account(&tx.outputs, received)?;
+        let amount = received.amount.checked_neg().ok()?;
+        Some(Leg::new(tx, to, amount))
+    }
+
+    fn new(tx: &'a Transaction, account: AccountId, amount: Amount) -> Self {
+        Leg {
+            tx,
+            account,
+            amount,
+            tx_hash: tx.tx_hash.as_deref().map(normalize_hash),
+        }
+    }
+}
+
+/// The only external effect of one side, when the other side has none. Trades go through
+/// external accounts both ways, so they're left out.
+fn single_external<'a>(side: &'a [TxEffect], other: &[TxEffect]) -> Option<&'a TxEffect> {
+    let is_external = |effect: &&TxEffect| effect.account_id.kind == AccountKind::External;
+    if other.iter().any(|effect| is_external(&effect)) {
+        return None;
+    }
+    let mut external = side.iter().filter(is_external);
+    let effect = external.next()?;
+    external.next().is_none().then_some(effect)
+}
+
+fn tracked_account(side: &[TxEffect], external: &TxEffect) -> Option<AccountId> {
+    side.iter()
+        .find(|effect| {
+            !effect.account_id.is_counter_account()
+                && effect.amount.asset_id == external.amount.asset_id
+        })
+        .map(|effect| effect.account_id.clone())
+}
+
+fn normalize_hash(hash: &str) -> String {
+    let hash = hash.trim().to_lowercase();
+    hash.strip_prefix("0x").map(str::to_string).unwrap_or(hash)
+}
+
+/// Pairs withdrawals with deposits on another provider: same asset, received within
+/// a time window and at most a fee short of what was sent, or the same on-chain hash
+#[derive(Debug, Clone)]
+pub struct TransferMatcher {
+    window: TimeDelta,
+    clock_skew: TimeDelta,
+    max_fee_ratio: f64,
+}
+
+impl Default for TransferMatcher {
+    fn default() -> Self {
+        TransferMatcher {
+            window: TimeDelta::hours(24),
+            clock_skew: TimeDelta::hours(1),
+            max_fee_ratio: 0.05,
+        }
+    }
+}
+
+impl TransferMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Longest time between a withdrawal and its deposit
+    pub fn with_window(mut self, window: TimeDelta) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// How early a deposit can be dated, providers' clocks don't always agree
+    pub fn with_clock_skew(mut self, clock_skew: TimeDelta) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    /// Largest share of the sent amount that can be lost on the way
+    pub fn with_max_fee_ratio(mut self, max_fee_ratio: f64) -> Self {
+        self.max_fee_ratio = max_fee_ratio;
+        self
+    }
+
+    /// Confirmed pairs, then the best suggestion for every other withdrawal, oldest first.
+    /// Rejected pairs are never suggested, decisions on transactions not around are ignored.
+    pub fn resolve(
+        &self,
+        transactions: &[Transaction],
+        decisions: &[TransferDecision],
+    ) -> Vec<TransferMatch> {
+        let withdrawals = transactions
+            .iter()
+            .filter_map(Leg::withdrawal)
+            .collect::<Vec<_>>();
+        let deposits = transactions
+            .iter()
+            .filter_map(Leg::deposit)
+            .collect::<Vec<_>>();
+        let withdrawal_by_id = withdrawals
+            .iter()
+            .map(|leg| (&leg.tx.id, leg))
+            .collect::<HashMap<_, _>>();
+        let deposit_by_id = deposits
+            .iter()
+            .map(|leg| (&leg.tx.id, leg))
+            .collect::<HashMap<_, _>>();
+
+        let mut matches = Vec::new();
+        let mut used = HashSet::new();
+        for decision in decisions {
+            if decision.kind != DecisionKind::Confirmed {
+                continue;
+            }
+            let (Some(withdrawal), Some(deposit)) = (
+                withdrawal_by_id.get(&decision.withdrawal_id),
+                deposit_by_id.get(&decision.deposit_id),
+            ) else {
+                continue;
+            };
+            if withdrawal.amount.asset_id != deposit.amount.asset_id
+                || used.contains(&withdrawal.tx.id)
+                || used.contains(&deposit.tx.id)
+            {
+                continue;
+            }
+            used.extend([&withdrawal.tx.id, &deposit.tx.id]);
+            matches.push(transfer_match(withdrawal, deposit, true));
+        }
+
+        let rejected = decisions
+            .iter()
+            .filter(|decision| decision.kind == DecisionKind::Rejected)
+            .map(|decision| (&decision.withdrawal_id, &decision.deposit_id))
+            .collect::<HashSet<_>>();
+        for withdrawal in &withdrawals {
+            if used.contains(&withdrawal.tx.id) {
+                continue;
+            }
+            let best = deposits
+                .iter()
+                .filter(|deposit| {
+                    !used.contains(&deposit.tx.id)
+                        && !rejected.contains(&(&withdrawal.tx.id, &deposit.tx.id))
+                        && self.accepts(withdrawal, deposit)
+                })
+                .min_by_key(|deposit| {
+                    let by_hash =
+                        withdrawal.tx_hash.is_some() && withdrawal.tx_hash == deposit.tx_hash;
+                    (
+                        !by_hash,
+                        (deposit.tx.datetime - withdrawal.tx.datetime).abs(),
+                    )
+                });
+            if let Some(deposit) = best {
+                used.extend([&withdrawal.tx.id, &deposit.tx.id]);
+                matches.push(transfer_match(withdrawal, deposit, false));
+            }
+        }
+        matches.sort_by_key(|m| m.sent_at);
+        matches
+    }
+
+    /// The pair as a transfer, whether or not the matcher would suggest it.
+    /// Used to check a pair before confirming it.
+    pub fn pair(
+        &self,
+        transactions: &[Transaction],
+        withdrawal_id: &TransactionId,
+        deposit_id: &TransactionId,
+    ) -> Result<TransferMatch, TransferError> {
+        let find = |id| transactions.iter().find(|tx| &tx.id == id);
+        let withdrawal = find(withdrawal_id)
+            .and_then(Leg::withdrawal)
+            .ok_or_else(|| TransferError::NotAWithdrawal {
+                id: withdrawal_id.clone(),
+            })?;
+        let deposit =
+            find(deposit_id)
+                .and_then(Leg::deposit)
+                .ok_or_else(|| TransferError::NotADeposit {
+                    id: deposit_id.clone(),
+                })?;
+        if withdrawal.amount.asset_id != deposit.amount.asset_id {
+            return Err(TransferError::AssetMismatch {
+                withdrawal: withdrawal_id.clone(),
+                deposit: deposit_id.clone(),
+            });
+        }
+        Ok(transfer_match(&withdrawal, &deposit, true))
+    }
+
+    fn accepts(&self, withdrawal: &Leg, deposit: &Leg) -> bool {
+        if withdrawal.account.provider == deposit.account.provider
+            || withdrawal.amount.asset_id != deposit.amount.asset_id
+        {
+            return false;
+        }
+        if let (Some(sent), Some(received)) = (&withdrawal.tx_hash, &deposit.tx_hash) {
+            return sent == received;
+        }
+        let delay = deposit.tx.datetime - withdrawal.tx.datetime;
+        let (sent, received) = (withdrawal.amount.units, deposit.amount.units);
+        delay >= -self.clock_skew
+            && delay <= self.window
+            && received <= sent
+            && (sent - received) as f64 <= sent as f64 * self.max_fee_ratio
+    }
+}
+
+fn transfer_match(withdrawal: &Leg, deposit: &Leg, confirmed: bool) -> TransferMatch {
+    TransferMatch {
+        withdrawal_id: withdrawal.tx.id.clone(),
+        deposit_id: deposit.tx.id.clone(),
+        from: withdrawal.account.clone(),
+        to: deposit.account.clone(),
+        sent: withdrawal.amount.clone(),
+        received: deposit.amount.clone(),
+        sent_at: withdrawal.tx.datetime,
+        received_at: deposit.tx.datetime,
+        by_hash: withdrawal.tx_hash.is_some() && withdrawal.tx_hash == deposit.tx_hash,
+        confirmed,
+    }
+}
+
+/// Replaces each matched pair with a single transaction moving the funds from one account
+/// to the other, dated at the later of the two. The difference is charged as a fee on the
+/// sending provider. Transactions stay sorted by date.
+pub fn merge_transfers(
+    transactions: Vec<Transaction>,
+    matches: &[TransferMatch],
+) -> Result<Vec<Transaction>, TransferError> {
+    let mut by_id = transactions
+        .into_iter()
+        .map(|tx| (tx.id.clone(), tx))
+        .collect::<HashMap<_, _>>();
+    let mut merged = Vec::new();
+    for m in matches {
+        let withdrawal = by_id
+            .remove(&m.withdrawal_id)
+            .filter(|tx| Leg::withdrawal(tx).is_some())
+            .ok_or_else(|| TransferError::NotAWithdrawal {
+                id: m.withdrawal_id.clone(),
+            })?;
+        let deposit = by_id
+            .remove(&m.deposit_id)
+            .filter(|tx| Leg::deposit(tx).is_some())
+            .ok_or_else(|| TransferError::NotADeposit {
+                id: m.deposit_id.clone(),
+            })?;
+        merged.push(internal_transfer(withdrawal, deposit, m)?);
+    }
+    merged.extend(by_id.into_values());
+    merged.sort_by(|a, b| (a.datetime, &a.id.0).cmp(&(b.datetime, &b.id.0)));
+    Ok(merged)
+}
+
+fn internal_transfer(
+    withdrawal: Transaction,
+    deposit: Transaction,
+    m: &TransferMatch,
+) -> Result<Transaction, TransferError> {
+    let is_external = |effect: &TxEffect| effect.account_id.kind == AccountKind::External;
+    let datetime = withdrawal.datetime.max(deposit.datetime);
+    let mut inputs = withdrawal.inputs;
+    inputs.extend(deposit.inputs.into_iter().filter(|e| !is_external(e)));
+    let mut outputs = withdrawal.outputs;
+    outputs.retain(|e| !is_external(e));
+    outputs.extend(deposit.outputs);
+
+    let fee = m.fee()?;
+    if fee.is_negative() {
+        inputs.push(TxEffect {
+            account_id: AccountId::external(m.to.provider.clone(), fee.asset_id.clone()),
+            amount: fee,
+            datetime: m.received_at,
+        });
+    } else if !fee.is_zero() {
+        outputs.push(TxEffect {
+            account_id: AccountId::fee(m.from.provider.clone(), fee.asset_id.clone()),
+            amount: fee,
+            datetime: m.received_at,
+        });
+    }
+    Ok(Transaction {
+        id: TransactionId(format!("{}>{}", withdrawal.id.0, deposit.id.0)),
+        inputs,
+        outputs,
+        datetime,
+        tx_hash: withdrawal.tx_hash.or(deposit.tx_hash),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balances::BalanceReplay;
+    use crate::types::{AssetId, ProviderId};
+    use chrono::TimeZone;
+
+    fn at(h: u32, m: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 2, h, m, 0).unwrap()
+    }
+
+    fn tx(
+        id: &str,
+        datetime: DateTime<Utc>,
+        moves: &[(&AccountId, &AccountId, i128)],
+    ) -> Transaction {
+        let effect = |account_id: &AccountId, units| TxEffect {
+            account_id: account_id.clone(),
+            amount: Amount::new(account_id.asset.clone(), units),
+            datetime,
+        };
+        Transaction {
+            id: TransactionId::from(id),
+            inputs: moves.iter().map(|(from, _, u)| effect(from, -u)).collect(),
+            outputs: moves.iter().map(|(_, to, u)| effect(to, *u)).collect(),
+            datetime,
+            tx_hash: None,
+        }
+    }
+
+    struct Accounts {
+        nexo: AccountId,
+        nexo_out: AccountId,
+        nexo_fee: AccountId,
+        binance: AccountId,
+        binance_in: AccountId,
+        kraken: AccountId,
+        kraken_in: AccountId,
+    }
+
+    fn accounts() -> Accounts {
+        let usdt = AssetId::from("USDT");
+        let [nexo, binance, kraken] = ["nexo", "binance", "kraken"].map(ProviderId::from);
+        Accounts {
+            nexo: AccountId::new(nexo.clone(), usdt.clone()),
+            nexo_out: AccountId::external(nexo.clone(), usdt.clone()),
+            nexo_fee: AccountId::fee(nexo, usdt.clone()),
+            binance: AccountId::new(binance.clone(), usdt.clone()),
+            binance_in: AccountId::external(binance, usdt.clone()),
+            kraken: AccountId::new(kraken.clone(), usdt.clone()),
+            kraken_in: AccountId::external(kraken, usdt),
+        }
+    }
+
+    /// 100 USDT withdrawn from Nexo with a 2.5 fee, 99 arriving on Binance half an hour
+    /// later, and a few deposits that don't fit
+    fn transactions(a: &Accounts) -> Vec<Transaction> {
+        let mut withdrawal = tx(
+            "nexo:W1",
+            at(10, 0),
+            &[(&a.nexo, &a.nexo_out, 100), (&a.nexo, &a.nexo_fee, 2)],
+        );
+        withdrawal.tx_hash = Some("0xAB12".to_string());
+        vec![
+            tx("nexo:D0", at(9, 0), &[(&a.nexo_out, &a.nexo, 500)]),
+            withdrawal,
+            tx("kraken:D1", at(10, 20), &[(&a.kraken_in, &a.kraken, 90)]),
+            tx("binance:D2", at(10, 30), &[(&a.binance_in, &a.binance, 99)]),
+            tx("kraken:D3", at(10, 40), &[(&a.kraken_in, &a.kraken, 99)]),
+        ]
+    }
+
+    #[test]
+    fn test_suggests_and_merges_transfers() -> anyhow::Result<()> {
+        let a = accounts();
+        let transactions = transactions(&a);
+        let matcher = TransferMatcher::new();
+        let matches = matcher.resolve(&transactions, &[]);
+        let [m] = &matches[..] else {
+            panic!("expected one match, got {matches:?}");
+        };
+        // too short: kraken:D1, same provider: nexo:D0, later: kraken:D3
+        assert_eq!(m.deposit_id, TransactionId::from("binance:D2"));
+        assert_eq!(
+            (m.from.clone(), m.to.clone()),
+            (a.nexo.clone(), a.binance.clone())
+        );
+        assert_eq!(m.fee()?.units, 1);
+        assert_eq!(m.delay(), TimeDelta::minutes(30));
+        assert!(!m.by_hash && !m.confirmed);
+
+        let merged = merge_transfers(transactions, &matches)?;
+        let ids = merged.iter().map(|tx| tx.id.0.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            ["nexo:D0", "kraken:D1", "nexo:W1>binance:D2", "kraken:D3"]
+        );
+        let transfer = &merged[2];
+        transfer.validate()?;
+        assert_eq!(transfer.datetime, at(10, 30));
+        assert!(
+            transfer
+                .effects()
+                .all(|e| e.account_id.kind != AccountKind::External)
+        );
+
+        let replay = BalanceReplay::replay(&merged)?;
+        let balance = |account| replay.balance_at(account, at(12, 0)).map(|b| b.units);
+        assert_eq!(balance(&a.nexo), Some(398));
+        assert_eq!(balance(&a.binance), Some(99));
+        assert_eq!(balance(&a.nexo_fee), Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hashes_and_decisions() -> anyhow::Result<()> {
+        let a = accounts();
+        let mut transactions = transactions(&a);
+        // the later deposit reports the withdrawal's hash, the closer one another one
+        transactions[3].tx_hash = Some("0xdead".to_string());
+        transactions[4].tx_hash = Some("ab12".to_string());
+        let matcher = TransferMatcher::new();
+        let matches = matcher.resolve(&transactions, &[]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].deposit_id, TransactionId::from("kraken:D3"));
+        assert!(matches[0].by_hash);
+
+        let decision = |deposit: &str, kind| TransferDecision {
+            withdrawal_id: TransactionId::from("nexo:W1"),
+            deposit_id: TransactionId::from(deposit),
+            kind,
+        };
+        let rejected = [decision("kraken:D3", DecisionKind::Rejected)];
+        assert!(matcher.resolve(&transactions, &rejected).is_empty());
+
+        // a confirmed pair wins over the matcher's rules
+        let confirmed = [decision("kraken:D1", DecisionKind::Confirmed)];
+        let matches = matcher.resolve(&transactions, &confirmed);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].deposit_id, TransactionId::from("kraken:D1"));
+        assert!(matches[0].confirmed);
+        let merged = merge_transfers(transactions.clone(), &matches)?;
+        merged.iter().try_for_each(Transaction::validate)?;
+
+        let w1 = TransactionId::from("nexo:W1");
+        assert!(
+            matcher
+                .pair(&transactions, &w1, &"binance:D2".into())
+                .is_ok()
+        );
+        assert_eq!(
+            matcher.pair(&transactions, &"binance:D2".into(), &w1),
+            Err(TransferError::NotAWithdrawal {
+                id: "binance:D2".into()
+            })
+        );
+
+        // a stale match whose deposit is gone
+        let mut stale = matches[0].clone();
+        stale.deposit_id = "kraken:D404".into();
+        assert_eq!(
+            merge_transfers(transactions, &[stale]).err(),
+            Some(TransferError::NotADeposit {
+                id: "kraken:D404".into()
+            })
+        );
+        Ok(())
+    }
+}
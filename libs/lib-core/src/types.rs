@@ -1,3 +1,5 @@
+use crate::amount::{Amount, AmountError};
+use crate::assets::AssetError;
 use crate::traits::Issuer3;
 use chrono::{DateTime, Utc};
 
@@ -8,28 +10,113 @@ use std::collections::HashMap;
 pub struct TxEffect {
     /// The account affected by this effect (e.g. "Binance", "WalletA")
     pub account_id: AccountId,
-    /// The change in balance. Positive for debit (increase?), Negative for credit (decrease?)
-    /// OR: In accounting, Debit is usually positive (assets increase), Credit is negative (assets decrease).
-    /// The design doc says: "Outflow: Negative amount", "Inflow: Positive amount".
-    pub amount: u64,
+    /// The change in balance: negative for an outflow, positive for an inflow.
+    pub amount: Amount,
     pub datetime: DateTime<Utc>,
 }
 
 // inside a transaction, inputs and outputs must be balanced (sum of inputs == sum of outputs)
+// money coming from or going to outside the tracked accounts goes through counter-accounts (see AccountKind)
 pub struct TxInput(pub TxEffect); // An input withdraws from an account
 pub struct TxOutput(pub TxEffect); // An output deposits into an account
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
+    /// Deterministic id, the same every time the record is imported
+    #[serde(default)]
+    pub id: TransactionId,
     /// List of effects that represent money leaving an account (debits/outflows).
     pub inputs: Vec<TxEffect>,
     /// List of effects that represent money entering an account (credits/inflows).
     pub outputs: Vec<TxEffect>,
     /// The time the transaction occurred.
     pub datetime: DateTime<Utc>,
+    /// On-chain hash of a deposit or withdrawal, when the provider reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_hash: Option<String>,
+}
+
+impl Transaction {
+    /// All effects, inputs first
+    pub fn effects(&self) -> impl Iterator<Item = &TxEffect> {
+        self.inputs.iter().chain(self.outputs.iter())
+    }
+
+    /// Net change per asset across all effects. A balanced transaction nets to zero everywhere.
+    pub fn net_by_asset(&self) -> Result<HashMap<AssetId, Amount>, AmountError> {
+        let mut net: HashMap<AssetId, Amount> = HashMap::new();
+        for effect in self.effects() {
+            let asset_id = &effect.amount.asset_id;
+            let total = net
+                .entry(asset_id.clone())
+                .or_insert_with(|| Amount::zero(asset_id.clone()));
+            *total = total.checked_add(&effect.amount)?;
+        }
+        Ok(net)
+    }
+
+    /// Whether inputs and outputs cancel out for every asset
+    pub fn is_balanced(&self) -> Result<bool, AmountError> {
+        Ok(self.net_by_asset()?.values().all(Amount::is_zero))
+    }
+
+    /// Non-zero net amounts per asset, sorted by asset for stable reporting
+    pub fn imbalances(&self) -> Result<Vec<Amount>, AmountError> {
+        let mut imbalances: Vec<Amount> = self
+            .net_by_asset()?
+            .into_values()
+            .filter(|net| !net.is_zero())
+            .collect();
+        imbalances.sort_by_key(|net| format!("{:?}", net.asset_id));
+        Ok(imbalances)
+    }
+
+    /// Checks the double-entry invariants: inputs are outflows, outputs are inflows,
+    /// and every asset nets to zero once counter-accounts are included.
+    pub fn validate(&self) -> Result<(), TxValidationError> {
+        if self.inputs.is_empty() && self.outputs.is_empty() {
+            return Err(TxValidationError::Empty);
+        }
+        if let Some(input) = self.inputs.iter().find(|e| e.amount.units > 0) {
+            return Err(TxValidationError::InflowInInputs(input.account_id.clone()));
+        }
+        if let Some(output) = self.outputs.iter().find(|e| e.amount.units < 0) {
+            return Err(TxValidationError::OutflowInOutputs(
+                output.account_id.clone(),
+            ));
+        }
+        let imbalances = self.imbalances()?;
+        if !imbalances.is_empty() {
+            return Err(TxValidationError::Unbalanced(imbalances));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TxValidationError {
+    #[error("transaction has no effects")]
+    Empty,
+    #[error("input on {0:?} is an inflow, inputs must have negative amounts")]
+    InflowInInputs(AccountId),
+    #[error("output on {0:?} is an outflow, outputs must have positive amounts")]
+    OutflowInOutputs(AccountId),
+    #[error("unbalanced transaction, net amounts: {}", fmt_amounts(.0))]
+    Unbalanced(Vec<Amount>),
+    #[error(transparent)]
+    Amount(#[from] AmountError),
+}
+
+fn fmt_amounts(amounts: &[Amount]) -> String {
+    amounts
+        .iter()
+        .map(|a| format!("{} {:?}", a.units, a.asset_id))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 /// Database structure containing all domain entities
+#[derive(Default)]
 pub struct Db {
     pub assets: HashMap<AssetId, AssetId>,
     pub positions: HashMap<PositionId, Position>,
@@ -39,17 +126,19 @@ pub struct Db {
 
 impl Db {
     pub fn new() -> Self {
-        Db {
-            assets: HashMap::new(),
-            positions: HashMap::new(),
-            products: HashMap::new(),
-            transactions: HashMap::new(),
-        }
+        Self::default()
     }
 
-    fn upsert_position(&mut self, position: &Position) {
+    pub fn upsert_position(&mut self, position: &Position) {
         self.positions.insert(position.id.clone(), position.clone());
     }
+
+    /// Inserts a transaction under its id, refusing any that doesn't balance
+    pub fn insert_transaction(&mut self, tx: Transaction) -> Result<(), TxValidationError> {
+        tx.validate()?;
+        self.transactions.insert(tx.id.clone(), tx);
+        Ok(())
+    }
 }
 
 /// Provider identifier
@@ -63,55 +152,35 @@ impl From<&str> for ProviderId {
     }
 }
 
-/// Asset identifier - represents known and unknown assets
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub enum AssetId {
-    Eth,
-    Unknown(String),
-}
+/// Canonical asset identifier (e.g. "ETH"), providers' symbols resolve to it via the AssetRegistry
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AssetId(pub String);
 
-impl AssetId {
-    pub fn unknown(name: &str) -> Self {
-        println!("Unknown asset: {}", name);
-        AssetId::Unknown(name.to_string())
+impl<S: AsRef<str>> From<S> for AssetId {
+    fn from(id: S) -> Self {
+        AssetId(id.as_ref().to_string())
     }
 }
 
-/// External asset ID from a specific provider
-#[derive(Debug, Clone)]
+/// An asset's symbol on a specific provider. "BTC" on Binance and "BTC" on Nexo are distinct ids.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ExternalAssetId {
+    pub provider: ProviderId,
     pub id: String,
-    pub issuer_name: String,
-    _asset: std::marker::PhantomData<AssetId>,
 }
 
 impl ExternalAssetId {
     pub fn new<Issuer: Issuer3>(id: &str) -> Self {
+        ExternalAssetId::with_issuer(&ProviderId::from(Issuer::name()), id)
+    }
+
+    pub fn with_issuer(issuer: &ProviderId, id: &str) -> Self {
         ExternalAssetId {
+            provider: issuer.clone(),
             id: id.to_string(),
-            issuer_name: Issuer::name().to_string(),
-            _asset: std::marker::PhantomData,
         }
     }
-
-    pub fn issuer_id(&self) -> ProviderId {
-        ProviderId::from(self.issuer_name.as_str())
-    }
-}
-
-impl PartialEq for ExternalAssetId {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
-    }
-}
-
-impl Eq for ExternalAssetId {}
-
-impl std::hash::Hash for ExternalAssetId {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.issuer_name.hash(state);
-        self.id.hash(state);
-    }
 }
 
 /// Asset with its cross-provider mappings
@@ -125,13 +194,36 @@ pub struct Asset {
 
 impl Asset {
     pub fn with_ext_id(mut self, ext_id: ExternalAssetId) -> Self {
-        self.external_ids.insert(ext_id.issuer_id(), ext_id);
+        self.external_ids.insert(ext_id.provider.clone(), ext_id);
         self
     }
 
-    pub fn merge(&mut self, other: &Self) -> &mut Self {
+    /// Adds the external ids of `other`, failing if a provider already maps to a different id
+    pub fn merge(&mut self, other: &Self) -> Result<&mut Self, AssetError> {
+        for (provider, ext_id) in &other.external_ids {
+            if let Some(existing) = self.external_ids.get(provider)
+                && existing != ext_id
+            {
+                return Err(AssetError::ConflictingExternalId {
+                    asset_id: self.id.clone(),
+                    existing: existing.clone(),
+                    new: ext_id.clone(),
+                });
+            }
+        }
         self.external_ids.extend(other.external_ids.clone());
-        self
+        Ok(self)
+    }
+
+    /// Converts a quantity reported by a provider to base units of this asset
+    pub fn amount_from_f64(&self, value: f64) -> Result<Amount, AmountError> {
+        Amount::from_f64(self.id.clone(), value, self.decimals)
+    }
+
+    /// Converts a decimal quantity reported by a provider to base units, exactly. Digits
+    /// beyond the asset's precision are an error rather than rounded away.
+    pub fn amount_from_str(&self, value: &str) -> Result<Amount, AmountError> {
+        Amount::from_decimal_str(self.id.clone(), value, self.decimals)
     }
 }
 
@@ -140,12 +232,75 @@ impl Asset {
 pub struct AccountId {
     pub provider: ProviderId,
     pub asset: AssetId,
+    #[serde(default)]
+    pub kind: AccountKind,
 }
 
 impl AccountId {
+    /// Funds of `asset` held at `provider`
     pub fn new(provider: ProviderId, asset: AssetId) -> Self {
-        AccountId { provider, asset }
+        AccountId {
+            provider,
+            asset,
+            kind: AccountKind::Holding,
+        }
+    }
+
+    /// Funds of `asset` allocated to a product of `provider`, e.g. a term deposit
+    pub fn product(provider: ProviderId, asset: AssetId, product_id: ProductId) -> Self {
+        AccountId {
+            kind: AccountKind::Product(product_id),
+            ..AccountId::new(provider, asset)
+        }
+    }
+
+    /// Counter-account for funds entering or leaving `provider` from the outside world
+    pub fn external(provider: ProviderId, asset: AssetId) -> Self {
+        AccountId {
+            kind: AccountKind::External,
+            ..AccountId::new(provider, asset)
+        }
     }
+
+    /// Counter-account for fees charged by `provider`
+    pub fn fee(provider: ProviderId, asset: AssetId) -> Self {
+        AccountId {
+            kind: AccountKind::Fee,
+            ..AccountId::new(provider, asset)
+        }
+    }
+
+    /// Counter-account for interest and rewards paid by `provider`
+    pub fn income(provider: ProviderId, asset: AssetId) -> Self {
+        AccountId {
+            kind: AccountKind::Income,
+            ..AccountId::new(provider, asset)
+        }
+    }
+
+    /// Whether this account only exists to balance transactions, as opposed to holding funds
+    pub fn is_counter_account(&self) -> bool {
+        matches!(
+            self.kind,
+            AccountKind::External | AccountKind::Fee | AccountKind::Income
+        )
+    }
+}
+
+/// Role of an account in the double-entry model
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AccountKind {
+    /// Funds actually held by the user
+    #[default]
+    Holding,
+    /// Funds held by the user but allocated to a product (term deposit, staking, ...)
+    Product(ProductId),
+    /// The world outside the tracked accounts (deposits, withdrawals)
+    External,
+    /// Fees paid
+    Fee,
+    /// Interest, staking rewards and other earnings
+    Income,
 }
 
 /// Position identifier
@@ -164,9 +319,12 @@ impl<S: AsRef<str>> From<S> for PositionId {
 pub struct Position {
     pub id: PositionId,
     pub product_id: ProductId,
-    pub amount: u64,
+    pub amount: Amount,
     pub start_date: DateTime<Utc>,
-    pub end_date: DateTime<Utc>,
+    /// When the funds become available again, None for flexible positions
+    pub end_date: Option<DateTime<Utc>>,
+    /// Whether the position is re-subscribed automatically when it ends
+    pub auto_renew: bool,
 }
 
 /// Collection of all positions
@@ -194,15 +352,14 @@ pub struct Product {
 }
 
 /// Collection of all products
+#[derive(Default)]
 pub struct AllProducts {
     pub products: HashMap<ProductId, Product>,
 }
 
 impl AllProducts {
     pub fn new() -> Self {
-        AllProducts {
-            products: HashMap::new(),
-        }
+        Self::default()
     }
 
     pub fn insert(&mut self, product: Product) {
@@ -214,8 +371,8 @@ impl AllProducts {
     }
 }
 
-/// Transaction identifier
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+/// Transaction identifier, `<provider>:<native id>` or `<provider>:#<content hash>`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
 #[serde(transparent)]
 pub struct TransactionId(pub String);
 
@@ -224,3 +381,129 @@ impl<S: AsRef<str>> From<S> for TransactionId {
         TransactionId(name.as_ref().to_string())
     }
 }
+
+impl TransactionId {
+    /// Id of a record the provider identifies itself, e.g. a Nexo transaction id
+    pub fn native(provider: &ProviderId, native_id: impl std::fmt::Display) -> Self {
+        TransactionId(format!("{}:{native_id}", provider.0))
+    }
+
+    /// Id of a record without a native id, hashed from when and what it moves.
+    /// Account providers are left out of the hash, the id is scoped by its prefix.
+    pub fn from_content(provider: &ProviderId, tx: &Transaction) -> Self {
+        let mut content = tx.datetime.to_rfc3339();
+        for (side, effects) in [("in", &tx.inputs), ("out", &tx.outputs)] {
+            for effect in effects {
+                let kind = serde_json::to_string(&effect.account_id.kind)
+                    .expect("account kinds serialize");
+                content.push_str(&format!(
+                    "|{side},{kind},{},{},{}",
+                    effect.amount.asset_id.0,
+                    effect.amount.units,
+                    effect.datetime.to_rfc3339()
+                ));
+            }
+        }
+        let hash = hmac_sha256::Hash::hash(content.as_bytes());
+        let hex = hash[..16]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        TransactionId(format!("{}:#{hex}", provider.0))
+    }
+
+    /// The same record's id for another instance of the provider
+    pub fn with_provider(&self, provider: &ProviderId) -> Self {
+        let local_id = self.0.split_once(':').map_or(self.0.as_str(), |(_, id)| id);
+        TransactionId(format!("{}:{local_id}", provider.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn effect(account_id: AccountId, units: i128) -> TxEffect {
+        TxEffect {
+            amount: Amount::new(account_id.asset.clone(), units),
+            account_id,
+            datetime: DateTime::UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn test_validate_interest_against_income_account() {
+        let nexo = ProviderId::from("nexo");
+        let mut tx = Transaction {
+            id: TransactionId::native(&nexo, "NXT1"),
+            tx_hash: None,
+            inputs: vec![effect(
+                AccountId::income(nexo.clone(), AssetId::from("ETH")),
+                -42,
+            )],
+            outputs: vec![effect(
+                AccountId::new(nexo.clone(), AssetId::from("ETH")),
+                42,
+            )],
+            datetime: DateTime::UNIX_EPOCH,
+        };
+        assert_eq!(tx.validate(), Ok(()));
+
+        tx.outputs
+            .push(effect(AccountId::fee(nexo, AssetId::from("ETH")), 1));
+        assert_eq!(
+            tx.validate(),
+            Err(TxValidationError::Unbalanced(vec![Amount::new(
+                AssetId::from("ETH"),
+                1
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_transaction_ids() {
+        let (nexo, joint) = (ProviderId::from("nexo"), ProviderId::from("nexo-joint"));
+        let id = TransactionId::native(&nexo, "NXT:1");
+        assert_eq!(id.0, "nexo:NXT:1");
+        assert_eq!(id.with_provider(&joint).0, "nexo-joint:NXT:1");
+
+        let interest = |provider: &ProviderId, units: i128| Transaction {
+            id: TransactionId::default(),
+            tx_hash: None,
+            inputs: vec![effect(
+                AccountId::income(provider.clone(), AssetId::from("ETH")),
+                -units,
+            )],
+            outputs: vec![effect(
+                AccountId::new(provider.clone(), AssetId::from("ETH")),
+                units,
+            )],
+            datetime: DateTime::UNIX_EPOCH,
+        };
+        let id = TransactionId::from_content(&nexo, &interest(&nexo, 42));
+        assert_eq!(id, TransactionId::from_content(&nexo, &interest(&nexo, 42)));
+        assert_ne!(id, TransactionId::from_content(&nexo, &interest(&nexo, 43)));
+        assert!(id.0.starts_with("nexo:#"));
+        // relabeling an instance keeps content ids in line with hashing it again
+        assert_eq!(
+            id.with_provider(&joint),
+            TransactionId::from_content(&joint, &interest(&joint, 42))
+        );
+    }
+
+    #[test]
+    fn test_validate_effect_signs() {
+        let account = AccountId::new(ProviderId::from("nexo"), AssetId::from("ETH"));
+        let tx = Transaction {
+            id: TransactionId::from("nexo:NXT2"),
+            tx_hash: None,
+            inputs: vec![effect(account.clone(), 5)],
+            outputs: vec![effect(account.clone(), -5)],
+            datetime: DateTime::UNIX_EPOCH,
+        };
+        assert_eq!(
+            tx.validate(),
+            Err(TxValidationError::InflowInInputs(account))
+        );
+    }
+}
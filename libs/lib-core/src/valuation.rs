@@ -0,0 +1,269 @@
+//! Net worth in a quote asset, from replayed balances or provider positions.
+
+use crate::amount::Amount;
+use crate::assets::{AssetError, AssetRegistry};
+use crate::balances::BalanceReplay;
+use crate::pricing::{PriceBook, PriceQuote, PricingError};
+use crate::types::{AccountId, AccountKind, AssetId, Position, ProductId, ProviderId};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// An amount held on a provider, valued in the quote asset
+#[derive(Debug, Clone)]
+pub struct Holding {
+    pub provider: ProviderId,
+    /// None for funds not allocated to a product
+    pub product_id: Option<ProductId>,
+    pub amount: Amount,
+    pub value: f64,
+    pub quote: PriceQuote,
+}
+
+/// Why an amount couldn't be valued
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum UnpricedError {
+    #[error(transparent)]
+    Pricing(#[from] PricingError),
+    /// The asset isn't in the registry, so its amount can't be converted
+    #[error(transparent)]
+    Asset(#[from] AssetError),
+}
+
+/// An amount that couldn't be priced, left out of the totals
+#[derive(Debug, Clone)]
+pub struct Unpriced {
+    pub provider: ProviderId,
+    pub amount: Amount,
+    pub error: UnpricedError,
+}
+
+/// Everything held at a point in time, valued in `vs_asset_id`
+#[derive(Debug, Clone)]
+pub struct Valuation {
+    pub vs_asset_id: AssetId,
+    pub datetime: DateTime<Utc>,
+    pub holdings: Vec<Holding>,
+    pub unpriced: Vec<Unpriced>,
+}
+
+impl Valuation {
+    pub fn new(vs_asset_id: AssetId, datetime: DateTime<Utc>) -> Self {
+        Valuation {
+            vs_asset_id,
+            datetime,
+            holdings: Vec::new(),
+            unpriced: Vec::new(),
+        }
+    }
+
+    /// Values the holding and product accounts of a replayed log as of `datetime`
+    pub fn from_replay(
+        replay: &BalanceReplay,
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+        vs_asset_id: &AssetId,
+        datetime: DateTime<Utc>,
+    ) -> Self {
+        Self::from_balances(
+            &replay.balances_at(datetime),
+            assets,
+            prices,
+            vs_asset_id,
+            datetime,
+        )
+    }
+
+    /// Values account balances, counter-accounts are skipped
+    pub fn from_balances(
+        balances: &HashMap<AccountId, Amount>,
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+        vs_asset_id: &AssetId,
+        datetime: DateTime<Utc>,
+    ) -> Self {
+        let mut valuation = Valuation::new(vs_asset_id.clone(), datetime);
+        for (account_id, amount) in balances {
+            let product_id = match &account_id.kind {
+                AccountKind::Holding => None,
+                AccountKind::Product(product_id) => Some(product_id.clone()),
+                AccountKind::External | AccountKind::Fee | AccountKind::Income => continue,
+            };
+            valuation.add(&account_id.provider, product_id, amount, assets, prices);
+        }
+        valuation
+    }
+
+    /// Values positions as reported by providers
+    pub fn from_positions<'a>(
+        positions: impl IntoIterator<Item = (&'a ProviderId, &'a Position)>,
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+        vs_asset_id: &AssetId,
+        datetime: DateTime<Utc>,
+    ) -> Self {
+        let mut valuation = Valuation::new(vs_asset_id.clone(), datetime);
+        for (provider, position) in positions {
+            let product_id = Some(position.product_id.clone());
+            valuation.add(provider, product_id, &position.amount, assets, prices);
+        }
+        valuation
+    }
+
+    /// Prices `amount` at the valuation's datetime, zero amounts are skipped
+    pub fn add(
+        &mut self,
+        provider: &ProviderId,
+        product_id: Option<ProductId>,
+        amount: &Amount,
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+    ) {
+        if amount.is_zero() {
+            return;
+        }
+        let priced = assets
+            .get(&amount.asset_id)
+            .map_err(UnpricedError::from)
+            .and_then(|asset| {
+                let quote = prices.price(&amount.asset_id, &self.vs_asset_id, self.datetime)?;
+                Ok((asset.decimals, quote))
+            });
+        match priced {
+            Ok((decimals, quote)) => self.holdings.push(Holding {
+                provider: provider.clone(),
+                product_id,
+                amount: amount.clone(),
+                value: amount.to_f64(decimals) * quote.price,
+                quote,
+            }),
+            Err(error) => self.unpriced.push(Unpriced {
+                provider: provider.clone(),
+                amount: amount.clone(),
+                error,
+            }),
+        }
+    }
+
+    /// Net worth, excluding unpriced amounts
+    pub fn total(&self) -> f64 {
+        self.holdings.iter().map(|h| h.value).sum()
+    }
+
+    pub fn by_asset(&self) -> HashMap<AssetId, f64> {
+        self.sum_by(|h| h.amount.asset_id.clone())
+    }
+
+    pub fn by_provider(&self) -> HashMap<ProviderId, f64> {
+        self.sum_by(|h| h.provider.clone())
+    }
+
+    /// Values per product, funds outside any product are under None
+    pub fn by_product(&self) -> HashMap<Option<ProductId>, f64> {
+        self.sum_by(|h| h.product_id.clone())
+    }
+
+    fn sum_by<K: Eq + Hash>(&self, key: impl Fn(&Holding) -> K) -> HashMap<K, f64> {
+        let mut sums = HashMap::new();
+        for holding in &self.holdings {
+            *sums.entry(key(holding)).or_default() += holding.value;
+        }
+        sums
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::AssetPricePoint;
+    use crate::types::{Transaction, TransactionId, TxEffect};
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 4, d, 0, 0, 0).unwrap()
+    }
+
+    fn eth(units: i128) -> Amount {
+        Amount::new(AssetId::from("ETH"), units * 1_000_000_000_000_000_000)
+    }
+
+    fn deposit(to: &AccountId, amount: Amount, d: u32) -> Transaction {
+        let effect = |account_id: &AccountId, amount| TxEffect {
+            account_id: account_id.clone(),
+            amount,
+            datetime: day(d),
+        };
+        let outside = AccountId::external(to.provider.clone(), amount.asset_id.clone());
+        Transaction {
+            id: TransactionId::default(),
+            tx_hash: None,
+            inputs: vec![effect(&outside, amount.checked_neg().unwrap())],
+            outputs: vec![effect(to, amount)],
+            datetime: day(d),
+        }
+    }
+
+    #[test]
+    fn test_networth_over_time() -> anyhow::Result<()> {
+        let (nexo, binance) = (ProviderId::from("nexo"), ProviderId::from("binance"));
+        let (usd, eur) = (AssetId::from("USD"), AssetId::from("EUR"));
+        let staking = ProductId::from("binance-staking-ETH");
+        let txs = [
+            deposit(
+                &AccountId::new(nexo.clone(), AssetId::from("ETH")),
+                eth(2),
+                1,
+            ),
+            deposit(
+                &AccountId::product(binance.clone(), AssetId::from("ETH"), staking.clone()),
+                eth(1),
+                10,
+            ),
+            deposit(
+                &AccountId::new(nexo.clone(), AssetId::from("AXS")),
+                Amount::new(AssetId::from("AXS"), 5),
+                10,
+            ),
+            deposit(
+                &AccountId::new(nexo.clone(), AssetId::from("NOPE")),
+                Amount::new(AssetId::from("NOPE"), 5),
+                10,
+            ),
+        ];
+        let replay = BalanceReplay::replay(&txs)?;
+
+        let mut prices = PriceBook::new();
+        for (d, price) in [(1, 3000.0), (10, 3500.0)] {
+            prices.insert(AssetPricePoint {
+                datetime: day(d),
+                asset_id: AssetId::from("ETH"),
+                vs_asset_id: usd.clone(),
+                price,
+            });
+        }
+        prices.insert(AssetPricePoint {
+            datetime: day(10),
+            asset_id: eur.clone(),
+            vs_asset_id: usd.clone(),
+            price: 1.25,
+        });
+        let assets = AssetRegistry::builtin();
+
+        let before = Valuation::from_replay(&replay, &assets, &prices, &usd, day(1));
+        assert_eq!(before.total(), 6000.0);
+
+        let after = Valuation::from_replay(&replay, &assets, &prices, &eur, day(10));
+        assert_eq!(after.total(), 8400.0);
+        assert_eq!(after.by_provider()[&nexo], 5600.0);
+        assert_eq!(after.by_product()[&Some(staking)], 2800.0);
+        assert_eq!(after.by_asset().len(), 1);
+        // no AXS price, and an asset missing from the registry doesn't stop the rest
+        let mut errors = after.unpriced.iter().map(|u| &u.error).collect::<Vec<_>>();
+        errors.sort_by_key(|e| matches!(e, UnpricedError::Asset(_)));
+        assert!(matches!(
+            errors[..],
+            [UnpricedError::Pricing(_), UnpricedError::Asset(_)]
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+//! Earn-per-year (EPY) projections from positions and the APY of their products,
+//! and the yield actually realized according to the transaction log.
+
+use crate::assets::AssetRegistry;
+use crate::balances::BalanceReplay;
+use crate::pricing::PriceBook;
+use crate::types::{
+    AccountId, AccountKind, AllProducts, AssetId, Position, PositionId, ProductId, ProviderId,
+    Transaction,
+};
+use crate::valuation::UnpricedError;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+const YEAR: TimeDelta = TimeDelta::days(365);
+
+/// Projected earnings of a position over the year following the projection date
+#[derive(Debug, Clone)]
+pub struct PositionYield {
+    pub provider: ProviderId,
+    pub position_id: PositionId,
+    pub product_id: ProductId,
+    pub asset_id: AssetId,
+    pub apy: f64,
+    /// Part of the year the position keeps earning, from 0 to 1
+    pub earning_fraction: f64,
+    /// Earnings in the position's asset
+    pub epy: f64,
+    /// Earnings in the quote asset, None if the asset couldn't be priced
+    pub epy_value: Option<f64>,
+}
+
+/// Earn-per-year of a set of positions, valued in `vs_asset_id`
+#[derive(Debug, Clone)]
+pub struct YieldProjection {
+    pub vs_asset_id: AssetId,
+    pub datetime: DateTime<Utc>,
+    pub positions: Vec<PositionYield>,
+    /// Positions whose product isn't known, so have no APY
+    pub unknown_products: Vec<PositionId>,
+    /// Positions without a quote, and positions in unregistered assets left out entirely
+    pub unpriced: Vec<UnpricedError>,
+}
+
+impl YieldProjection {
+    /// Projects the earnings of `positions` over the year starting at `datetime`.
+    /// Positions that end within the year only earn until their end date, unless they auto-renew.
+    pub fn project<'a>(
+        positions: impl IntoIterator<Item = (&'a ProviderId, &'a Position)>,
+        products: &AllProducts,
+        assets: &AssetRegistry,
+        prices: &PriceBook,
+        vs_asset_id: &AssetId,
+        datetime: DateTime<Utc>,
+    ) -> Self {
+        let mut projection = YieldProjection {
+            vs_asset_id: vs_asset_id.clone(),
+            datetime,
+            positions: Vec::new(),
+            unknown_products: Vec::new(),
+            unpriced: Vec::new(),
+        };
+        for (provider, position) in positions {
+            let Some(product) = products.get(&position.product_id) else {
+                projection.unknown_products.push(position.id.clone());
+                continue;
+            };
+            let asset_id = &position.amount.asset_id;
+            let decimals = match assets.get(asset_id) {
+                Ok(asset) => asset.decimals,
+                Err(err) => {
+                    projection.unpriced.push(err.into());
+                    continue;
+                }
+            };
+
+            let earning_fraction = earning_fraction(position, datetime);
+            let growth = (1.0 + product.apy).powf(earning_fraction) - 1.0;
+            let epy = position.amount.to_f64(decimals) * growth;
+            let epy_value = match prices.price(asset_id, vs_asset_id, datetime) {
+                Ok(quote) => Some(epy * quote.price),
+                Err(err) => {
+                    projection.unpriced.push(err.into());
+                    None
+                }
+            };
+
+            projection.positions.push(PositionYield {
+                provider: provider.clone(),
+                position_id: position.id.clone(),
+                product_id: product.id.clone(),
+                asset_id: asset_id.clone(),
+                apy: product.apy,
+                earning_fraction,
+                epy,
+                epy_value,
+            });
+        }
+        projection
+    }
+
+    /// Yearly earnings in the quote asset, excluding unpriced positions
+    pub fn total_value(&self) -> f64 {
+        self.positions.iter().filter_map(|p| p.epy_value).sum()
+    }
+
+    /// Yearly earnings per asset, in the asset itself
+    pub fn by_asset(&self) -> HashMap<AssetId, f64> {
+        let mut sums = HashMap::new();
+        for position in &self.positions {
+            *sums.entry(position.asset_id.clone()).or_default() += position.epy;
+        }
+        sums
+    }
+
+    /// Yearly earnings per asset, in the quote asset
+    pub fn value_by_asset(&self) -> HashMap<AssetId, f64> {
+        self.value_by(|p| p.asset_id.clone())
+    }
+
+    pub fn value_by_provider(&self) -> HashMap<ProviderId, f64> {
+        self.value_by(|p| p.provider.clone())
+    }
+
+    pub fn value_by_product(&self) -> HashMap<ProductId, f64> {
+        self.value_by(|p| p.product_id.clone())
+    }
+
+    fn value_by<K: Eq + Hash>(&self, key: impl Fn(&PositionYield) -> K) -> HashMap<K, f64> {
+        let mut sums = HashMap::new();
+        for position in &self.positions {
+            if let Some(value) = position.epy_value {
+                *sums.entry(key(position)).or_default() += value;
+            }
+        }
+        sums
+    }
+}
+
+/// Yield an account earned over a window, annualized
+#[derive(Debug, Clone)]
+pub struct RealizedYield {
+    pub account_id: AccountId,
+    /// Time-weighted average balance over the window, in base units
+    pub average_balance: f64,
+    /// Income paid into the account over the window, in base units
+    pub income: f64,
+    pub realized_apy: f64,
+    /// APY of the account's product, if it is allocated to a known product
+    pub advertised_apy: Option<f64>,
+}
+impl RealizedYield {
+    /// Realized minus advertised APY
+    pub fn drift(&self) -> Option<f64> {
+        Some(self.realized_apy - self.advertised_apy?)
+    }
+}
+
+/// Realized yields of every account that earned or was allocated to a product over `(from, to]`
+#[derive(Debug, Clone)]
+pub struct RealizedYields {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub accounts: Vec<RealizedYield>,
+}
+
+impl RealizedYields {
+    /// Income is what income counter-accounts paid in, attributed to the product account
+    /// that earned it when the transaction passes through one, e.g. a locked reward paid out
+    /// to spot, else to the account it was paid into.
+    /// `transactions` must be the log `replay` was built from.
+    pub fn compute<'a>(
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+        replay: &BalanceReplay,
+        products: &AllProducts,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Self {
+        let mut income: HashMap<&AccountId, i128> = HashMap::new();
+        for tx in transactions {
+            if tx.datetime <= from || tx.datetime > to {
+                continue;
+            }
+            let paid = tx
+                .inputs
+                .iter()
+                .filter(|e| e.account_id.kind == AccountKind::Income);
+            for payment in paid {
+                let asset_id = &payment.amount.asset_id;
+                let receivers = || {
+                    tx.effects()
+                        .map(|e| &e.account_id)
+                        .filter(|a| &a.asset == asset_id && !a.is_counter_account())
+                };
+                let earner = receivers()
+                    .find(|a| matches!(a.kind, AccountKind::Product(_)))
+                    .or_else(|| receivers().next());
+                if let Some(earner) = earner {
+                    *income.entry(earner).or_default() -= payment.amount.units;
+                }
+            }
+        }
+
+        let window = (to - from).num_seconds() as f64;
+        let mut accounts = Vec::new();
+        for account_id in replay.accounts() {
+            if account_id.is_counter_account() {
+                continue;
+            }
+            let advertised_apy = match &account_id.kind {
+                AccountKind::Product(product_id) => products.get(product_id).map(|p| p.apy),
+                _ => None,
+            };
+            let income = income.get(account_id).copied().unwrap_or_default() as f64;
+            let average_balance = average_balance(replay, account_id, from, to);
+            if average_balance <= 0.0 || (income == 0.0 && advertised_apy.is_none()) {
+                continue;
+            }
+
+            let period_return = income / average_balance;
+            let years = window / YEAR.num_seconds() as f64;
+            accounts.push(RealizedYield {
+                account_id: account_id.clone(),
+                average_balance,
+                income,
+                realized_apy: (1.0 + period_return).powf(1.0 / years) - 1.0,
+                advertised_apy,
+            });
+        }
+        RealizedYields { from, to, accounts }
+    }
+
+    /// Accounts whose realized APY is more than `tolerance` away from the advertised one
+    pub fn drifting(&self, tolerance: f64) -> impl Iterator<Item = &RealizedYield> {
+        self.accounts
+            .iter()
+            .filter(move |y| y.drift().is_some_and(|drift| drift.abs() > tolerance))
+    }
+}
+
+/// Time-weighted average balance of `account_id` over `(from, to]`
+fn average_balance(
+    replay: &BalanceReplay,
+    account_id: &AccountId,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> f64 {
+    let window = (to - from).num_seconds() as f64;
+    if window <= 0.0 {
+        return 0.0;
+    }
+    let mut balance = replay.balance_at(account_id, from).map_or(0, |b| b.units) as f64;
+    let mut since = from;
+    let mut weighted = 0.0;
+    for point in replay.history(account_id) {
+        if point.datetime <= from || point.datetime > to {
+            continue;
+        }
+        weighted += balance * (point.datetime - since).num_seconds() as f64;
+        balance = point.balance.units as f64;
+        since = point.datetime;
+    }
+    weighted += balance * (to - since).num_seconds() as f64;
+    weighted / window
+}
+
+/// Part of the year after `from` during which the position earns
+fn earning_fraction(position: &Position, from: DateTime<Utc>) -> f64 {
+    let earning = match position.end_date {
+        Some(end_date) if !position.auto_renew => (end_date - from).clamp(TimeDelta::zero(), YEAR),
+        _ => YEAR,
+    };
+    earning.num_seconds() as f64 / YEAR.num_seconds() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::history::AssetPricePoint;
+    use crate::types::{Product, TransactionId, TxEffect};
+    use chrono::TimeZone;
+
+    fn position(
+        id: &str,
+        product: &str,
+        units: i128,
+        end_date: Option<i64>,
+        auto_renew: bool,
+    ) -> Position {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        Position {
+            id: PositionId::from(id),
+            product_id: ProductId::from(product),
+            amount: Amount::new(AssetId::from("ETH"), units * 1_000_000_000_000_000_000),
+            start_date: start,
+            end_date: end_date.map(|days| start + TimeDelta::days(days)),
+            auto_renew,
+        }
+    }
+
+    #[test]
+    fn test_epy_with_lock_end_dates() -> anyhow::Result<()> {
+        let binance = ProviderId::from("binance");
+        let (eth, usd) = (AssetId::from("ETH"), AssetId::from("USD"));
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        let mut products = AllProducts::new();
+        for (id, apy) in [("flexible", 0.1), ("locked", 0.2)] {
+            products.insert(Product {
+                id: ProductId::from(id),
+                asset_id: eth.clone(),
+                apy,
+            });
+        }
+        let mut prices = PriceBook::new();
+        prices.insert(AssetPricePoint {
+            datetime: now,
+            asset_id: eth.clone(),
+            vs_asset_id: usd.clone(),
+            price: 3000.0,
+        });
+
+        let positions = [
+            position("flex", "flexible", 10, None, false),
+            position("locked-once", "locked", 10, Some(182), false),
+            position("locked-renewed", "locked", 10, Some(30), true),
+            position("orphan", "unknown", 1, None, false),
+            Position {
+                amount: Amount::new(AssetId::from("NOT-LISTED"), 1),
+                ..position("unlisted", "flexible", 1, None, false)
+            },
+        ];
+        let projection = YieldProjection::project(
+            positions.iter().map(|p| (&binance, p)),
+            &products,
+            &AssetRegistry::builtin(),
+            &prices,
+            &usd,
+            now,
+        );
+
+        let epy = |id: &str| {
+            let p = projection
+                .positions
+                .iter()
+                .find(|p| p.position_id.0 == id)
+                .unwrap();
+            (p.epy * 1000.0).round() / 1000.0
+        };
+        assert_eq!(epy("flex"), 1.0);
+        assert_eq!(epy("locked-once"), 0.952);
+        assert_eq!(epy("locked-renewed"), 2.0);
+        assert_eq!(projection.unknown_products, [PositionId::from("orphan")]);
+        assert_eq!(projection.positions.len(), 3);
+        assert!(matches!(projection.unpriced[..], [UnpricedError::Asset(_)]));
+
+        let total = projection.by_asset()[&eth];
+        assert!((projection.total_value() - total * 3000.0).abs() < 1e-6);
+        assert!((projection.value_by_provider()[&binance] - total * 3000.0).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_realized_yield_drift() -> anyhow::Result<()> {
+        let nexo = ProviderId::from("nexo");
+        let eth = AssetId::from("ETH");
+        let from = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let to = from + TimeDelta::days(364);
+        let account =
+            |product: &str| AccountId::product(nexo.clone(), eth.clone(), ProductId::from(product));
+        let transfer = |from_account: &AccountId, to_account: &AccountId, units: i128, datetime| {
+            let effect = |account_id: &AccountId, units| TxEffect {
+                account_id: account_id.clone(),
+                amount: Amount::new(eth.clone(), units),
+                datetime,
+            };
+            Transaction {
+                id: TransactionId::default(),
+                tx_hash: None,
+                inputs: vec![effect(from_account, -units)],
+                outputs: vec![effect(to_account, units)],
+                datetime,
+            }
+        };
+        let (outside, income) = (
+            AccountId::external(nexo.clone(), eth.clone()),
+            AccountId::income(nexo.clone(), eth.clone()),
+        );
+        let (honest, overpromised) = (account("honest"), account("overpromised"));
+
+        let txs = [
+            transfer(&outside, &honest, 1000, from),
+            transfer(&outside, &overpromised, 1000, from),
+            transfer(&outside, &overpromised, 1000, from + TimeDelta::days(182)),
+            transfer(&income, &honest, 100, to),
+            transfer(&income, &overpromised, 150, to),
+        ];
+        let replay = BalanceReplay::replay(&txs)?;
+        let mut products = AllProducts::new();
+        for (id, apy) in [("honest", 0.1), ("overpromised", 0.2)] {
+            products.insert(Product {
+                id: ProductId::from(id),
+                asset_id: eth.clone(),
+                apy,
+            });
+        }
+
+        let yields = RealizedYields::compute(&txs, &replay, &products, from, to);
+        assert_eq!(yields.accounts.len(), 2);
+        let realized = |account_id: &AccountId| {
+            let y = yields
+                .accounts
+                .iter()
+                .find(|y| &y.account_id == account_id)
+                .unwrap();
+            (
+                y.average_balance,
+                (y.realized_apy * 1000.0).round() / 1000.0,
+            )
+        };
+        assert_eq!(realized(&honest), (1000.0, 0.1));
+        assert_eq!(realized(&overpromised), (1500.0, 0.1));
+
+        let drifting = yields.drifting(0.05).collect::<Vec<_>>();
+        assert_eq!(drifting.len(), 1);
+        assert_eq!(drifting[0].account_id, overpromised);
+        Ok(())
+    }
+}
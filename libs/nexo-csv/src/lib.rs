@@ -1,26 +1,127 @@
+use ::utils::decimal;
+use anyhow::Context;
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
+/// Reader for Nexo transaction exports
 pub struct NexoCsv {}
 impl NexoCsv {
-    pub fn read_all() -> anyhow::Result<Vec<NexoTx>> {
-        let mut rdr = csv::Reader::from_path("../../.cache/nexo_transactions.csv")?;
+    /// Reads a single export, or every `.csv` export in a directory
+    pub fn read_path(path: impl AsRef<Path>) -> anyhow::Result<NexoImport> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            Self::read_dir(path)
+        } else {
+            Self::read_file(path)
+        }
+    }
+
+    pub fn read_file(path: impl AsRef<Path>) -> anyhow::Result<NexoImport> {
+        let mut import = NexoImport::default();
+        import.extend_from_file(path.as_ref())?;
+        Ok(import)
+    }
 
+    /// Reads every `.csv` export in `dir` in file name order, skipping rows seen in earlier files
+    pub fn read_dir(dir: impl AsRef<Path>) -> anyhow::Result<NexoImport> {
+        let dir = dir.as_ref();
+        let mut paths = std::fs::read_dir(dir)
+            .with_context(|| format!("reading {}", dir.display()))?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+        paths.retain(|p| {
+            p.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+        });
+        paths.sort();
+
+        let mut import = NexoImport::default();
+        for path in paths {
+            import.extend_from_file(&path)?;
+        }
+        Ok(import)
+    }
+
+    pub fn read(rdr: impl Read) -> anyhow::Result<NexoImport> {
+        let mut import = NexoImport::default();
+        import.extend(Self::rows(rdr, "<reader>")?);
+        Ok(import)
+    }
+
+    /// Streams the rows of an export. Malformed rows come out as errors without ending the stream.
+    /// Only fails if the header row can't be read.
+    pub fn rows(
+        rdr: impl Read,
+        file: impl Into<String>,
+    ) -> anyhow::Result<impl Iterator<Item = Result<NexoTx, RowError>>> {
+        let mut rdr = csv::Reader::from_reader(rdr);
         let headers = rdr.headers()?.clone();
+        let file = file.into();
 
-        let nexo_txs = rdr
-            .records()
-            .take(5)
-            .map(|row| {
-                let row = row?;
-                let record: NexoTx = row
-                    .deserialize(Some(&headers.to_owned()))
-                    .map_err(|e| anyhow::Error::new(e).context(format!("{:#?}", row)))?;
-                Ok(record)
-            })
-            .collect::<anyhow::Result<Vec<NexoTx>>>()?;
+        let rows = rdr.into_records().map(move |row| {
+            let row = row.map_err(|e| RowError::new(&file, e.position(), &e))?;
+            row.deserialize::<NexoTx>(Some(&headers))
+                .map_err(anyhow::Error::from)
+                .and_then(|nexo_tx| nexo_tx.check_fee().map(|()| nexo_tx))
+                .map_err(|e| RowError::new(&file, row.position(), e))
+        });
+        Ok(rows)
+    }
+}
 
-        Ok(nexo_txs)
+/// Rows read from one or more exports, deduplicated by transaction id
+#[derive(Debug, Default)]
+pub struct NexoImport {
+    /// Unique rows, in the order they were first read
+    pub transactions: Vec<NexoTx>,
+    /// Malformed rows that were skipped
+    pub errors: Vec<RowError>,
+    /// Number of rows skipped because their transaction was already read
+    pub duplicates: usize,
+    seen_tx_ids: HashSet<String>,
+}
+impl NexoImport {
+    pub fn extend(&mut self, rows: impl IntoIterator<Item = Result<NexoTx, RowError>>) {
+        for row in rows {
+            match row {
+                Ok(nexo_tx) if !self.seen_tx_ids.insert(nexo_tx.tx_id.clone()) => {
+                    self.duplicates += 1;
+                }
+                Ok(nexo_tx) => self.transactions.push(nexo_tx),
+                Err(err) => self.errors.push(err),
+            }
+        }
+    }
+
+    fn extend_from_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let rows = NexoCsv::rows(file, path.display().to_string())
+            .with_context(|| format!("reading {}", path.display()))?;
+        self.extend(rows);
+        Ok(())
+    }
+}
+
+/// A row that couldn't be read
+#[derive(Debug, thiserror::Error)]
+#[error("{file}:{line}: {message}")]
+pub struct RowError {
+    pub file: String,
+    /// 1-based line of the row in its file, 0 if unknown
+    pub line: u64,
+    pub message: String,
+}
+impl RowError {
+    fn new(file: &str, position: Option<&csv::Position>, err: impl std::fmt::Display) -> Self {
+        RowError {
+            file: file.to_string(),
+            line: position.map_or(0, |p| p.line()),
+            message: err.to_string(),
+        }
     }
 }
 
@@ -32,59 +133,310 @@ pub struct NexoTx {
     pub kind: TransactionType,
     #[serde(rename = "Input Currency")]
     pub input_currency: String,
-    #[serde(rename = "Input Amount")]
-    pub input_amount: f64, // TODO use decimal
+    /// Decimal string as exported, negative for some outflows
+    #[serde(rename = "Input Amount", deserialize_with = "utils::de_decimal")]
+    pub input_amount: String,
     #[serde(rename = "Output Currency")]
     pub output_currency: String,
-    #[serde(rename = "Output Amount")]
-    pub output_amount: f64, // TODO use decimal
-    #[serde(rename = "USD Equivalent")]
-    pub usd_equivalent: String,
+    #[serde(rename = "Output Amount", deserialize_with = "utils::de_decimal")]
+    pub output_amount: String,
+    /// None when Nexo couldn't price the transaction ("-")
+    #[serde(rename = "USD Equivalent", deserialize_with = "utils::de_usd")]
+    pub usd_equivalent: Option<f64>,
+    /// Decimal string as exported, only present in newer exports
+    #[serde(rename = "Fee", default, deserialize_with = "utils::de_opt_decimal")]
+    pub fee: Option<String>,
+    #[serde(
+        rename = "Fee Currency",
+        default,
+        deserialize_with = "utils::de_opt_str"
+    )]
+    pub fee_currency: Option<String>,
     #[serde(rename = "Details")]
     pub details: String,
     #[serde(rename = "Date / Time (UTC)", deserialize_with = "utils::de_datetime")]
     pub date_time_utc: DateTime<Utc>,
 }
+impl NexoTx {
+    /// Input amount without its sign, Nexo reports outflows with either sign
+    pub fn input_quantity(&self) -> &str {
+        decimal::unsigned(&self.input_amount)
+    }
+
+    pub fn output_quantity(&self) -> &str {
+        decimal::unsigned(&self.output_amount)
+    }
+
+    /// Non-zero fee amount, without its sign, and its currency, if any
+    pub fn fee(&self) -> Option<(&str, &str)> {
+        let fee = decimal::unsigned(self.fee.as_deref()?);
+        if decimal::is_zero(fee) {
+            return None;
+        }
+        let currency = self.fee_currency.as_deref()?;
+        Some((fee, currency))
+    }
+
+    /// A non-zero fee must name its currency
+    fn check_fee(&self) -> anyhow::Result<()> {
+        let fee = self.fee.as_deref().map(decimal::unsigned);
+        let is_charged = fee.is_some_and(|fee| !decimal::is_zero(fee));
+        if is_charged && self.fee_currency.is_none() {
+            anyhow::bail!(
+                "fee {:?} without a currency",
+                self.fee.as_deref().unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    /// On-chain hash of a deposit or withdrawal, when `Details` ends with one,
+    /// e.g. "approved / 0x3f2a9c1e"
+    pub fn tx_hash(&self) -> Option<&str> {
+        let (_, last) = self.details.rsplit_once(" / ")?;
+        let last = last.trim();
+        let hex = last.strip_prefix("0x").unwrap_or(last);
+        let is_hash = (last.starts_with("0x") || hex.len() >= 32)
+            && !hex.is_empty()
+            && hex.chars().all(|c| c.is_ascii_hexdigit());
+        is_hash.then_some(last)
+    }
+}
 
-#[derive(Deserialize, Debug)]
+/// Value of the `Type` column. Types this crate doesn't know about yet end up in `Other`.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TransactionType {
     Interest,
-    #[serde(rename = "Locking Term Deposit")]
     LockTermDeposit,
-    #[serde(rename = "Unlocking Term Deposit")]
     UnlockTermDeposit,
-    #[serde(rename = "Fixed Term Interest")]
     TermInterest,
-    #[serde(rename = "Transfer From Pro Wallet")]
     TransferFromProWallet,
-    #[serde(rename = "Transfer To Pro Wallet")]
     TransferToProWallet,
-    #[serde(rename = "Exchange Deposited On")]
     ExchangeDepositedOn,
-    #[serde(rename = "Deposit To Exchange")]
     DepositToExchange,
-    #[serde(rename = "Withdraw Exchanged")]
     WithdrawExchanged,
-    #[serde(rename = "Exchange To Withdraw")]
     ExchangeToWithdraw,
-    #[serde(rename = "Top up Crypto")]
     TopUpCrypto,
+    Deposit,
+    Withdrawal,
+    TransferIn,
+    TransferOut,
+    Exchange,
+    ManualSellOrder,
+    Cashback,
+    ExchangeCashback,
+    ReferralBonus,
+    Dividend,
+    LoanWithdrawal,
+    Repayment,
+    ManualRepayment,
+    Liquidation,
+    NexoCardPurchase,
+    NexoCardRefund,
+    Other(String),
+}
+
+impl TransactionType {
+    const LABELS: [(&str, TransactionType); 27] = [
+        ("Interest", TransactionType::Interest),
+        ("Locking Term Deposit", TransactionType::LockTermDeposit),
+        ("Unlocking Term Deposit", TransactionType::UnlockTermDeposit),
+        ("Fixed Term Interest", TransactionType::TermInterest),
+        (
+            "Transfer From Pro Wallet",
+            TransactionType::TransferFromProWallet,
+        ),
+        (
+            "Transfer To Pro Wallet",
+            TransactionType::TransferToProWallet,
+        ),
+        (
+            "Exchange Deposited On",
+            TransactionType::ExchangeDepositedOn,
+        ),
+        ("Deposit To Exchange", TransactionType::DepositToExchange),
+        ("Withdraw Exchanged", TransactionType::WithdrawExchanged),
+        ("Exchange To Withdraw", TransactionType::ExchangeToWithdraw),
+        ("Top up Crypto", TransactionType::TopUpCrypto),
+        ("Deposit", TransactionType::Deposit),
+        ("Withdrawal", TransactionType::Withdrawal),
+        ("Transfer In", TransactionType::TransferIn),
+        ("Transfer Out", TransactionType::TransferOut),
+        ("Exchange", TransactionType::Exchange),
+        ("Manual Sell Order", TransactionType::ManualSellOrder),
+        ("Cashback", TransactionType::Cashback),
+        ("Exchange Cashback", TransactionType::ExchangeCashback),
+        ("Referral Bonus", TransactionType::ReferralBonus),
+        ("Dividend", TransactionType::Dividend),
+        ("Loan Withdrawal", TransactionType::LoanWithdrawal),
+        ("Repayment", TransactionType::Repayment),
+        ("Manual Repayment", TransactionType::ManualRepayment),
+        ("Liquidation", TransactionType::Liquidation),
+        ("Nexo Card Purchase", TransactionType::NexoCardPurchase),
+        ("Nexo Card Refund", TransactionType::NexoCardRefund),
+    ];
+
+    pub fn from_label(label: &str) -> Self {
+        Self::LABELS
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(label.trim()))
+            .map(|(_, kind)| kind.clone())
+            .unwrap_or_else(|| TransactionType::Other(label.to_string()))
+    }
+
+    /// The label used in Nexo exports
+    pub fn label(&self) -> &str {
+        match self {
+            TransactionType::Other(label) => label,
+            kind => Self::LABELS
+                .iter()
+                .find(|(_, known)| known == kind)
+                .map(|(label, _)| *label)
+                .expect("every known type has a label"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let label = String::deserialize(deserializer)?;
+        Ok(TransactionType::from_label(&label))
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
 
+    const HEADER: &str = "Transaction,Type,Input Currency,Input Amount,Output Currency,Output Amount,USD Equivalent,Details,Date / Time (UTC)";
+
+    fn interest_row(tx_id: &str, date: &str) -> String {
+        format!(
+            "{tx_id},Interest,ETH,0.0005,ETH,0.0005,$1.50,approved / ETH Interest Earned,{date}"
+        )
+    }
+
     #[test]
     fn test_read() -> anyhow::Result<()> {
-        let nexo_txs = NexoCsv::read_all()?;
-        dbg!(nexo_txs);
+        let dir = std::env::temp_dir().join(format!("nexo-csv-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        // two overlapping exports, plus a file that isn't an export
+        let older = [
+            HEADER,
+            &interest_row("NXT1", "2024-01-01 06:00:00"),
+            &interest_row("NXT2", "2024-01-02 06:00:00"),
+        ];
+        let newer = [
+            HEADER,
+            &interest_row("NXT2", "2024-01-02 06:00:00"),
+            &interest_row("NXT3", "2024-01-03 06:00:00"),
+        ];
+        std::fs::write(dir.join("2024-01-02.csv"), older.join("\n"))?;
+        std::fs::write(dir.join("2024-01-03.csv"), newer.join("\n"))?;
+        std::fs::write(dir.join("notes.txt"), "not an export")?;
+
+        let import = NexoCsv::read_path(&dir)?;
+        let tx_ids: Vec<_> = import
+            .transactions
+            .iter()
+            .map(|tx| tx.tx_id.as_str())
+            .collect();
+        assert_eq!(tx_ids, ["NXT1", "NXT2", "NXT3"]);
+        assert_eq!(import.duplicates, 1);
+        assert!(import.errors.is_empty());
+
+        let single = NexoCsv::read_path(dir.join("2024-01-03.csv"))?;
+        assert_eq!(single.transactions.len(), 2);
 
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_columns_and_unknown_types() -> anyhow::Result<()> {
+        let csv = [
+            "Transaction,Type,Input Currency,Input Amount,Output Currency,Output Amount,USD Equivalent,Fee,Fee Currency,Details,Date / Time (UTC)",
+            "NXT1,Withdrawal,USDT,-100,USDT,100,$100.00,2.5,USDT,approved / 0xabc,2024-01-01 06:00:00",
+            "NXT2,Exchange,ETH,-1,BTC,0.05,\"$3,000.00\",-,-,approved / ETH to BTC,2024-01-02 06:00:00",
+            "NXT3,Some Future Type,ETH,1,ETH,1,-,-,-,approved,2024-01-03 06:00:00",
+        ]
+        .join("\n");
+
+        let import = NexoCsv::read(csv.as_bytes())?;
+        assert!(import.errors.is_empty(), "{:?}", import.errors);
+        let [withdrawal, exchange, unknown] = &import.transactions[..] else {
+            panic!("expected 3 rows");
+        };
+        assert_eq!(withdrawal.kind, TransactionType::Withdrawal);
+        assert_eq!(withdrawal.fee(), Some(("2.5", "USDT")));
+        assert_eq!(withdrawal.input_quantity(), "100");
+        assert_eq!(exchange.output_quantity(), "0.05");
+        assert_eq!(withdrawal.tx_hash(), Some("0xabc"));
+        assert_eq!(exchange.tx_hash(), None);
+        assert_eq!(unknown.tx_hash(), None);
+        assert_eq!(exchange.kind, TransactionType::Exchange);
+        assert_eq!(exchange.usd_equivalent, Some(3000.0));
+        assert_eq!(exchange.fee(), None);
+        assert_eq!(
+            unknown.kind,
+            TransactionType::Other("Some Future Type".to_string())
+        );
+        assert_eq!(unknown.kind.label(), "Some Future Type");
+        assert_eq!(unknown.usd_equivalent, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_rows_are_reported() -> anyhow::Result<()> {
+        let csv = [
+            HEADER,
+            &interest_row("NXT1", "2024-01-01 06:00:00"),
+            &interest_row("NXT2", "not a date"),
+            "NXT3,Interest,ETH",
+            &interest_row("NXT4", "2024-01-04 06:00:00"),
+        ]
+        .join("\n");
+
+        let import = NexoCsv::read(csv.as_bytes())?;
+        assert_eq!(import.transactions.len(), 2);
+        let lines: Vec<_> = import.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [3, 4]);
+        assert!(import.errors[0].to_string().starts_with("<reader>:3: "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_fees_are_reported() -> anyhow::Result<()> {
+        let csv = [
+            "Transaction,Type,Input Currency,Input Amount,Output Currency,Output Amount,USD Equivalent,Fee,Fee Currency,Details,Date / Time (UTC)",
+            "NXT1,Withdrawal,USDT,-100,USDT,100,$100.00,2.5,USDT,approved,2024-01-01 06:00:00",
+            "NXT2,Withdrawal,USDT,-100,USDT,100,$100.00,2.5.1,USDT,approved,2024-01-02 06:00:00",
+            "NXT3,Withdrawal,USDT,-100,USDT,100,$100.00,2.5,-,approved,2024-01-03 06:00:00",
+            "NXT4,Withdrawal,USDT,-100,USDT,100,$100.00,0,-,approved,2024-01-04 06:00:00",
+        ]
+        .join("\n");
+
+        let import = NexoCsv::read(csv.as_bytes())?;
+        let tx_ids: Vec<_> = import
+            .transactions
+            .iter()
+            .map(|tx| tx.tx_id.as_str())
+            .collect();
+        assert_eq!(tx_ids, ["NXT1", "NXT4"]);
+        let lines: Vec<_> = import.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [3, 4]);
+        assert!(
+            import.errors[0]
+                .message
+                .contains("invalid amount \"2.5.1\"")
+        );
+        assert!(import.errors[1].message.contains("without a currency"));
         Ok(())
     }
 }
 
 pub mod utils {
+    use ::utils::decimal::unsigned;
     use chrono::{DateTime, NaiveDateTime, Utc};
     use serde::{Deserialize, Deserializer, de};
 
@@ -100,6 +452,66 @@ pub mod utils {
         Ok(dt.to_utc())
     }
 
+    /// Parses amounts like "$1,234.56"; "-" or an empty cell mean no value
+    pub fn de_usd<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_opt_f64(&s.replacen('$', "", 1)).map_err(de::Error::custom)
+    }
+
+    /// Keeps a decimal cell as written, failing on anything else so a malformed amount
+    /// is reported with its row
+    pub fn de_decimal<'de, D>(deserializer: D) -> Result<String, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_decimal(s.trim()).map_err(de::Error::custom)
+    }
+
+    /// Like [`de_decimal`], with "-" or an empty cell meaning no value
+    pub fn de_opt_decimal<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let s = s.trim();
+        if s.is_empty() || s == "-" {
+            return Ok(None);
+        }
+        parse_decimal(s).map(Some).map_err(de::Error::custom)
+    }
+
+    fn parse_decimal(s: &str) -> Result<String, String> {
+        let digits = unsigned(s);
+        let is_decimal = digits.chars().any(|c| c.is_ascii_digit())
+            && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+            && digits.matches('.').count() <= 1;
+        match is_decimal {
+            true => Ok(s.to_string()),
+            false => Err(format!("invalid amount {s:?}")),
+        }
+    }
+
+    pub fn de_opt_str<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let s = s.trim();
+        Ok((!s.is_empty() && s != "-").then(|| s.to_string()))
+    }
+
+    fn parse_opt_f64(s: &str) -> Result<Option<f64>, std::num::ParseFloatError> {
+        let s = s.trim().replace(',', "");
+        if s.is_empty() || s == "-" {
+            return Ok(None);
+        }
+        s.parse().map(Some)
+    }
+
     #[cfg(test)]
     mod tests {
         use super::*;
@@ -117,5 +529,20 @@ pub mod utils {
 
             Ok(())
         }
+
+        #[test]
+        fn test_serde_usd() -> anyhow::Result<()> {
+            let de_usd_str = |s: &str| {
+                de_usd(StringDeserializer::<csv::DeserializeError>::new(
+                    s.to_string(),
+                ))
+            };
+            assert_eq!(de_usd_str("$1,234.56")?, Some(1234.56));
+            assert_eq!(de_usd_str("12.5")?, Some(12.5));
+            assert_eq!(de_usd_str("-")?, None);
+            assert_eq!(de_usd_str("")?, None);
+            assert!(de_usd_str("$abc").is_err());
+            Ok(())
+        }
     }
 }
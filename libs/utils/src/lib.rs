@@ -141,3 +141,54 @@ pub mod api_client_utils {
     //     pub client: reqwest::Client,
     // }
 }
+
+pub mod time {
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    /// Splits `[start, end)` into consecutive windows no longer than `max`
+    pub fn windows(
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        max: TimeDelta,
+    ) -> impl Iterator<Item = (DateTime<Utc>, DateTime<Utc>)> {
+        let mut from = start;
+        std::iter::from_fn(move || {
+            if from >= end {
+                return None;
+            }
+            let to = (from + max).min(end);
+            let window = (from, to);
+            from = to;
+            Some(window)
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_windows() {
+            let start = DateTime::from_timestamp(0, 0).unwrap();
+            let end = start + TimeDelta::days(200);
+            let windows = windows(start, end, TimeDelta::days(90)).collect::<Vec<_>>();
+            assert_eq!(windows.len(), 3);
+            assert_eq!(windows[0], (start, start + TimeDelta::days(90)));
+            assert_eq!(windows[1].0, windows[0].1);
+            assert_eq!(windows[2].1, end);
+            assert_eq!(self::windows(end, end, TimeDelta::days(90)).count(), 0);
+        }
+    }
+}
+
+pub mod decimal {
+    /// A decimal string without its sign
+    pub fn unsigned(decimal: &str) -> &str {
+        decimal.trim().trim_start_matches(['-', '+'])
+    }
+
+    /// Whether a decimal string is zero, e.g. "0.00" or "-0"
+    pub fn is_zero(decimal: &str) -> bool {
+        unsigned(decimal).chars().all(|c| c == '0' || c == '.')
+    }
+}